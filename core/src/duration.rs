@@ -0,0 +1,151 @@
+//! Human-friendly (de)serialization of durations.
+//!
+//! Durations are written as strings like `"50ms"`, `"5s"`, or `"2m"`; plain integers (or integer
+//! strings) are taken as seconds. Use with `#[serde(with = "deoxy_core::duration")]`, or with the
+//! [`option`](option/index.html) and [`array`](array/index.html) submodules for `Option`s and
+//! pairs of durations.
+use serde::{
+    de::{self, Unexpected, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use std::{fmt, time::Duration};
+
+/// A duration which (de)serializes in human-friendly form.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Human(Duration);
+
+struct HumanVisitor;
+
+impl<'de> Visitor<'de> for HumanVisitor {
+    type Value = Human;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a duration (such as \"50ms\", \"5s\", or \"2m\") or a number of seconds")
+    }
+
+    fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Human, E> {
+        Ok(Human(Duration::from_secs(secs)))
+    }
+
+    fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Human, E> {
+        if secs < 0 {
+            Err(E::invalid_value(Unexpected::Signed(secs), &self))
+        } else {
+            self.visit_u64(secs as u64)
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Human, E> {
+        let value = value.trim();
+        if let Ok(secs) = value.parse() {
+            return self.visit_u64(secs);
+        }
+        humantime::parse_duration(value)
+            .map(Human)
+            .map_err(|err| E::custom(format!("invalid duration {:?}: {}", value, err)))
+    }
+}
+
+impl fmt::Display for Human {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", humantime::format_duration(self.0))
+    }
+}
+
+impl Serialize for Human {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Human {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(HumanVisitor)
+    }
+}
+
+/// Serializes a duration in its compact string form (such as `"2m"`).
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    Human(*duration).serialize(serializer)
+}
+
+/// Deserializes a duration from a string (such as `"2m"`) or a number of seconds.
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Human::deserialize(deserializer).map(|human| human.0)
+}
+
+/// Human-friendly (de)serialization of optional durations.
+pub mod option {
+    use super::Human;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    /// Serializes an optional duration in its compact string form.
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        duration.map(Human).serialize(serializer)
+    }
+
+    /// Deserializes an optional duration from a string or a number of seconds.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<Human>::deserialize(deserializer).map(|human| human.map(|human| human.0))
+    }
+}
+
+/// Human-friendly (de)serialization of pairs of durations (such as signal ranges).
+pub mod array {
+    use super::Human;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    /// Serializes a pair of durations in their compact string forms.
+    pub fn serialize<S: Serializer>(
+        durations: &[Duration; 2],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        [Human(durations[0]), Human(durations[1])].serialize(serializer)
+    }
+
+    /// Deserializes a pair of durations, each from a string or a number of seconds.
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[Duration; 2], D::Error> {
+        <[Human; 2]>::deserialize(deserializer).map(|[start, end]| [start.0, end.0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::{value::Error, IntoDeserializer};
+
+    fn parse<'de, T: IntoDeserializer<'de, Error>>(value: T) -> Result<Duration, Error> {
+        deserialize(value.into_deserializer())
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse("600us"), Ok(Duration::from_micros(600)));
+        assert_eq!(parse("50ms"), Ok(Duration::from_millis(50)));
+        assert_eq!(parse("5s"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse(30u64), Ok(Duration::from_secs(30)));
+        assert!(parse("soon").is_err());
+        assert!(parse(-1i64).is_err());
+    }
+
+    #[test]
+    fn compact() {
+        let format = |duration| Human(duration).to_string();
+        assert_eq!(format(Duration::from_secs(120)), "2m");
+        assert_eq!(format(Duration::from_millis(50)), "50ms");
+        assert_eq!(format(Duration::new(0, 0)), "0s");
+    }
+}
@@ -21,9 +21,20 @@
 /// Used to uniquely identify motors/valves.
 pub type MotorId = usize;
 
+/// Used to uniquely identify pumps (the first pump is used unless a step says otherwise).
+pub type PumpId = usize;
+
+/// Used to uniquely identify sample chambers (each with its own manifold and pump).
+pub type ChamberId = usize;
+
+#[cfg(feature = "use_serde")]
+pub mod duration;
 mod program;
 pub use self::program::{
-    Action, Notification, Program, Protocol, Step, ValidateError as ValidateProtocolError,
+    buffer_label, Action, ActionDiff, Diff, Monitor, Notification,
+    ParseError as ParseProtocolError, ParseErrorKind as ParseProtocolErrorKind, Program, Protocol,
+    ProtocolBuilder, Step, StepDiff, ValidateError as ValidateProtocolError, DEFAULT_RATE,
+    DEFAULT_VOLUME,
 };
 
 #[cfg(feature = "use_serde")]
@@ -1,7 +1,39 @@
 //! Utilities for scheduling actions.
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt, mem,
+    time::Duration,
+};
 
-use crate::MotorId;
+use crate::{MotorId, PumpId};
+
+/// The default volume of buffer needed to fill the chamber, in milliliters.
+pub const DEFAULT_VOLUME: f64 = 500.0;
+/// The default rate at which buffer is pumped, in milliliters per second.
+pub const DEFAULT_RATE: f64 = 3.75;
+
+/// The display name of the given buffer, given the labels of the motors (by id).
+///
+/// Buffers without a label are named by number, like `Buffer 3`.
+pub fn buffer_label(labels: &[Option<String>], buffer: MotorId) -> String {
+    labels
+        .get(buffer)
+        .cloned()
+        .flatten()
+        .unwrap_or_else(|| format!("Buffer {}", buffer))
+}
+
+/// Names each of the given buffers (see [`buffer_label`](fn.buffer_label.html)).
+fn resolve_labels<I>(labels: &[Option<String>], buffers: I) -> BTreeMap<MotorId, String>
+where
+    I: IntoIterator<Item = MotorId>,
+{
+    buffers
+        .into_iter()
+        .map(|buffer| (buffer, buffer_label(labels, buffer)))
+        .collect()
+}
 
 /// Represents an error encountered while validating a protocol.
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -11,9 +43,276 @@ pub enum ValidateError {
     /// The protocol is empty and so cannot be valid.
     Empty,
     /// The last step is not an indefinite perfusion.
-    Last(Step),
-    /// A perfusion has a duration of zero.
-    ZeroDuration,
+    Last {
+        /// The index of the last step in the [expanded](struct.Protocol.html#method.expand)
+        /// protocol.
+        index: usize,
+        /// The offending step.
+        step: Step,
+    },
+    /// The perfusion, priming, wait, or recirculation at the given index of the
+    /// [expanded](struct.Protocol.html#method.expand) protocol has a duration of zero.
+    ZeroDuration(usize),
+    /// The recirculation at the given index of the [expanded](struct.Protocol.html#method.expand)
+    /// protocol has no cycles.
+    ZeroCycles(usize),
+    /// A repetition would run no steps, either because its count is zero or because it is empty.
+    ZeroRepeat,
+    /// The perfusion, prompt, or wait at the given index of the
+    /// [expanded](struct.Protocol.html#method.expand) protocol lasts longer than allowed.
+    TooLong {
+        /// The index of the step in the expanded protocol.
+        index: usize,
+        /// How long the step lasts.
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))]
+        duration: Duration,
+    },
+    /// When joining protocols, the first ended in a bath at the given index of the
+    /// [expanded](struct.Protocol.html#method.expand) result, leaving the sample waiting
+    /// mid-protocol.
+    IntermediateBath(usize),
+}
+
+impl fmt::Display for ValidateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "The protocol has no steps"),
+            Self::Last { index, .. } => write!(
+                f,
+                "The last step (step {}) must be a perfusion without a duration",
+                index + 1
+            ),
+            Self::ZeroDuration(index) => write!(f, "Step {} has a duration of zero", index + 1),
+            Self::ZeroCycles(index) => write!(f, "Step {} recirculates zero times", index + 1),
+            Self::ZeroRepeat => write!(f, "A repetition would run no steps"),
+            Self::TooLong { index, duration } => write!(
+                f,
+                "Step {} lasts {} seconds, which is longer than allowed",
+                index + 1,
+                duration.as_secs()
+            ),
+            Self::IntermediateBath(index) => write!(
+                f,
+                "Step {} is a bath, but another protocol was joined after it",
+                index + 1
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValidateError {}
+
+/// What was wrong with a protocol that couldn't be [parsed](struct.Protocol.html#method.parse).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    /// A statement began with something other than `perfuse`, `bath`, `wait`, or `prompt`.
+    UnknownKeyword,
+    /// A buffer was expected, but the token isn't a number.
+    InvalidBuffer,
+    /// A duration was expected, but the token isn't one (such as `5m`, `30s`, or `1h30m`).
+    InvalidDuration,
+    /// Quoted text was expected, but the token isn't quoted.
+    ExpectedText,
+    /// The statement ended before the given argument.
+    MissingArgument(&'static str),
+    /// The statement has more arguments than its keyword takes.
+    ExtraArgument,
+    /// Quoted text was never closed.
+    UnterminatedQuote,
+}
+
+/// Represents an error encountered while parsing a protocol from text.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    /// The (byte) offset of the offending token in the text.
+    pub position: usize,
+    /// The offending token (empty if an argument is missing).
+    pub token: String,
+    /// What was wrong with it.
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::UnknownKeyword => write!(f, "Unknown keyword `{}`", self.token),
+            ParseErrorKind::InvalidBuffer => write!(f, "`{}` is not a buffer", self.token),
+            ParseErrorKind::InvalidDuration => write!(f, "`{}` is not a duration", self.token),
+            ParseErrorKind::ExpectedText => write!(f, "Expected quoted text, not `{}`", self.token),
+            ParseErrorKind::MissingArgument(argument) => write!(f, "Missing {}", argument),
+            ParseErrorKind::ExtraArgument => write!(f, "Unexpected `{}`", self.token),
+            ParseErrorKind::UnterminatedQuote => write!(f, "Unterminated quote"),
+        }?;
+        write!(f, " at position {}", self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A word or quoted string in the text of a protocol.
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    /// The (byte) offset of the token in the text, including any opening quote.
+    position: usize,
+    /// The text of the token, without quotes.
+    text: &'a str,
+    /// Whether the token was quoted.
+    quoted: bool,
+}
+
+impl Token<'_> {
+    /// The offset just past the end of the token in the text.
+    fn end(&self) -> usize {
+        self.position + self.text.len() + if self.quoted { 2 } else { 0 }
+    }
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            position: self.position,
+            token: self.text.to_owned(),
+            kind,
+        }
+    }
+}
+
+/// Splits the text of a protocol into statements of tokens.
+///
+/// Statements are separated by semicolons or newlines; empty statements are skipped.
+fn tokenize(text: &str) -> Result<Vec<Vec<Token>>, ParseError> {
+    let mut statements = vec![];
+    let mut statement = vec![];
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        match c {
+            ';' | '\n' => {
+                if !statement.is_empty() {
+                    statements.push(mem::take(&mut statement));
+                }
+            }
+            '"' => {
+                let end = loop {
+                    match chars.next() {
+                        Some((end, '"')) => break end,
+                        Some(_) => {}
+                        None => {
+                            return Err(ParseError {
+                                position: start,
+                                token: text[start..].to_owned(),
+                                kind: ParseErrorKind::UnterminatedQuote,
+                            })
+                        }
+                    }
+                };
+                statement.push(Token {
+                    position: start,
+                    text: &text[start + 1..end],
+                    quoted: true,
+                });
+            }
+            c if c.is_whitespace() => {}
+            _ => {
+                let mut end = text.len();
+                while let Some(&(next, c)) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        end = next;
+                        break;
+                    }
+                    chars.next();
+                }
+                statement.push(Token {
+                    position: start,
+                    text: &text[start..end],
+                    quoted: false,
+                });
+            }
+        }
+    }
+    if !statement.is_empty() {
+        statements.push(statement);
+    }
+    Ok(statements)
+}
+
+/// Parses a duration such as `500ms`, `30s`, `5m` (or `5min`), `2h`, or `1h30m`.
+///
+/// A plain number is taken as seconds.
+fn parse_duration(text: &str) -> Option<Duration> {
+    if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) {
+        return text.parse().ok().map(Duration::from_secs);
+    }
+    let mut millis: u64 = 0;
+    let mut rest = text;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or_else(|| rest.len());
+        let value: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or_else(|| rest.len());
+        let scale = match &rest[..unit] {
+            "ms" => 1,
+            "s" => 1000,
+            "m" | "min" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            _ => return None,
+        };
+        rest = &rest[unit..];
+        millis = millis.checked_add(value.checked_mul(scale)?)?;
+    }
+    if text.is_empty() {
+        None
+    } else {
+        Some(Duration::from_millis(millis))
+    }
+}
+
+/// The arguments of a single statement, taken in order.
+struct Arguments<'a, 'b> {
+    tokens: std::slice::Iter<'b, Token<'a>>,
+    /// Where the statement ends, for reporting missing arguments.
+    end: usize,
+}
+
+impl<'a> Arguments<'a, '_> {
+    fn argument(&mut self, argument: &'static str) -> Result<Token<'a>, ParseError> {
+        self.tokens.next().copied().ok_or(ParseError {
+            position: self.end,
+            token: String::new(),
+            kind: ParseErrorKind::MissingArgument(argument),
+        })
+    }
+    fn buffer(&mut self) -> Result<MotorId, ParseError> {
+        let token = self.argument("a buffer")?;
+        match token.text.parse() {
+            Ok(buffer) if !token.quoted => Ok(buffer),
+            _ => Err(token.error(ParseErrorKind::InvalidBuffer)),
+        }
+    }
+    fn duration(&mut self) -> Result<Duration, ParseError> {
+        let token = self.argument("a duration")?;
+        parse_duration(token.text)
+            .filter(|_| !token.quoted)
+            .ok_or_else(|| token.error(ParseErrorKind::InvalidDuration))
+    }
+    fn notification(&mut self) -> Result<Notification, ParseError> {
+        let token = self.argument("quoted text")?;
+        if token.quoted {
+            Ok(Notification {
+                subject: token.text.to_owned(),
+                message: String::new(),
+            })
+        } else {
+            Err(token.error(ParseErrorKind::ExpectedText))
+        }
+    }
+    /// Ensures that every argument has been taken.
+    fn finish(mut self) -> Result<(), ParseError> {
+        match self.tokens.next() {
+            Some(token) => Err(token.error(ParseErrorKind::ExtraArgument)),
+            None => Ok(()),
+        }
+    }
 }
 
 /// Encodes a notification to users.
@@ -27,28 +326,105 @@ pub struct Notification {
 }
 
 /// Represents a high-level step to be taken in a protocol.
+// Older versions of serde_derive can't handle `Self` in the variants.
+#[allow(clippy::use_self)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 #[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
 #[cfg_attr(feature = "use_serde", serde(rename_all = "lowercase"))]
 pub enum Step {
     /// The specified motor should fully perfuse the tissue for the given duration (or until
     /// otherwise instructed if `None`).
-    Perfuse(MotorId, Option<Duration>),
+    Perfuse(
+        MotorId,
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration::option"))]
+        Option<Duration>,
+    ),
     /// The system should fully perfuse the tissue with the given solution, prompt the user with
     /// the given message, await acknowledgement, wait for the specified duration, and then notify
     /// the user again.
-    PerfusePrompt(MotorId, Notification, Duration, Notification),
+    PerfusePrompt(
+        MotorId,
+        Notification,
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))] Duration,
+        Notification,
+    ),
+    /// The given step, with the user silently notified as it starts and/or completes.
+    ///
+    /// This is intended for remote monitoring; unlike a prompt, it never waits on the user.
+    Monitored(Box<Step>, Monitor),
+    /// The given steps, run in order the given number of times.
+    Repeat(u32, Vec<Step>),
+    /// The lines should be primed with the specified solution by running the pump for the given
+    /// duration (rather than until full), and then drained to waste.
+    ///
+    /// This clears the air from the lines before the first perfusion, which would otherwise be
+    /// short.
+    Prime(
+        MotorId,
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))] Duration,
+    ),
+    /// The sample should sit in its current buffer, without flow, for the given duration.
+    ///
+    /// Nothing is perfused or drained; if the step before would have drained its buffer, the
+    /// drain is put off until the waiting is done. A protocol can't end with a wait.
+    Wait(#[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))] Duration),
+    /// The given step, run with the given pump rather than the first.
+    ///
+    /// Using a pump with a repetition uses it for each of the steps the repetition expands to.
+    Pumped(PumpId, Box<Step>),
+    /// The chamber should be filled from the specified buffer, which is then gently mixed by
+    /// running the pump forward and backward (with the buffer's valve held open) for the given
+    /// number of cycles, and then drained.
+    Recirculate {
+        /// The buffer to recirculate.
+        motor: MotorId,
+        /// The number of times to run the pump forward and then backward.
+        cycles: u32,
+        /// How long to run the pump forward each cycle.
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))]
+        forward: Duration,
+        /// How long to run the pump backward each cycle.
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))]
+        backward: Duration,
+    },
+}
+
+/// Specifies when the user should be notified about the progress of a step.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "use_serde", serde(default))]
+pub struct Monitor {
+    /// Whether to notify the user when the step starts.
+    pub on_start: bool,
+    /// Whether to notify the user when the step completes (after draining).
+    pub on_complete: bool,
 }
 
 /// A high-level description of a series of actions to be taken.
 ///
-/// This is what the end user will feed in (by way of a form).
+/// This is what the end user will feed in (by way of a form). A protocol can be built from any
+/// steps (converting from a `Vec<Step>`), so it needn't be valid until it's run; use
+/// [`ProtocolBuilder`](struct.ProtocolBuilder.html) or [`set_steps`](#method.set_steps) to
+/// ensure that it is.
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
 #[cfg_attr(feature = "use_serde", serde(rename_all = "lowercase", transparent))]
 pub struct Protocol {
     /// The component steps of the protocol.
-    pub steps: Vec<Step>,
+    steps: Vec<Step>,
+}
+
+impl From<Vec<Step>> for Protocol {
+    fn from(steps: Vec<Step>) -> Self {
+        Self { steps }
+    }
+}
+
+impl TryFrom<&str> for Protocol {
+    type Error = ParseError;
+    fn try_from(text: &str) -> Result<Self, Self::Error> {
+        Self::parse(text)
+    }
 }
 
 impl Protocol {
@@ -56,6 +432,77 @@ impl Protocol {
     pub fn with_step(step: Step) -> Self {
         Self { steps: vec![step] }
     }
+    /// Parses a protocol from its compact textual form, such as
+    /// `perfuse 0 5m; perfuse 1 10m; bath 3`.
+    ///
+    /// Statements are separated by semicolons or newlines, and each is one of:
+    ///
+    /// - `perfuse <buffer> <duration>`, a timed perfusion;
+    /// - `bath <buffer>`, a perfusion until otherwise instructed;
+    /// - `wait <duration>`, a wait without flow; or
+    /// - `prompt <buffer> "<begin>" <duration> "<end>"`, a prompted perfusion (see
+    ///   [`Step::PerfusePrompt`](enum.Step.html#variant.PerfusePrompt)), notifying the user with
+    ///   the quoted subjects.
+    ///
+    /// Durations are written like `500ms`, `30s`, `5m`, `2h`, or `1h30m`; plain numbers are
+    /// seconds. The protocol is only parsed, not validated.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut steps = vec![];
+        for statement in tokenize(text)? {
+            let (keyword, rest) = statement.split_first().expect("statements are never empty");
+            let mut arguments = Arguments {
+                tokens: rest.iter(),
+                end: statement.last().map_or(keyword.end(), Token::end),
+            };
+            let step = match keyword.text {
+                _ if keyword.quoted => return Err(keyword.error(ParseErrorKind::UnknownKeyword)),
+                "perfuse" => Step::Perfuse(arguments.buffer()?, Some(arguments.duration()?)),
+                "bath" => Step::Perfuse(arguments.buffer()?, None),
+                "wait" => Step::Wait(arguments.duration()?),
+                "prompt" => Step::PerfusePrompt(
+                    arguments.buffer()?,
+                    arguments.notification()?,
+                    arguments.duration()?,
+                    arguments.notification()?,
+                ),
+                _ => return Err(keyword.error(ParseErrorKind::UnknownKeyword)),
+            };
+            arguments.finish()?;
+            steps.push(step);
+        }
+        Ok(Self::from(steps))
+    }
+    /// The component steps of the protocol.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+    /// Adds a step to the end of the protocol.
+    pub fn push_step(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+    /// Inserts a step at the given position, shifting the steps after it along.
+    ///
+    /// # Panics
+    /// Panics if the index is greater than the number of steps.
+    pub fn insert_step(&mut self, index: usize, step: Step) {
+        self.steps.insert(index, step);
+    }
+    /// Removes and returns the step at the given position, shifting the steps after it back.
+    ///
+    /// # Panics
+    /// Panics if the index is out of bounds.
+    pub fn remove_step(&mut self, index: usize) -> Step {
+        self.steps.remove(index)
+    }
+    /// Replaces the steps of the protocol, if the result would be valid.
+    ///
+    /// If it wouldn't, the protocol is left unchanged.
+    pub fn set_steps(&mut self, steps: Vec<Step>) -> Result<(), ValidateError> {
+        let protocol = Self::from(steps);
+        protocol.validate()?;
+        *self = protocol;
+        Ok(())
+    }
     /// Ensures the validity of the protocol.
     ///
     /// This method is called automatically during the conversion to `Program`, but it can also be
@@ -66,68 +513,114 @@ impl Protocol {
     /// unspecified duration (i.e. a bath). If this is not the case, something's wrong with the
     /// protocol and we should refuse to run it.
     pub fn validate(&self) -> Result<(), ValidateError> {
-        let is_zero_perfusion = |step: &Step| {
-            if let Step::Perfuse(_, duration) = step {
-                if let Some(duration) = *duration {
-                    duration == Duration::new(0, 0)
-                } else {
-                    false
-                }
+        if self.steps.iter().any(Step::has_zero_repeat) {
+            return Err(ValidateError::ZeroRepeat);
+        }
+        let steps = self.expand();
+        let is_zero_perfusion = |step: &Step| match step.inner() {
+            Step::Perfuse(_, Some(duration)) | Step::Prime(_, duration) | Step::Wait(duration) => {
+                *duration == Duration::new(0, 0)
+            }
+            Step::Recirculate {
+                forward, backward, ..
+            } => *forward == Duration::new(0, 0) || *backward == Duration::new(0, 0),
+            _ => false,
+        };
+        let is_zero_cycles = |step: &Step| match step.inner() {
+            Step::Recirculate { cycles, .. } => *cycles == 0,
+            _ => false,
+        };
+        if let Some(index) = steps.iter().position(is_zero_perfusion) {
+            Err(ValidateError::ZeroDuration(index))
+        } else if let Some(index) = steps.iter().position(is_zero_cycles) {
+            Err(ValidateError::ZeroCycles(index))
+        } else if let Some(last) = steps.last() {
+            if last.is_bath() {
+                Ok(())
             } else {
-                false
-            }
-        };
-        if self.steps.iter().any(is_zero_perfusion) {
-            Err(ValidateError::ZeroDuration)
-        } else if let Some(last) = self.steps.last() {
-            match last {
-                Step::Perfuse(_, duration) => {
-                    if duration.is_none() {
-                        Ok(())
-                    } else {
-                        Err(ValidateError::Last(last.clone()))
-                    }
-                }
-                Step::PerfusePrompt(_, _, _, _) => Err(ValidateError::Last(last.clone())),
+                Err(ValidateError::Last {
+                    index: steps.len() - 1,
+                    step: last.clone(),
+                })
             }
         } else {
             Err(ValidateError::Empty)
         }
     }
+    /// Ensures the validity of the protocol, as with [`validate`](#method.validate), and that none
+    /// of its perfusions, prompts, or waits lasts longer than the given limit (if any).
+    ///
+    /// This guards against typos (hours instead of minutes, say) leaving a sample perfusing for
+    /// far longer than intended.
+    pub fn validate_with_limit(
+        &self,
+        max_step_duration: Option<Duration>,
+    ) -> Result<(), ValidateError> {
+        self.validate()?;
+        if let Some(limit) = max_step_duration {
+            let step_duration = |step: &Step| match step.inner() {
+                Step::Perfuse(_, Some(duration))
+                | Step::PerfusePrompt(_, _, duration, _)
+                | Step::Wait(duration) => Some(*duration),
+                _ => None,
+            };
+            let too_long = self
+                .expand()
+                .iter()
+                .map(step_duration)
+                .enumerate()
+                .find_map(|(index, duration)| match duration {
+                    Some(duration) if duration > limit => Some((index, duration)),
+                    _ => None,
+                });
+            if let Some((index, duration)) = too_long {
+                return Err(ValidateError::TooLong { index, duration });
+            }
+        }
+        Ok(())
+    }
+    /// Joins another protocol (such as a reusable fragment) onto the end of this one.
+    ///
+    /// Only the other protocol's final bath may remain a bath; if this protocol ends in one, an
+    /// error is returned. The joined protocol is then validated.
+    pub fn concat(mut self, other: Self) -> Result<Self, ValidateError> {
+        let steps = self.expand();
+        if steps.last().map_or(false, Step::is_bath) {
+            return Err(ValidateError::IntermediateBath(steps.len() - 1));
+        }
+        self.steps.extend(other.steps);
+        self.validate()?;
+        Ok(self)
+    }
+    /// Appends another protocol to this one, as with [`concat`](#method.concat).
+    ///
+    /// If the joined protocol would be invalid, this protocol is left unchanged.
+    pub fn append(&mut self, other: Self) -> Result<(), ValidateError> {
+        *self = self.clone().concat(other)?;
+        Ok(())
+    }
     /// Attempts to convert the protocol to a [`program`](struct.Program.html).
     ///
     /// The protocol will first be validated.
     pub fn as_program(&self) -> Result<Program, ValidateError> {
-        self.validate()?;
-        let mut actions = self
-            .steps
-            .iter()
-            .flat_map(|step| {
-                let mut actions = vec![];
-                match step {
-                    &Step::Perfuse(motor, duration) => {
-                        actions.push(Action::Perfuse(motor));
-                        actions.push(duration.map(Action::Sleep).unwrap_or(Action::Hail));
-                        actions.push(Action::Drain);
-                    }
-                    Step::PerfusePrompt(motor, begin, duration, end) => {
-                        actions.push(Action::Perfuse(*motor));
-                        actions.push(Action::Notify(begin.clone()));
-                        actions.push(Action::Hail);
-                        actions.push(Action::Sleep(*duration));
-                        actions.push(Action::Notify(end.clone()));
-                        actions.push(Action::Hail);
-                        actions.push(Action::Drain);
-                    }
-                }
-                actions.into_iter()
-            })
-            .collect::<Vec<_>>();
-        let _ = actions.pop();
-        let _ = actions.pop();
+        self.as_program_with_soak(Duration::new(0, 0))
+    }
+    /// Attempts to convert the protocol to a [`program`](struct.Program.html), letting each
+    /// buffer sit (without flow) for the given soak time before it is drained.
+    ///
+    /// A soak of zero is equivalent to [`as_program`](#method.as_program).
+    pub fn as_program_with_soak(&self, soak: Duration) -> Result<Program, ValidateError> {
+        let mut actions: Vec<Action> = self.step_actions(soak)?.into_iter().flatten().collect();
         actions.push(Action::Finish);
         assert!(actions.len() > 1);
-        if let Action::Perfuse(_) = actions[0] {
+        if let Some(Action::Perfuse(_)) | Some(Action::Prime(_, _)) =
+            actions.iter().find(|action| {
+                !matches!(
+                    action,
+                    Action::Notify(_) | Action::SelectPump(_) | Action::Sleep(_)
+                )
+            })
+        {
             Ok(Program { actions })
         } else {
             // This shouldn't be able to happen, so it's more than user error; it's on us.
@@ -135,6 +628,510 @@ impl Protocol {
             panic!("Invalid program detected; no initial perfusion.");
         }
     }
+    /// The estimated total runtime of the protocol, if it can be known in advance.
+    ///
+    /// Perfusions and drains are timed from the default volume and flow rate, ignoring the brief
+    /// delays for valves to settle. If the protocol waits on the user at any point (or is invalid),
+    /// `None` is returned. The final bath is counted only until the chamber is full.
+    pub fn duration(&self) -> Option<Duration> {
+        let fill = Duration::from_millis((DEFAULT_VOLUME / DEFAULT_RATE * 1000.0) as u64);
+        let program = self.as_program().ok()?;
+        program
+            .actions
+            .iter()
+            .map(|action| match action {
+                Action::Perfuse(_) => Some(fill),
+                Action::Sleep(duration)
+                | Action::Prime(_, duration)
+                | Action::Forward(_, duration)
+                | Action::Backward(_, duration) => Some(*duration),
+                Action::Hail => None,
+                Action::Drain => Some(fill * 2),
+                Action::Finish | Action::Notify(_) | Action::SelectPump(_) => {
+                    Some(Duration::new(0, 0))
+                }
+            })
+            .try_fold(Duration::new(0, 0), |total, duration| {
+                Some(total + duration?)
+            })
+    }
+    /// The buffers the protocol perfuses with.
+    pub fn buffers(&self) -> BTreeSet<MotorId> {
+        self.expand()
+            .iter()
+            .filter_map(|step| match step.inner() {
+                Step::Perfuse(motor, _)
+                | Step::PerfusePrompt(motor, _, _, _)
+                | Step::Prime(motor, _)
+                | Step::Recirculate { motor, .. } => Some(*motor),
+                Step::Monitored(_, _) | Step::Pumped(_, _) | Step::Repeat(_, _) | Step::Wait(_) => {
+                    None
+                }
+            })
+            .collect()
+    }
+    /// Estimates how much of each buffer the protocol uses (in milliliters), given the volume
+    /// needed to fill the chamber.
+    ///
+    /// Each perfusion (including a bath, or a recirculation) fills the chamber once, and buffers
+    /// don't flow while the sample sits in them, so a bath counts no more than a timed perfusion.
+    /// Priming depends on the flow rate rather than the volume, so it isn't counted.
+    pub fn volume_per_buffer(&self, default_volume: f64) -> BTreeMap<MotorId, f64> {
+        let mut volumes = BTreeMap::new();
+        for step in self.expand() {
+            match *step.inner() {
+                Step::Perfuse(motor, _)
+                | Step::PerfusePrompt(motor, _, _, _)
+                | Step::Recirculate { motor, .. } => {
+                    *volumes.entry(motor).or_insert(0.0) += default_volume;
+                }
+                Step::Monitored(_, _)
+                | Step::Pumped(_, _)
+                | Step::Repeat(_, _)
+                | Step::Prime(_, _)
+                | Step::Wait(_) => {}
+            }
+        }
+        volumes
+    }
+    /// The pumps the protocol uses.
+    pub fn pumps(&self) -> BTreeSet<PumpId> {
+        self.expand().iter().map(Step::pump).collect()
+    }
+    /// Names each of the buffers the protocol perfuses with, given the labels of the motors (by
+    /// id).
+    ///
+    /// The protocol itself refers to buffers only by id; this is for display.
+    pub fn resolve_labels(&self, labels: &[Option<String>]) -> BTreeMap<MotorId, String> {
+        resolve_labels(labels, self.buffers())
+    }
+    /// The steps of the protocol with all repetitions expanded.
+    pub fn expand(&self) -> Vec<Step> {
+        let mut steps = vec![];
+        for step in &self.steps {
+            step.expand(&mut steps);
+        }
+        steps
+    }
+    /// Validates the protocol and lowers each of its steps into actions separately.
+    ///
+    /// The `i`th element holds the actions for the `i`th step of the
+    /// [expanded](#method.expand) protocol; flattening the result (and appending `Action::Finish`)
+    /// gives the program.
+    ///
+    /// The drain of a step followed by a wait is held over to the step after the wait.
+    pub fn step_actions(&self, soak: Duration) -> Result<Vec<Vec<Action>>, ValidateError> {
+        self.validate()?;
+        let steps = self.expand();
+        let total = steps.len();
+        let mut lowered: Vec<Vec<Action>> = vec![];
+        let mut deferred = vec![];
+        for (index, step) in steps.iter().enumerate() {
+            let mut actions = vec![];
+            if step.is_wait() {
+                // Keep the sample in the previous buffer until the waiting is done. Priming
+                // drains straight away regardless, since it never reaches the sample.
+                let primed = index > 0 && matches!(steps[index - 1].inner(), Step::Prime(_, _));
+                if let Some(previous) = lowered.last_mut().filter(|_| !primed) {
+                    deferred = defer_drain(previous);
+                }
+            } else {
+                actions.append(&mut deferred);
+            }
+            step.lower(index, total, soak, &mut actions);
+            lowered.push(actions);
+        }
+        Ok(lowered)
+    }
+    /// The step-by-step differences between this protocol and another (such as an edited copy
+    /// of it), in order.
+    pub fn diff(&self, other: &Self) -> Vec<StepDiff> {
+        diff(&self.steps, &other.steps)
+    }
+}
+
+/// Removes the drain ending the given step's actions, returning it along with any switches back
+/// to the first pump that follow it, so that the drain still uses the step's pump.
+///
+/// Notifications after the drain are left where they are. If the step doesn't end by draining,
+/// nothing is removed.
+fn defer_drain(actions: &mut Vec<Action>) -> Vec<Action> {
+    let trailing = actions
+        .iter()
+        .rev()
+        .take_while(|action| matches!(action, Action::Notify(_) | Action::SelectPump(_)))
+        .count();
+    let drain = actions.len() - trailing;
+    if drain == 0 || actions[drain - 1] != Action::Drain {
+        return vec![];
+    }
+    let mut deferred = vec![actions.remove(drain - 1)];
+    let mut index = drain - 1;
+    while index < actions.len() {
+        if let Action::SelectPump(_) = actions[index] {
+            deferred.push(actions.remove(index));
+        } else {
+            index += 1;
+        }
+    }
+    deferred
+}
+
+/// Renders a duration briefly, in the largest unit that divides it evenly.
+fn describe(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if duration.subsec_nanos() != 0 {
+        format!("{} ms", duration.as_millis())
+    } else if secs >= 3600 && secs % 3600 == 0 {
+        format!("{} h", secs / 3600)
+    } else if secs >= 60 && secs % 60 == 0 {
+        format!("{} min", secs / 60)
+    } else {
+        format!("{} s", secs)
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, step) in self.steps.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}. {}", index + 1, step)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Perfuse(motor, Some(duration)) => {
+                write!(f, "Perfuse buffer {} for {}", motor, describe(*duration))
+            }
+            Self::Perfuse(motor, None) => write!(f, "Bath in buffer {}", motor),
+            Self::PerfusePrompt(motor, begin, duration, end) => write!(
+                f,
+                "Perfuse buffer {}, prompting \"{}\" before and \"{}\" after {}",
+                motor,
+                begin.subject,
+                end.subject,
+                describe(*duration)
+            ),
+            Self::Monitored(step, _) => write!(f, "{} (monitored)", step),
+            Self::Pumped(pump, step) => write!(f, "{} (pump {})", step, pump),
+            Self::Wait(duration) => write!(f, "Wait {}", describe(*duration)),
+            Self::Recirculate {
+                motor,
+                cycles,
+                forward,
+                backward,
+            } => write!(
+                f,
+                "Recirculate buffer {} {} times ({} forward, {} backward)",
+                motor,
+                cycles,
+                describe(*forward),
+                describe(*backward)
+            ),
+            Self::Prime(motor, duration) => {
+                write!(f, "Prime buffer {} for {}", motor, describe(*duration))
+            }
+            Self::Repeat(count, steps) => {
+                write!(f, "Repeat {} times:", count)?;
+                for (index, step) in steps.iter().enumerate() {
+                    let separator = if index == 0 { " " } else { "; " };
+                    write!(f, "{}{}", separator, step)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Perfuse(motor) => write!(f, "Perfuse buffer {}", motor),
+            Self::Prime(motor, duration) => {
+                write!(f, "Prime buffer {} for {}", motor, describe(*duration))
+            }
+            Self::Sleep(duration) => write!(f, "Wait {}", describe(*duration)),
+            Self::Hail => write!(f, "Wait for operator"),
+            Self::Drain => write!(f, "Drain"),
+            Self::Finish => write!(f, "Finish"),
+            Self::Notify(notification) => write!(f, "Notify \"{}\"", notification.subject),
+            Self::SelectPump(pump) => write!(f, "Use pump {}", pump),
+            Self::Forward(motor, duration) => {
+                write!(
+                    f,
+                    "Pump buffer {} forward for {}",
+                    motor,
+                    describe(*duration)
+                )
+            }
+            Self::Backward(motor, duration) => {
+                write!(
+                    f,
+                    "Pump buffer {} backward for {}",
+                    motor,
+                    describe(*duration)
+                )
+            }
+        }
+    }
+}
+
+/// Assembles a protocol step by step.
+///
+/// ```
+/// # use deoxy_core::ProtocolBuilder;
+/// # use std::time::Duration;
+/// let protocol = ProtocolBuilder::new()
+///     .perfuse(0, Duration::new(300, 0))
+///     .perfuse(1, Duration::new(600, 0))
+///     .bath(2)
+///     .build();
+/// assert!(protocol.is_ok());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ProtocolBuilder {
+    steps: Vec<Step>,
+}
+
+impl ProtocolBuilder {
+    /// Starts an empty protocol.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds the given step.
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+    /// Perfuses with the given buffer for the given duration.
+    pub fn perfuse(self, motor: MotorId, duration: Duration) -> Self {
+        self.step(Step::Perfuse(motor, Some(duration)))
+    }
+    /// Perfuses with the given buffer, prompting the user as described for
+    /// [`Step::PerfusePrompt`](enum.Step.html#variant.PerfusePrompt).
+    pub fn prompt(
+        self,
+        motor: MotorId,
+        begin: Notification,
+        duration: Duration,
+        end: Notification,
+    ) -> Self {
+        self.step(Step::PerfusePrompt(motor, begin, duration, end))
+    }
+    /// Primes the lines with the given buffer for the given duration, draining it to waste.
+    pub fn prime(self, motor: MotorId, duration: Duration) -> Self {
+        self.step(Step::Prime(motor, duration))
+    }
+    /// Leaves the sample in its current buffer, without flow, for the given duration.
+    pub fn wait(self, duration: Duration) -> Self {
+        self.step(Step::Wait(duration))
+    }
+    /// Perfuses with the given buffer, recirculating it as described for
+    /// [`Step::Recirculate`](enum.Step.html#variant.Recirculate).
+    pub fn recirculate(
+        self,
+        motor: MotorId,
+        cycles: u32,
+        forward: Duration,
+        backward: Duration,
+    ) -> Self {
+        self.step(Step::Recirculate {
+            motor,
+            cycles,
+            forward,
+            backward,
+        })
+    }
+    /// Perfuses with the given buffer indefinitely.
+    ///
+    /// Every protocol must end with a bath.
+    pub fn bath(self, motor: MotorId) -> Self {
+        self.step(Step::Perfuse(motor, None))
+    }
+    /// Validates and returns the protocol.
+    pub fn build(self) -> Result<Protocol, ValidateError> {
+        let protocol = Protocol { steps: self.steps };
+        protocol.validate()?;
+        Ok(protocol)
+    }
+}
+
+impl Step {
+    /// The underlying step, without any monitoring.
+    fn inner(&self) -> &Self {
+        match self {
+            Self::Monitored(step, _) | Self::Pumped(_, step) => step.inner(),
+            Self::Perfuse(_, _)
+            | Self::PerfusePrompt(_, _, _, _)
+            | Self::Prime(_, _)
+            | Self::Repeat(_, _)
+            | Self::Recirculate { .. }
+            | Self::Wait(_) => self,
+        }
+    }
+    /// Whether this step is (or contains) a repetition that would run no steps.
+    fn has_zero_repeat(&self) -> bool {
+        match self {
+            Self::Monitored(step, _) | Self::Pumped(_, step) => step.has_zero_repeat(),
+            Self::Repeat(count, steps) => {
+                *count == 0 || steps.is_empty() || steps.iter().any(Self::has_zero_repeat)
+            }
+            Self::Perfuse(_, _)
+            | Self::PerfusePrompt(_, _, _, _)
+            | Self::Prime(_, _)
+            | Self::Recirculate { .. }
+            | Self::Wait(_) => false,
+        }
+    }
+    /// Appends this step to `steps`, expanding any repetitions.
+    ///
+    /// Monitoring a repetition monitors each of the steps it expands to.
+    fn expand(&self, steps: &mut Vec<Self>) {
+        match self {
+            Self::Monitored(step, monitor) => {
+                let mut inner = vec![];
+                step.expand(&mut inner);
+                steps.extend(
+                    inner
+                        .into_iter()
+                        .map(|step| Self::Monitored(Box::new(step), *monitor)),
+                );
+            }
+            Self::Pumped(pump, step) => {
+                let mut inner = vec![];
+                step.expand(&mut inner);
+                steps.extend(
+                    inner
+                        .into_iter()
+                        .map(|step| Self::Pumped(*pump, Box::new(step))),
+                );
+            }
+            Self::Repeat(count, inner) => {
+                for _ in 0..*count {
+                    for step in inner {
+                        step.expand(steps);
+                    }
+                }
+            }
+            Self::Perfuse(_, _)
+            | Self::PerfusePrompt(_, _, _, _)
+            | Self::Prime(_, _)
+            | Self::Recirculate { .. }
+            | Self::Wait(_) => steps.push(self.clone()),
+        }
+    }
+    /// The pump this step uses.
+    ///
+    /// Where pumps are nested, the innermost applies.
+    pub fn pump(&self) -> PumpId {
+        self.chosen_pump().unwrap_or(0)
+    }
+    /// The pump this step explicitly chooses, if any.
+    fn chosen_pump(&self) -> Option<PumpId> {
+        match self {
+            Self::Pumped(pump, step) => Some(step.chosen_pump().unwrap_or(*pump)),
+            Self::Monitored(step, _) => step.chosen_pump(),
+            _ => None,
+        }
+    }
+    /// Whether this step is a wait, during which nothing is perfused or drained.
+    pub fn is_wait(&self) -> bool {
+        matches!(self.inner(), Self::Wait(_))
+    }
+    /// Whether this step is a perfusion for an unspecified duration (a bath).
+    ///
+    /// Only a bath may end a protocol.
+    pub fn is_bath(&self) -> bool {
+        match self.inner() {
+            Self::Perfuse(_, duration) => duration.is_none(),
+            _ => false,
+        }
+    }
+    /// Appends the actions for this step (the `index`th of `total`) to `actions`.
+    ///
+    /// Each step but the last drains its buffer (after soaking) when done; the last step leaves
+    /// the sample bathed, and so neither waits nor drains. Priming always drains straight away,
+    /// since it never reaches the sample, and waiting only sleeps. Repetitions must already have
+    /// been expanded.
+    fn lower(&self, index: usize, total: usize, soak: Duration, actions: &mut Vec<Action>) {
+        let last = index + 1 == total;
+        match self {
+            &Self::Perfuse(motor, duration) => {
+                actions.push(Action::Perfuse(motor));
+                if !last {
+                    actions.push(duration.map(Action::Sleep).unwrap_or(Action::Hail));
+                }
+            }
+            Self::PerfusePrompt(motor, begin, duration, end) => {
+                actions.push(Action::Perfuse(*motor));
+                actions.push(Action::Notify(begin.clone()));
+                actions.push(Action::Hail);
+                actions.push(Action::Sleep(*duration));
+                actions.push(Action::Notify(end.clone()));
+                actions.push(Action::Hail);
+            }
+            Self::Monitored(step, monitor) => {
+                let number = index + 1;
+                if monitor.on_start {
+                    actions.push(Action::Notify(Notification {
+                        subject: format!("Step {} started", number),
+                        message: format!("Step {} of {} has started.", number, total),
+                    }));
+                }
+                step.lower(index, total, soak, actions);
+                // The final bath never completes; the user is notified when the job finishes.
+                if monitor.on_complete && !last {
+                    actions.push(Action::Notify(Notification {
+                        subject: format!("Step {} completed", number),
+                        message: format!("Step {} of {} has completed.", number, total),
+                    }));
+                }
+                return;
+            }
+            &Self::Prime(motor, duration) => {
+                actions.push(Action::Prime(motor, duration));
+                actions.push(Action::Drain);
+                return;
+            }
+            &Self::Wait(duration) => {
+                actions.push(Action::Sleep(duration));
+                return;
+            }
+            &Self::Recirculate {
+                motor,
+                cycles,
+                forward,
+                backward,
+            } => {
+                actions.push(Action::Perfuse(motor));
+                for _ in 0..cycles {
+                    actions.push(Action::Forward(motor, forward));
+                    actions.push(Action::Backward(motor, backward));
+                }
+            }
+            Self::Pumped(pump, step) => {
+                // Everything the step does (including draining) uses the pump, and the first
+                // pump is used again afterward.
+                actions.push(Action::SelectPump(*pump));
+                step.lower(index, total, soak, actions);
+                actions.push(Action::SelectPump(0));
+                return;
+            }
+            Self::Repeat(_, _) => unreachable!("Repetitions are expanded before lowering."),
+        }
+        if !last {
+            if soak > Duration::new(0, 0) {
+                actions.push(Action::Sleep(soak));
+            }
+            actions.push(Action::Drain);
+        }
+    }
 }
 
 /// Represents a specific action to be run.
@@ -145,6 +1142,12 @@ pub enum Action {
     /// Perfuse with the specified solution until a full volume is reached, then close the valve
     /// and turn off the pump.
     Perfuse(MotorId),
+    /// Run the specified solution through the lines for the specified duration (regardless of
+    /// volume), then close the valve and turn off the pump.
+    Prime(
+        MotorId,
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))] Duration,
+    ),
     /// Wait for the specified duration.
     Sleep(Duration),
     /// Wait for the user to continue.
@@ -155,9 +1158,48 @@ pub enum Action {
     Finish,
     /// Notify the user.
     Notify(Notification),
+    /// Use the given pump for the actions that follow.
+    SelectPump(PumpId),
+    /// Run the pump forward for the specified duration with the specified solution's valve open,
+    /// then turn off the pump (leaving the valve open).
+    Forward(
+        MotorId,
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))] Duration,
+    ),
+    /// Run the pump backward for the specified duration with the specified solution's valve open,
+    /// then turn off the pump (leaving the valve open).
+    Backward(
+        MotorId,
+        #[cfg_attr(feature = "use_serde", serde(with = "crate::duration"))] Duration,
+    ),
 }
 
 impl Action {
+    /// The name of the kind of action, as used when serializing it (such as `perfuse`).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Perfuse(_) => "perfuse",
+            Self::Prime(_, _) => "prime",
+            Self::Sleep(_) => "sleep",
+            Self::Hail => "hail",
+            Self::Drain => "drain",
+            Self::Finish => "finish",
+            Self::Notify(_) => "notify",
+            Self::SelectPump(_) => "selectpump",
+            Self::Forward(_, _) => "forward",
+            Self::Backward(_, _) => "backward",
+        }
+    }
+    /// The buffer whose valve the action opens, if any.
+    pub fn buffer(&self) -> Option<MotorId> {
+        match self {
+            Self::Perfuse(motor)
+            | Self::Prime(motor, _)
+            | Self::Forward(motor, _)
+            | Self::Backward(motor, _) => Some(*motor),
+            _ => None,
+        }
+    }
     /// Whether this action can be performed in isolation from the preceding steps.
     ///
     /// If true, the coordinator will stop *before* this step when stopping early.
@@ -165,8 +1207,12 @@ impl Action {
         match self {
             // These actions come after perfusing, so we can stop after the prior step if need be.
             Self::Sleep(_) | Self::Hail | Self::Finish | Self::Drain => true,
+            // Selecting a pump doesn't move anything.
+            Self::SelectPump(_) => true,
+            // Recirculating happens with the chamber full, so the sample is never left dry.
+            Self::Forward(_, _) | Self::Backward(_, _) => true,
             // Don't stop before perfusing (the sample should not be dry when we're done)
-            Self::Perfuse(_) => false,
+            Self::Perfuse(_) | Self::Prime(_, _) => false,
             // Don't stop without notifying
             Self::Notify(_) => false,
         }
@@ -181,12 +1227,177 @@ pub struct Program {
     actions: Vec<Action>,
 }
 
+impl Program {
+    /// The number of actions in the program.
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+    /// Whether the program has no actions.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+    /// The actions of the program, in order.
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+    /// The buffers the program perfuses with.
+    pub fn buffers(&self) -> BTreeSet<MotorId> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Perfuse(motor) | Action::Prime(motor, _) => Some(*motor),
+                _ => None,
+            })
+            .collect()
+    }
+    /// Names each of the buffers the program perfuses with, given the labels of the motors (by
+    /// id).
+    pub fn resolve_labels(&self, labels: &[Option<String>]) -> BTreeMap<MotorId, String> {
+        resolve_labels(labels, self.buffers())
+    }
+    /// The pumps the program selects.
+    ///
+    /// The first pump is used until another is selected, so it's only listed here if the
+    /// program returns to it.
+    pub fn pumps(&self) -> BTreeSet<PumpId> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::SelectPump(pump) => Some(*pump),
+                _ => None,
+            })
+            .collect()
+    }
+    /// The action-by-action differences between this program and another, in order.
+    pub fn diff(&self, other: &Self) -> Vec<ActionDiff> {
+        diff(&self.actions, &other.actions)
+    }
+}
+
+/// A difference between two sequences of entries (steps or actions).
+///
+/// Indices into the original sequence are called `old`, and those into the edited sequence
+/// `new`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "use_serde", serde(rename_all = "lowercase"))]
+pub enum Diff<T> {
+    /// The entry was added.
+    Added {
+        /// The index of the entry in the edited sequence.
+        new: usize,
+        /// The entry itself.
+        entry: T,
+    },
+    /// The entry was removed.
+    Removed {
+        /// The index of the entry in the original sequence.
+        old: usize,
+        /// The entry itself.
+        entry: T,
+    },
+    /// The entry was replaced by another.
+    Changed {
+        /// The index of the entry in the original sequence.
+        old: usize,
+        /// The index of its replacement in the edited sequence.
+        new: usize,
+        /// The original entry.
+        from: T,
+        /// Its replacement.
+        to: T,
+    },
+}
+
+/// A difference between the steps of two protocols.
+pub type StepDiff = Diff<Step>;
+
+/// A difference between the actions of two programs.
+pub type ActionDiff = Diff<Action>;
+
+/// Finds the differences between two sequences, keeping as many entries in common as possible.
+///
+/// Where entries are removed and others added in their place, they're reported as changed,
+/// pairwise.
+fn diff<T: Clone + PartialEq>(old: &[T], new: &[T]) -> Vec<Diff<T>> {
+    // `common[i][j]` is the length of the longest common subsequence of `old[i..]` and
+    // `new[j..]`.
+    let mut common = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut diffs = vec![];
+    // The indices of the entries removed and added since the last entry in common.
+    let (mut removed, mut added) = (vec![], vec![]);
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            flush(&mut diffs, (old, &mut removed), (new, &mut added));
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || common[i][j + 1] >= common[i + 1][j]) {
+            added.push(j);
+            j += 1;
+        } else {
+            removed.push(i);
+            i += 1;
+        }
+    }
+    flush(&mut diffs, (old, &mut removed), (new, &mut added));
+    diffs
+}
+
+/// Records a run of removed and added entries (by index), pairing them off as changes.
+fn flush<T: Clone>(
+    diffs: &mut Vec<Diff<T>>,
+    (old, removed): (&[T], &mut Vec<usize>),
+    (new, added): (&[T], &mut Vec<usize>),
+) {
+    let paired = removed.len().min(added.len());
+    for (&i, &j) in removed.iter().zip(added.iter()) {
+        diffs.push(Diff::Changed {
+            old: i,
+            new: j,
+            from: old[i].clone(),
+            to: new[j].clone(),
+        });
+    }
+    for &i in &removed[paired..] {
+        diffs.push(Diff::Removed {
+            old: i,
+            entry: old[i].clone(),
+        });
+    }
+    for &j in &added[paired..] {
+        diffs.push(Diff::Added {
+            new: j,
+            entry: new[j].clone(),
+        });
+    }
+    removed.clear();
+    added.clear();
+}
+
 impl Into<Vec<Action>> for Program {
     fn into(self) -> Vec<Action> {
         self.actions
     }
 }
 
+impl<'a> IntoIterator for &'a Program {
+    type Item = &'a Action;
+    type IntoIter = std::slice::Iter<'a, Action>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.actions.iter()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -203,4 +1414,741 @@ mod tests {
         protocol.steps.clear();
         assert_eq!(protocol.as_program(), Err(ValidateError::Empty));
     }
+    #[test]
+    fn borrowed_program() {
+        let protocol = Protocol {
+            steps: vec![
+                Step::Perfuse(0, Some(Duration::new(10, 0))),
+                Step::Perfuse(1, None),
+            ],
+        };
+        let program = protocol.as_program().unwrap();
+        assert_eq!(program.len(), 5);
+        assert!(!program.is_empty());
+        assert_eq!(program.actions()[0], Action::Perfuse(0));
+        let mut perfusions = 0;
+        for action in &program {
+            if let Action::Perfuse(_) = action {
+                perfusions += 1;
+            }
+        }
+        assert_eq!(perfusions, 2);
+        let actions: Vec<Action> = program.clone().into();
+        assert_eq!(program.actions(), &actions[..]);
+    }
+    #[test]
+    fn soak_before_drain() {
+        let protocol = Protocol {
+            steps: vec![
+                Step::Perfuse(0, Some(Duration::new(10, 0))),
+                Step::Perfuse(1, None),
+            ],
+        };
+        let soaked = protocol.as_program_with_soak(Duration::new(5, 0)).unwrap();
+        assert_eq!(
+            soaked.actions,
+            vec![
+                Action::Perfuse(0),
+                Action::Sleep(Duration::new(10, 0)),
+                Action::Sleep(Duration::new(5, 0)),
+                Action::Drain,
+                Action::Perfuse(1),
+                Action::Finish,
+            ]
+        );
+        assert_eq!(
+            protocol.as_program_with_soak(Duration::new(0, 0)),
+            protocol.as_program()
+        );
+    }
+    #[test]
+    fn pumped_steps() {
+        let protocol = Protocol {
+            steps: vec![
+                Step::Perfuse(0, Some(Duration::new(10, 0))),
+                Step::Pumped(1, Box::new(Step::Perfuse(1, Some(Duration::new(10, 0))))),
+                Step::Perfuse(2, None),
+            ],
+        };
+        let program = protocol.as_program().unwrap();
+        assert_eq!(
+            program.actions,
+            vec![
+                Action::Perfuse(0),
+                Action::Sleep(Duration::new(10, 0)),
+                Action::Drain,
+                Action::SelectPump(1),
+                Action::Perfuse(1),
+                Action::Sleep(Duration::new(10, 0)),
+                Action::Drain,
+                Action::SelectPump(0),
+                Action::Perfuse(2),
+                Action::Finish,
+            ]
+        );
+        assert_eq!(protocol.pumps(), program.pumps());
+        assert_eq!(program.pumps().into_iter().collect::<Vec<_>>(), vec![0, 1]);
+        let first = Protocol {
+            steps: vec![Step::Pumped(1, Box::new(Step::Perfuse(0, None)))],
+        };
+        assert!(first.as_program().is_ok());
+        assert_eq!(first.steps[0].pump(), 1);
+    }
+    #[test]
+    fn notify_on_complete() {
+        let monitor = Monitor {
+            on_start: false,
+            on_complete: true,
+        };
+        let protocol = Protocol {
+            steps: vec![
+                Step::Monitored(
+                    Box::new(Step::Perfuse(0, Some(Duration::new(10, 0)))),
+                    monitor,
+                ),
+                Step::Monitored(Box::new(Step::Perfuse(1, None)), monitor),
+            ],
+        };
+        let program = protocol.as_program().unwrap();
+        assert_eq!(
+            program.actions,
+            vec![
+                Action::Perfuse(0),
+                Action::Sleep(Duration::new(10, 0)),
+                Action::Drain,
+                Action::Notify(Notification {
+                    subject: "Step 1 completed".into(),
+                    message: "Step 1 of 2 has completed.".into(),
+                }),
+                Action::Perfuse(1),
+                Action::Finish,
+            ]
+        );
+    }
+    #[test]
+    fn timed_duration() {
+        let fill = Duration::from_millis((DEFAULT_VOLUME / DEFAULT_RATE * 1000.0) as u64);
+        let bath = Protocol::with_step(Step::Perfuse(0, None));
+        assert_eq!(bath.duration(), Some(fill));
+        let protocol = Protocol {
+            steps: vec![
+                Step::Perfuse(0, Some(Duration::new(60, 0))),
+                Step::Perfuse(1, Some(Duration::new(30, 0))),
+                Step::Perfuse(2, None),
+            ],
+        };
+        assert_eq!(protocol.duration(), Some(fill * 7 + Duration::new(90, 0)));
+    }
+    #[test]
+    fn indefinite_duration() {
+        let protocol = Protocol {
+            steps: vec![Step::Perfuse(0, None), Step::Perfuse(1, None)],
+        };
+        assert_eq!(protocol.duration(), None);
+        let notification = Notification {
+            subject: "Check".into(),
+            message: "Check the sample.".into(),
+        };
+        let protocol = Protocol {
+            steps: vec![
+                Step::PerfusePrompt(0, notification.clone(), Duration::new(60, 0), notification),
+                Step::Perfuse(1, None),
+            ],
+        };
+        assert_eq!(protocol.duration(), None);
+    }
+    #[test]
+    fn repeat_expands() {
+        let minute = Duration::new(60, 0);
+        let protocol = Protocol {
+            steps: vec![
+                Step::Repeat(
+                    3,
+                    vec![
+                        Step::Perfuse(0, Some(minute)),
+                        Step::Perfuse(1, Some(minute)),
+                    ],
+                ),
+                Step::Perfuse(2, None),
+            ],
+        };
+        let cycle = vec![
+            Action::Perfuse(0),
+            Action::Sleep(minute),
+            Action::Drain,
+            Action::Perfuse(1),
+            Action::Sleep(minute),
+            Action::Drain,
+        ];
+        let mut expected = vec![];
+        for _ in 0..3 {
+            expected.extend(cycle.clone());
+        }
+        expected.push(Action::Perfuse(2));
+        expected.push(Action::Finish);
+        assert_eq!(protocol.as_program().unwrap().actions, expected);
+    }
+    #[test]
+    fn parse_protocol() {
+        let protocol = Protocol::try_from(
+            "perfuse 0 5m; wait 90\nprompt 1 \"Add antibody\" 1h30m \"Rinse\";\n\nbath 3;",
+        )
+        .unwrap();
+        let notification = |subject: &str| Notification {
+            subject: subject.to_owned(),
+            message: String::new(),
+        };
+        assert_eq!(
+            protocol.steps(),
+            [
+                Step::Perfuse(0, Some(Duration::new(300, 0))),
+                Step::Wait(Duration::new(90, 0)),
+                Step::PerfusePrompt(
+                    1,
+                    notification("Add antibody"),
+                    Duration::new(5400, 0),
+                    notification("Rinse")
+                ),
+                Step::Perfuse(3, None),
+            ]
+        );
+        assert_eq!(protocol.validate(), Ok(()));
+        let short = Protocol::parse("perfuse 2 500ms").unwrap();
+        assert_eq!(
+            short.steps(),
+            [Step::Perfuse(2, Some(Duration::from_millis(500)))]
+        );
+    }
+    #[test]
+    fn parse_errors() {
+        let err = Protocol::parse("perfuse 0 5m; flush 1 10m; bath 3").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError {
+                position: 14,
+                token: "flush".to_owned(),
+                kind: ParseErrorKind::UnknownKeyword,
+            }
+        );
+        assert_eq!(err.to_string(), "Unknown keyword `flush` at position 14");
+        let kind = |text| Protocol::parse(text).unwrap_err().kind;
+        assert_eq!(kind("perfuse a 5m"), ParseErrorKind::InvalidBuffer);
+        assert_eq!(kind("perfuse 0 5 minutes"), ParseErrorKind::ExtraArgument);
+        assert_eq!(kind("wait 5y"), ParseErrorKind::InvalidDuration);
+        assert_eq!(kind("prompt 0 begin 5m end"), ParseErrorKind::ExpectedText);
+        assert_eq!(
+            kind("prompt 0 \"begin 5m"),
+            ParseErrorKind::UnterminatedQuote
+        );
+        let missing = Protocol::parse("bath 0; perfuse 1").unwrap_err();
+        assert_eq!(missing.kind, ParseErrorKind::MissingArgument("a duration"));
+        assert_eq!(missing.position, 17);
+    }
+    #[test]
+    fn step_accessors() {
+        let mut protocol = Protocol::with_step(Step::Perfuse(2, None));
+        protocol.insert_step(0, Step::Perfuse(0, Some(Duration::new(60, 0))));
+        protocol.push_step(Step::Perfuse(3, None));
+        assert_eq!(protocol.remove_step(2), Step::Perfuse(3, None));
+        assert_eq!(
+            protocol.steps(),
+            [
+                Step::Perfuse(0, Some(Duration::new(60, 0))),
+                Step::Perfuse(2, None)
+            ]
+        );
+        // Invalid steps are refused, leaving the protocol as it was.
+        let unfinished = vec![Step::Perfuse(1, Some(Duration::new(60, 0)))];
+        assert!(matches!(
+            protocol.set_steps(unfinished),
+            Err(ValidateError::Last { index: 0, .. })
+        ));
+        assert_eq!(protocol.steps().len(), 2);
+        protocol.set_steps(vec![Step::Perfuse(1, None)]).unwrap();
+        assert_eq!(protocol.steps(), [Step::Perfuse(1, None)]);
+    }
+    #[test]
+    fn volume_per_reused_buffer() {
+        let minute = Duration::new(60, 0);
+        let protocol = ProtocolBuilder::new()
+            .prime(0, minute)
+            .perfuse(0, minute)
+            .perfuse(1, minute)
+            .perfuse(0, minute)
+            .recirculate(2, 3, minute, minute)
+            .perfuse(0, minute)
+            .bath(1)
+            .build()
+            .unwrap();
+        let volumes = protocol.volume_per_buffer(100.0);
+        // Priming doesn't count, and recirculating only fills once.
+        let expected = vec![(0, 300.0), (1, 200.0), (2, 100.0)]
+            .into_iter()
+            .collect();
+        assert_eq!(volumes, expected);
+    }
+    #[test]
+    fn step_duration_limit() {
+        let minutes = |minutes: u64| Some(Duration::new(60 * minutes, 0));
+        let protocol = Protocol {
+            steps: vec![
+                Step::Perfuse(0, minutes(5)),
+                Step::Wait(Duration::new(60 * 30, 0)),
+                Step::Perfuse(1, minutes(20)),
+                Step::Perfuse(2, None),
+            ],
+        };
+        // There's no limit unless one is given.
+        assert_eq!(protocol.validate_with_limit(None), Ok(()));
+        assert_eq!(protocol.validate_with_limit(minutes(30)), Ok(()));
+        let typo = Protocol {
+            steps: vec![
+                Step::Perfuse(0, minutes(5)),
+                Step::Repeat(2, vec![Step::Perfuse(1, Some(Duration::new(50000, 0)))]),
+                Step::Perfuse(2, None),
+            ],
+        };
+        assert_eq!(typo.validate(), Ok(()));
+        let err = typo.validate_with_limit(minutes(30)).unwrap_err();
+        assert_eq!(
+            err,
+            ValidateError::TooLong {
+                index: 1,
+                duration: Duration::new(50000, 0)
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "Step 2 lasts 50000 seconds, which is longer than allowed"
+        );
+    }
+    #[test]
+    fn zero_duration_index() {
+        let minute = Some(Duration::new(60, 0));
+        let protocol = Protocol {
+            steps: vec![
+                Step::Perfuse(0, minute),
+                Step::Repeat(2, vec![Step::Perfuse(1, minute)]),
+                Step::Perfuse(2, Some(Duration::new(0, 0))),
+                Step::Perfuse(3, minute),
+                Step::Perfuse(0, None),
+            ],
+        };
+        assert_eq!(protocol.validate(), Err(ValidateError::ZeroDuration(3)));
+        assert_eq!(
+            protocol.validate().unwrap_err().to_string(),
+            "Step 4 has a duration of zero"
+        );
+    }
+    #[test]
+    fn prime_then_perfuse() {
+        let protocol = ProtocolBuilder::new()
+            .prime(0, Duration::new(5, 0))
+            .perfuse(0, Duration::new(60, 0))
+            .bath(1)
+            .build()
+            .unwrap();
+        assert_eq!(
+            protocol
+                .as_program_with_soak(Duration::new(10, 0))
+                .unwrap()
+                .actions,
+            vec![
+                Action::Prime(0, Duration::new(5, 0)),
+                Action::Drain,
+                Action::Perfuse(0),
+                Action::Sleep(Duration::new(60, 0)),
+                Action::Sleep(Duration::new(10, 0)),
+                Action::Drain,
+                Action::Perfuse(1),
+                Action::Finish,
+            ]
+        );
+    }
+    #[test]
+    fn prime_is_not_terminal() {
+        let protocol = Protocol {
+            steps: vec![Step::Perfuse(0, None), Step::Prime(1, Duration::new(5, 0))],
+        };
+        assert!(matches!(
+            protocol.validate(),
+            Err(ValidateError::Last { index: 1, .. })
+        ));
+        let protocol = Protocol {
+            steps: vec![Step::Prime(1, Duration::new(0, 0)), Step::Perfuse(0, None)],
+        };
+        assert_eq!(protocol.validate(), Err(ValidateError::ZeroDuration(0)));
+    }
+    #[test]
+    fn wait_only_sleeps() {
+        let wait = Duration::new(600, 0);
+        let protocol = Protocol {
+            steps: vec![
+                Step::Perfuse(0, Some(Duration::new(10, 0))),
+                Step::Wait(wait),
+                Step::Perfuse(1, None),
+            ],
+        };
+        let steps = protocol.step_actions(Duration::new(0, 0)).unwrap();
+        assert_eq!(steps[1], vec![Action::Sleep(wait)]);
+        // The sample stays in the first buffer through the wait.
+        assert_eq!(
+            protocol.as_program().unwrap().actions,
+            vec![
+                Action::Perfuse(0),
+                Action::Sleep(Duration::new(10, 0)),
+                Action::Sleep(wait),
+                Action::Drain,
+                Action::Perfuse(1),
+                Action::Finish,
+            ]
+        );
+        assert_eq!(
+            protocol.duration(),
+            Protocol {
+                steps: vec![
+                    Step::Perfuse(0, Some(Duration::new(10, 0))),
+                    Step::Perfuse(1, None)
+                ],
+            }
+            .duration()
+            .map(|duration| duration + wait)
+        );
+        assert!(protocol.buffers().into_iter().eq(vec![0, 1]));
+    }
+    #[test]
+    fn wait_after_pumped_step() {
+        let minute = Duration::new(60, 0);
+        let protocol = Protocol {
+            steps: vec![
+                Step::Monitored(
+                    Box::new(Step::Pumped(1, Box::new(Step::Perfuse(0, Some(minute))))),
+                    Monitor {
+                        on_start: false,
+                        on_complete: true,
+                    },
+                ),
+                Step::Wait(minute),
+                Step::Perfuse(1, None),
+            ],
+        };
+        let actions = protocol.as_program().unwrap().actions;
+        assert!(matches!(actions[3], Action::Notify(_)));
+        // The second pump drains the sample, but only once the waiting is done.
+        assert_eq!(
+            actions[..3],
+            [
+                Action::SelectPump(1),
+                Action::Perfuse(0),
+                Action::Sleep(minute)
+            ]
+        );
+        assert_eq!(
+            actions[4..],
+            [
+                Action::Sleep(minute),
+                Action::Drain,
+                Action::SelectPump(0),
+                Action::Perfuse(1),
+                Action::Finish,
+            ]
+        );
+    }
+    #[test]
+    fn wait_is_not_terminal() {
+        let protocol = Protocol {
+            steps: vec![Step::Perfuse(0, None), Step::Wait(Duration::new(5, 0))],
+        };
+        assert!(matches!(
+            protocol.validate(),
+            Err(ValidateError::Last { index: 1, .. })
+        ));
+        let protocol = Protocol {
+            steps: vec![Step::Wait(Duration::new(0, 0)), Step::Perfuse(0, None)],
+        };
+        assert_eq!(protocol.validate(), Err(ValidateError::ZeroDuration(0)));
+    }
+    #[test]
+    fn recirculate_cycles() {
+        let (forward, backward) = (Duration::new(5, 0), Duration::new(3, 0));
+        let protocol = ProtocolBuilder::new()
+            .recirculate(1, 2, forward, backward)
+            .bath(0)
+            .build()
+            .unwrap();
+        assert_eq!(
+            protocol.as_program().unwrap().actions,
+            vec![
+                Action::Perfuse(1),
+                Action::Forward(1, forward),
+                Action::Backward(1, backward),
+                Action::Forward(1, forward),
+                Action::Backward(1, backward),
+                Action::Drain,
+                Action::Perfuse(0),
+                Action::Finish,
+            ]
+        );
+        assert!(protocol.buffers().into_iter().eq(vec![0, 1]));
+        let step = |cycles, forward| Step::Recirculate {
+            motor: 1,
+            cycles,
+            forward,
+            backward,
+        };
+        let zero = Protocol {
+            steps: vec![step(0, forward), Step::Perfuse(0, None)],
+        };
+        assert_eq!(zero.validate(), Err(ValidateError::ZeroCycles(0)));
+        let instant = Protocol {
+            steps: vec![step(2, Duration::new(0, 0)), Step::Perfuse(0, None)],
+        };
+        assert_eq!(instant.validate(), Err(ValidateError::ZeroDuration(0)));
+        let last = Protocol {
+            steps: vec![Step::Perfuse(0, None), step(2, forward)],
+        };
+        assert!(matches!(
+            last.validate(),
+            Err(ValidateError::Last { index: 1, .. })
+        ));
+    }
+    #[test]
+    fn labels() {
+        let labels = vec![None, Some("Water".to_owned())];
+        assert_eq!(buffer_label(&labels, 0), "Buffer 0");
+        assert_eq!(buffer_label(&labels, 1), "Water");
+        assert_eq!(buffer_label(&labels, 2), "Buffer 2");
+        let protocol = Protocol {
+            steps: vec![
+                Step::Repeat(1, vec![Step::Perfuse(2, Some(Duration::new(5, 0)))]),
+                Step::Perfuse(1, None),
+            ],
+        };
+        let resolved = protocol.resolve_labels(&labels);
+        assert_eq!(
+            resolved.into_iter().collect::<Vec<_>>(),
+            vec![(1, "Water".to_owned()), (2, "Buffer 2".to_owned())]
+        );
+        assert_eq!(
+            protocol.as_program().unwrap().resolve_labels(&labels),
+            protocol.resolve_labels(&labels)
+        );
+    }
+    #[test]
+    fn builder() {
+        let minute = Duration::new(60, 0);
+        let protocol = ProtocolBuilder::new()
+            .perfuse(0, minute)
+            .perfuse(1, minute)
+            .bath(2)
+            .build()
+            .unwrap();
+        assert_eq!(
+            protocol.steps,
+            vec![
+                Step::Perfuse(0, Some(minute)),
+                Step::Perfuse(1, Some(minute)),
+                Step::Perfuse(2, None),
+            ]
+        );
+        let unbathed = ProtocolBuilder::new().bath(0).perfuse(1, minute).build();
+        assert_eq!(
+            unbathed.unwrap_err(),
+            ValidateError::Last {
+                index: 1,
+                step: Step::Perfuse(1, Some(minute)),
+            }
+        );
+        assert_eq!(
+            ProtocolBuilder::new().build().unwrap_err(),
+            ValidateError::Empty
+        );
+    }
+    #[test]
+    fn display_steps() {
+        let notification = |subject: &str| Notification {
+            subject: subject.into(),
+            message: String::new(),
+        };
+        let five = Duration::new(300, 0);
+        assert_eq!(
+            Step::Perfuse(2, Some(five)).to_string(),
+            "Perfuse buffer 2 for 5 min"
+        );
+        assert_eq!(Step::Perfuse(4, None).to_string(), "Bath in buffer 4");
+        assert_eq!(
+            Step::PerfusePrompt(1, notification("Add stain"), five, notification("Rinse"))
+                .to_string(),
+            "Perfuse buffer 1, prompting \"Add stain\" before and \"Rinse\" after 5 min"
+        );
+        assert_eq!(
+            Step::Monitored(Box::new(Step::Perfuse(0, None)), Monitor::default()).to_string(),
+            "Bath in buffer 0 (monitored)"
+        );
+        assert_eq!(
+            Step::Repeat(
+                2,
+                vec![
+                    Step::Perfuse(0, Some(Duration::new(90, 0))),
+                    Step::Perfuse(1, Some(Duration::new(7200, 0))),
+                ]
+            )
+            .to_string(),
+            "Repeat 2 times: Perfuse buffer 0 for 90 s; Perfuse buffer 1 for 2 h"
+        );
+        let protocol = Protocol {
+            steps: vec![Step::Perfuse(0, Some(five)), Step::Perfuse(1, None)],
+        };
+        assert_eq!(
+            protocol.to_string(),
+            "1. Perfuse buffer 0 for 5 min\n2. Bath in buffer 1"
+        );
+    }
+    #[test]
+    fn display_actions() {
+        assert_eq!(Action::Perfuse(3).to_string(), "Perfuse buffer 3");
+        assert_eq!(
+            Action::Sleep(Duration::from_millis(1500)).to_string(),
+            "Wait 1500 ms"
+        );
+        assert_eq!(Action::Hail.to_string(), "Wait for operator");
+        assert_eq!(Action::Drain.to_string(), "Drain");
+        assert_eq!(Action::Finish.to_string(), "Finish");
+        assert_eq!(
+            Action::Notify(Notification {
+                subject: "Done".into(),
+                message: "All done.".into(),
+            })
+            .to_string(),
+            "Notify \"Done\""
+        );
+    }
+    #[test]
+    fn join_fragments() {
+        let minute = Duration::new(60, 0);
+        let wash = Protocol::with_step(Step::Repeat(2, vec![Step::Perfuse(0, Some(minute))]));
+        let fix = Protocol {
+            steps: vec![Step::Perfuse(1, Some(minute)), Step::Perfuse(2, None)],
+        };
+        let joined = wash.clone().concat(fix.clone()).unwrap();
+        assert_eq!(joined.steps.len(), 3);
+        assert!(joined.validate().is_ok());
+        let mut appended = wash;
+        appended.append(fix.clone()).unwrap();
+        assert_eq!(appended.steps, joined.steps);
+        // The fixation block ends in a bath, so nothing can follow it.
+        let mut bathed = fix.clone();
+        assert_eq!(
+            bathed.append(fix.clone()),
+            Err(ValidateError::IntermediateBath(1))
+        );
+        assert_eq!(bathed.steps, fix.steps);
+    }
+    #[test]
+    fn repeat_validation() {
+        let bath = Step::Perfuse(1, None);
+        let zero = Protocol {
+            steps: vec![Step::Repeat(0, vec![Step::Perfuse(0, None)]), bath.clone()],
+        };
+        assert_eq!(zero.validate(), Err(ValidateError::ZeroRepeat));
+        let nested = Protocol {
+            steps: vec![
+                Step::Repeat(2, vec![Step::Perfuse(0, Some(Duration::new(0, 0)))]),
+                bath.clone(),
+            ],
+        };
+        assert_eq!(nested.validate(), Err(ValidateError::ZeroDuration(0)));
+        // The protocol ends with the last step of the last repetition, which is a bath.
+        let trailing = Protocol {
+            steps: vec![Step::Repeat(
+                2,
+                vec![Step::Perfuse(0, Some(Duration::new(5, 0))), bath],
+            )],
+        };
+        assert!(trailing.validate().is_ok());
+        let unbathed = Protocol::with_step(Step::Repeat(
+            2,
+            vec![Step::Perfuse(0, Some(Duration::new(5, 0)))],
+        ));
+        assert_eq!(
+            unbathed.validate(),
+            Err(ValidateError::Last {
+                index: 1,
+                step: Step::Perfuse(0, Some(Duration::new(5, 0))),
+            })
+        );
+    }
+    #[test]
+    fn inserted_step_diff() {
+        let original = Protocol {
+            steps: vec![
+                Step::Perfuse(0, Some(Duration::new(60, 0))),
+                Step::Perfuse(1, None),
+            ],
+        };
+        let mut edited = original.clone();
+        edited.insert_step(1, Step::Perfuse(2, Some(Duration::new(30, 0))));
+        assert_eq!(
+            original.diff(&edited),
+            vec![Diff::Added {
+                new: 1,
+                entry: Step::Perfuse(2, Some(Duration::new(30, 0))),
+            }]
+        );
+        assert_eq!(
+            edited.diff(&original),
+            vec![Diff::Removed {
+                old: 1,
+                entry: Step::Perfuse(2, Some(Duration::new(30, 0))),
+            }]
+        );
+        let before = original.as_program().unwrap();
+        let after = edited.as_program().unwrap();
+        assert_eq!(
+            before.diff(&after),
+            vec![
+                Diff::Added {
+                    new: 3,
+                    entry: Action::Perfuse(2),
+                },
+                Diff::Added {
+                    new: 4,
+                    entry: Action::Sleep(Duration::new(30, 0)),
+                },
+                Diff::Added {
+                    new: 5,
+                    entry: Action::Drain,
+                },
+            ]
+        );
+        assert!(after.diff(&after).is_empty());
+    }
+    #[test]
+    fn changed_step_diff() {
+        let original = Protocol {
+            steps: vec![
+                Step::Perfuse(0, Some(Duration::new(60, 0))),
+                Step::Perfuse(1, None),
+            ],
+        };
+        let mut edited = original.clone();
+        edited.remove_step(0);
+        edited.insert_step(0, Step::Perfuse(0, Some(Duration::new(90, 0))));
+        edited.push_step(Step::Perfuse(2, None));
+        assert_eq!(
+            original.diff(&edited),
+            vec![
+                Diff::Changed {
+                    old: 0,
+                    new: 0,
+                    from: Step::Perfuse(0, Some(Duration::new(60, 0))),
+                    to: Step::Perfuse(0, Some(Duration::new(90, 0))),
+                },
+                Diff::Added {
+                    new: 2,
+                    entry: Step::Perfuse(2, None),
+                },
+            ]
+        );
+    }
 }
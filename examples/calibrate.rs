@@ -0,0 +1,87 @@
+use deoxy::{actix::*, Motor, MotorMessage};
+use futures::future::Future;
+use std::{
+    io::{stdin, stdout, Write},
+    str::FromStr,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+use termion::input::TermRead;
+
+/// Asks the operator for a line of input.
+fn prompt(message: &str) -> Option<String> {
+    let stdout = stdout();
+    let mut stdout = stdout.lock();
+    stdout.write_all(message.as_bytes()).ok()?;
+    stdout.write_all(b": ").ok()?;
+    stdout.flush().ok()?;
+    stdin().lock().read_line().ok()?
+}
+
+/// Asks the operator for a value until they give a valid one.
+fn ask<T: FromStr>(message: &str, default: T) -> T {
+    loop {
+        match prompt(message) {
+            Some(ref input) if input.trim().is_empty() => return default,
+            Some(input) => match input.trim().parse() {
+                Ok(value) => return value,
+                Err(_) => println!("Couldn't understand that; try again."),
+            },
+            None => std::process::exit(1),
+        }
+    }
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let pin: u16 = ask("Pin number [5]", 5);
+    let period: u64 = ask("Period (ms) [20]", 20);
+    let start: u64 = ask("Narrowest signal to try (µs) [500]", 500);
+    let end: u64 = ask("Widest signal to try (µs) [2500]", 2500);
+    let step: u64 = ask("Step (µs) [20]", 20);
+    let motor = Motor::try_new(
+        Duration::from_millis(period),
+        Duration::from_micros(start)..=Duration::from_micros(end),
+        pin,
+    )
+    .expect("Could not open pin");
+    let mut calibration = motor.calibrate(Duration::from_micros(step.max(1)));
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        System::run(move || {
+            tx.send(motor.start()).unwrap();
+        });
+    });
+    let addr = rx.recv().unwrap();
+
+    println!("Press enter to step the signal; enter `o` when the valve is open to its buffer, and");
+    println!("`c` when it's closed (passing fluid through, but not from its buffer).");
+    let mut width = calibration.next();
+    while let Some(current) = width {
+        addr.send(MotorMessage::SetPulseWidth(current))
+            .wait()
+            .unwrap();
+        let input = prompt(&format!("{} µs [enter/o/c/q]", current.as_micros()));
+        match input.as_ref().map(|input| input.trim()) {
+            Some("o") => calibration.confirm_open(),
+            Some("c") => calibration.confirm_closed(),
+            Some("q") | None => break,
+            _ => width = calibration.next(),
+        }
+        if calibration.signal_range().is_ok() {
+            break;
+        }
+    }
+    addr.send(MotorMessage::Stop).wait().unwrap();
+
+    match calibration.signal_range() {
+        Ok(range) => println!(
+            "Add this to the motor's configuration:\nrange = [\"{}us\", \"{}us\"]",
+            range.start().as_micros(),
+            range.end().as_micros()
+        ),
+        Err(err) => println!("Calibration incomplete: {}", err),
+    }
+}
@@ -0,0 +1,21 @@
+//! Checks that a protocol file (in JSON) could be run, without running it.
+//!
+//! Usage: `cargo run --example check -- protocol.json`
+use std::{env, process};
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: check <protocol.json>");
+            process::exit(2);
+        }
+    };
+    match deoxy::check::check(&path) {
+        Ok(checked) => println!("{}", checked),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
@@ -2,7 +2,7 @@
 use deoxy::Tui;
 
 use futures::Future;
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use deoxy::{actix::*, Config, CoordMessage, Coordinator, MotorConfig, Protocol, PumpConfig, Step};
 
@@ -12,36 +12,68 @@ fn main() {
     let pump = PumpConfig {
         pins: [1, 2, 3, 4],
         invert: false,
+        period: None,
+        dead_time: None,
     };
     let motor1 = MotorConfig {
         pin: 5,
         period: Duration::new(1, 0),
         range: [Duration::from_millis(500), Duration::from_millis(750)],
         label: None,
+        range_degrees: None,
+        slew: None,
     };
     let motor2 = MotorConfig {
         pin: 6,
         period: Duration::new(1, 0),
         range: [Duration::from_millis(500), Duration::from_millis(750)],
         label: None,
+        range_degrees: None,
+        slew: None,
     };
     let motor3 = MotorConfig {
         pin: 7,
         period: Duration::new(1, 0),
         range: [Duration::from_millis(500), Duration::from_millis(750)],
         label: None,
+        range_degrees: None,
+        slew: None,
     };
     let motor4 = MotorConfig {
         pin: 8,
         period: Duration::new(1, 0),
         range: [Duration::from_millis(500), Duration::from_millis(750)],
         label: None,
+        range_degrees: None,
+        slew: None,
     };
     let motors = vec![motor1, motor2, motor3, motor4];
     let config = Config {
         motors,
-        pump,
+        waste_motor: 0,
+        pumps: vec![pump],
         admins: vec![],
+        rescue_buffer: None,
+        ambient: None,
+        interlocks: vec![],
+        soak: Duration::new(0, 0),
+        volume: None,
+        rate: None,
+        drain_rate: None,
+        estop_pin: None,
+        history: None,
+        state_file: None,
+        settle_delay: None,
+        line_clear_delay: None,
+        mail: None,
+        webhook_url: None,
+        auth: None,
+        server: None,
+        motor_retries: None,
+        halt_retries: None,
+        halt_backoff: None,
+        max_step_duration: None,
+        simulate: false,
     };
 
     let step1 = Step::Perfuse(0, Some(Duration::new(5, 0)));
@@ -49,7 +81,7 @@ fn main() {
     let step3 = Step::Perfuse(3, Some(Duration::new(3, 0)));
     let step4 = Step::Perfuse(2, None);
     let steps = vec![step1, step2, step3, step4];
-    let proto = Protocol { steps };
+    let proto = Protocol::from(steps);
 
     let system = System::new("pause");
 
@@ -59,6 +91,6 @@ fn main() {
         let tui = Box::new(Tui {});
         coord.do_send(CoordMessage::Subscribe(tui));
     }
-    coord.do_send(CoordMessage::Start(proto, None));
+    coord.do_send(CoordMessage::Start(proto, None, HashMap::new()));
     system.run();
 }
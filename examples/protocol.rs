@@ -1,7 +1,10 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::time::Duration;
 
-use deoxy::{actix::*, Config, CoordMessage, Coordinator, MotorConfig, Protocol, PumpConfig, Step};
+use deoxy::{
+    actix::*, Config, CoordMessage, Coordinator, MotorConfig, ProtocolBuilder, PumpConfig,
+};
 
 macro_rules! motor {
     ($pin:expr) => {
@@ -10,38 +13,56 @@ macro_rules! motor {
             period: Duration::from_millis(50),
             pin: $pin,
             range: [Duration::from_millis(1), Duration::from_millis(100)],
+            range_degrees: None,
+            slew: None,
         }
     };
 }
 
-macro_rules! secs {
-    ($s:expr) => {
-        Some(Duration::new($s, 0))
-    };
-}
-
 fn main() -> Result<(), Box<dyn Error>> {
     pretty_env_logger::init();
     let config = Config {
-        pump: PumpConfig {
+        pumps: vec![PumpConfig {
             pins: [24, 25, 5, 6],
             invert: false,
-        },
+            period: None,
+            dead_time: None,
+        }],
         motors: vec![motor!(4), motor!(27), motor!(21), motor!(13)],
+        waste_motor: 0,
         admins: vec![],
+        rescue_buffer: None,
+        ambient: None,
+        interlocks: vec![],
+        soak: Duration::new(0, 0),
+        volume: None,
+        rate: None,
+        drain_rate: None,
+        estop_pin: None,
+        history: None,
+        state_file: None,
+        settle_delay: None,
+        line_clear_delay: None,
+        mail: None,
+        webhook_url: None,
+        auth: None,
+        server: None,
+        motor_retries: None,
+        halt_retries: None,
+        halt_backoff: None,
+        max_step_duration: None,
+        simulate: false,
     };
-    let proto = Protocol {
-        steps: vec![
-            Step::Perfuse(0, secs!(5)),
-            Step::Perfuse(1, secs!(10)),
-            Step::Perfuse(2, secs!(5)),
-            Step::Perfuse(3, None),
-        ],
-    };
+    let proto = ProtocolBuilder::new()
+        .perfuse(0, Duration::new(5, 0))
+        .perfuse(1, Duration::new(10, 0))
+        .perfuse(2, Duration::new(5, 0))
+        .bath(3)
+        .build()?;
     let coord = Coordinator::try_new(config)?;
     let system = System::new("deoxy-protocol-example");
     let addr = coord.start();
-    addr.do_send(CoordMessage::Start(proto, None));
+    addr.do_send(CoordMessage::Start(proto, None, HashMap::new()));
     system.run();
     Ok(())
 }
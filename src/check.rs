@@ -0,0 +1,121 @@
+//! Checking protocol files without running them.
+use crate::{Protocol, ValidateProtocolError};
+
+use std::{fmt, fs, io, path::Path, time::Duration};
+
+/// What a valid protocol file would run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Checked {
+    /// The number of steps, with repetitions expanded.
+    pub steps: usize,
+    /// The estimated runtime, if it can be known in advance.
+    pub duration: Option<Duration>,
+}
+
+impl fmt::Display for Checked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let plural = if self.steps == 1 { "" } else { "s" };
+        write!(f, "Protocol is valid: {} step{}", self.steps, plural)?;
+        match self.duration {
+            Some(duration) => write!(
+                f,
+                ", taking about {}",
+                humantime::format_duration(Duration::from_secs(duration.as_secs()))
+            ),
+            None => write!(f, ", waiting on the operator at least once"),
+        }
+    }
+}
+
+/// Protocol file checking error type.
+#[derive(Debug)]
+pub enum CheckError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file is not a protocol.
+    Json(serde_json::Error),
+    /// The protocol can't be run.
+    Invalid(ValidateProtocolError),
+}
+
+impl From<io::Error> for CheckError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CheckError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl From<ValidateProtocolError> for CheckError {
+    fn from(err: ValidateProtocolError) -> Self {
+        Self::Invalid(err)
+    }
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Could not read protocol: {}", err),
+            Self::Json(err) => write!(f, "Not a protocol: {}", err),
+            Self::Invalid(err) => write!(f, "Invalid protocol: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+/// Reads the protocol (as JSON) from the given file and checks that it could be run.
+pub fn check(path: impl AsRef<Path>) -> Result<Checked, CheckError> {
+    let protocol: Protocol = serde_json::from_str(&fs::read_to_string(path)?)?;
+    // Converting to a program validates the protocol.
+    protocol.as_program()?;
+    Ok(Checked {
+        steps: protocol.expand().len(),
+        duration: protocol.duration(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    /// Writes the given contents to a temporary file and checks it.
+    fn check_str(contents: &str) -> Result<Checked, CheckError> {
+        let path = temp_dir().join(format!("deoxy-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, contents).unwrap();
+        let checked = check(&path);
+        fs::remove_file(&path).unwrap();
+        checked
+    }
+
+    #[test]
+    fn valid_file() {
+        let checked =
+            check_str(r#"[{ "perfuse": [0, "5min"] }, { "perfuse": [1, null] }]"#).unwrap();
+        assert_eq!(checked.steps, 2);
+        assert!(checked.duration.is_some());
+        assert!(checked
+            .to_string()
+            .starts_with("Protocol is valid: 2 steps"));
+    }
+
+    #[test]
+    fn invalid_file() {
+        let err = check_str(r#"[{ "perfuse": [0, "5min"] }]"#).unwrap_err();
+        assert!(matches!(
+            err,
+            CheckError::Invalid(ValidateProtocolError::Last { index: 0, .. })
+        ));
+        assert!(err.to_string().contains("step 1"));
+        assert!(matches!(check_str("{"), Err(CheckError::Json(_))));
+        assert!(matches!(
+            check(temp_dir().join("deoxy-missing.json")),
+            Err(CheckError::Io(_))
+        ));
+    }
+}
@@ -1,10 +1,19 @@
 //! Communication utilities.
 use crate::actix::*;
+#[cfg(feature = "use_serde")]
+use crate::webhook::{Endpoint, Webhook};
 use crate::{
-    mail, Action, Config, Motor, MotorId, MotorMessage, PinError, Program, Protocol, Pump,
-    PumpMessage, Step, ValidateProtocolError,
+    buffer_label,
+    mail::{self, Deliver, Email, JobSummary, Mailer},
+    motor::{self, Valve},
+    sensor::{self, Reading, Sensor},
+    Action, Config, ConfigError, In, Input, MailConfig, Motor, MotorFailure, MotorId, MotorMessage,
+    Notification, PinError, Program, Protocol, Pump, PumpId, PumpMessage, SensorConfig, Step,
+    ValidateProtocolError, DEFAULT_RATE, DEFAULT_VOLUME,
 };
 
+use actix_web::actix::MessageResult;
+use futures::Future;
 use lazy_static::lazy_static;
 use uom::si::f64::*;
 use uom::si::time::second;
@@ -12,25 +21,59 @@ use uom::si::volume::milliliter;
 use uom::si::volume_rate::milliliter_per_second;
 use uuid::Uuid;
 
-use std::{fmt, ops::Index, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt, mem,
+    ops::Index,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+#[cfg(feature = "use_serde")]
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+/// Writes a file by way of a temporary file beside it, so that it's never left half-written.
+#[cfg(feature = "use_serde")]
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".tmp");
+    let temp = path.with_file_name(name);
+    let mut file = fs::File::create(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+}
+
+/// Converts a `uom` time into a `Duration`.
+fn as_duration(time: Time) -> Duration {
+    let secs = time.get::<second>();
+    let nanos = ((secs - secs.floor()) * 1.0_E9).floor() as u32;
+    let secs = secs.floor() as u64;
+    Duration::new(secs, nanos)
+}
 
 lazy_static! {
-    static ref VOLUME: Volume = Volume::new::<milliliter>(500.0);
-    static ref RATE: VolumeRate = VolumeRate::new::<milliliter_per_second>(3.75);
-    static ref TIME: Time = *VOLUME / *RATE;
-    static ref DURATION: Duration = {
-        let secs = TIME.get::<second>();
-        let nanos = ((secs - secs.floor()) * 1.0_E9).floor() as u32;
-        let secs = secs.floor() as u64;
-        Duration::new(secs, nanos)
-    };
     // Motor delay after motor motion before the pump starts
     static ref PUMP_DELAY: Duration = Duration::new(2, 0);
+    // How often the emergency stop button is checked
+    static ref ESTOP_INTERVAL: Duration = Duration::from_millis(50);
 }
 
 type Result<T> = std::result::Result<T, Error>;
 type CoordContext = Context<Coordinator>;
 
+/// Logs on behalf of the coordinator's current job, so structured loggers can tell which job (and
+/// state) an event belongs to.
+macro_rules! job_log {
+    ($coord:expr, $level:ident, $($arg:tt)+) => {
+        crate::logging::scoped($coord.state.uuid, $coord.state.status, || log::$level!($($arg)+))
+    };
+}
+
 /// **Expand for important information.**
 ///
 /// Represents an error encountered by the coordinator.
@@ -54,6 +97,24 @@ pub enum Error {
     Busy,
     /// A pin-related initialization error occured.
     Pin(PinError),
+    /// The coordinator's devices are not ready (they were never initialized).
+    NotReady,
+    /// A valve was to be opened while a valve interlocked with it was open.
+    ///
+    /// The first parameter is the valve to be opened; the second is the open partner.
+    Interlock(MotorId, MotorId),
+    /// We were asked to cancel the queued job, but no job was queued.
+    NothingQueued,
+    /// The configuration is invalid.
+    Config(ConfigError),
+    /// The protocol uses the given buffers, which have no motors.
+    UnknownBuffers(Vec<MotorId>),
+    /// The protocol uses the given pumps, which aren't configured.
+    UnknownPumps(Vec<PumpId>),
+    /// The pump could not be sent a message (its actor has stopped).
+    Unreachable,
+    /// The emergency stop button is pressed, so no job may be started.
+    EmergencyStop,
 }
 
 impl From<ValidateProtocolError> for Error {
@@ -62,6 +123,12 @@ impl From<ValidateProtocolError> for Error {
     }
 }
 
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Self::Config(err)
+    }
+}
+
 impl From<PinError> for Error {
     fn from(err: PinError) -> Self {
         Self::Pin(err)
@@ -70,7 +137,10 @@ impl From<PinError> for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Coordinator error: {:?}", self)
+        match self {
+            Self::ProtocolConversion(err) => write!(f, "Invalid protocol: {}", err),
+            _ => write!(f, "Coordinator error: {:?}", self),
+        }
     }
 }
 
@@ -81,6 +151,12 @@ impl std::error::Error for Error {}
 pub enum Message {
     /// The user has instructed us to move on to the next step.
     Continue,
+    /// The user has asked us to pause the job where it is, as to check on the sample.
+    ///
+    /// The pump is stopped and the action in progress is held until [`Continue`](#variant.Continue)
+    /// is given, when it picks up where it left off (with the pump running as before). This is
+    /// ignored unless the job is running.
+    Pause,
     /// We have been asked to immediately stop the program.
     Halt,
     /// We have been asked to stop the program after the next step.
@@ -93,10 +169,48 @@ pub enum Message {
     /// The user has instructed us to start a new protocol.
     ///
     /// If the second parameter is specified, it is used as the label for the job; otherwise, one
-    /// is generated.
-    Start(Protocol, Option<Uuid>),
+    /// is generated. The third describes the job (such as the sample's name and the operator), so
+    /// that it can be found later; it's reported along with the job, but never affects how it
+    /// runs.
+    Start(Protocol, Option<Uuid>, HashMap<String, String>),
+    /// The user has asked us to start the given protocol once the current job finishes.
+    ///
+    /// Only one job can be queued at a time; queueing another replaces it. The second parameter
+    /// and third are used as the job label and metadata, as with [`Start`](#variant.Start).
+    Queue(Protocol, Option<Uuid>, HashMap<String, String>),
+    /// The user has asked us to cancel the queued job, leaving the current job untouched.
+    CancelQueued,
+    /// The user has asked us to start the given protocol at the given time.
+    ///
+    /// The protocol is checked immediately, and started as with [`Start`](#variant.Start) once the
+    /// time comes. Only one start can be scheduled at a time; scheduling another replaces it, and
+    /// a [`Halt`](#variant.Halt) before the start time cancels it.
+    ScheduleStart(Protocol, Option<Uuid>, SystemTime),
+    /// The user has asked us to skip the step we're waiting on.
+    ///
+    /// Any waits immediately following it are skipped as well, so execution moves on to the next
+    /// step that actually does something. This is ignored unless the coordinator is waiting.
+    Skip,
+    /// Moves the given valve for servicing the manifold (such as flushing a line), outside of any
+    /// job.
+    ///
+    /// The valve is opened (or closed) for the given duration and then closed; the pump isn't
+    /// run. This is refused while a job is active, or while another valve is being jogged.
+    Jog {
+        /// The buffer valve to move.
+        motor: MotorId,
+        /// Whether to open the valve (rather than close it).
+        open: bool,
+        /// How long to leave the valve before closing it.
+        duration: Duration,
+    },
     /// Used to subscribe to coordinator updates.
     Subscribe(Box<dyn Update>),
+    /// Parks the machine and stops the actix system, as when the process is asked to exit.
+    ///
+    /// The pump is stopped and all valves are closed; once they've settled, the system is
+    /// stopped. The job's progress is left alone, so it can be resumed from the state file.
+    Shutdown,
 }
 
 impl ActixMessage for Message {
@@ -121,6 +235,232 @@ pub enum State {
     Running,
 }
 
+/// Why a job was halted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "use_serde", serde(rename_all = "lowercase"))]
+pub enum HaltReason {
+    /// Someone told the coordinator to halt.
+    Operator,
+    /// The program could not be advanced; the error is described.
+    Error(String),
+    /// The emergency stop button was pressed.
+    EmergencyStop,
+    /// A motor could not be moved.
+    MotorFault,
+}
+
+impl fmt::Display for HaltReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Operator => write!(f, "aborted manually"),
+            Self::Error(description) => write!(f, "aborted due to an error: {}", description),
+            Self::EmergencyStop => write!(f, "aborted by the emergency stop button"),
+            Self::MotorFault => write!(f, "aborted because a motor could not be moved"),
+        }
+    }
+}
+
+/// Halts the coordinator for the given reason.
+///
+/// This is how the coordinator halts itself; [`Message::Halt`](enum.Message.html#variant.Halt)
+/// is the same, on the operator's behalf.
+#[derive(Clone, Debug)]
+pub(crate) struct Halt(pub(crate) HaltReason);
+
+impl ActixMessage for Halt {
+    type Result = Result<()>;
+}
+
+/// Requests a snapshot of the coordinator's current (or most recent) job.
+#[derive(Clone, Copy, Debug)]
+pub struct GetSnapshot;
+
+impl ActixMessage for GetSnapshot {
+    type Result = Snapshot;
+}
+
+/// Requests the [plan](struct.Plan.html) of the given protocol, were it to start at the given
+/// time.
+#[derive(Clone, Debug)]
+pub struct Project(pub Protocol, pub SystemTime);
+
+impl ActixMessage for Project {
+    type Result = Result<Plan>;
+}
+
+/// Requests the records of recently-ended jobs, newest first.
+#[derive(Clone, Copy, Debug)]
+pub struct GetHistory;
+
+impl ActixMessage for GetHistory {
+    type Result = Vec<JobRecord>;
+}
+
+/// Requests the metrics of the given job, if it's running or was recently run.
+#[derive(Clone, Copy, Debug)]
+pub struct GetMetrics(pub Uuid);
+
+impl ActixMessage for GetMetrics {
+    type Result = Option<Metrics>;
+}
+
+/// Requests the health of the coordinator's devices.
+#[derive(Clone, Copy, Debug)]
+pub struct GetHealth;
+
+impl ActixMessage for GetHealth {
+    type Result = Health;
+}
+
+/// Whether the coordinator's devices were initialized, and can be driven.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct Health {
+    /// Whether the devices have been started and are ready to be driven.
+    pub ready: bool,
+    /// The pins which couldn't be opened.
+    pub failed_pins: Vec<u16>,
+}
+
+impl Health {
+    /// Whether the coordinator is fit to run jobs.
+    pub fn is_healthy(&self) -> bool {
+        self.ready && self.failed_pins.is_empty()
+    }
+}
+
+/// A record of a job that has ended.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct JobRecord {
+    /// The job ID.
+    pub id: Uuid,
+    /// The state the job ended in.
+    pub state: State,
+    /// The actions that were completed.
+    pub completed: Vec<Action>,
+    /// When the job ended.
+    pub ended: SystemTime,
+    /// The metrics accumulated over the job.
+    pub metrics: Metrics,
+    /// The metadata the job was started with.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub metadata: HashMap<String, String>,
+    /// Why the job was halted, if it was.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub halt_reason: Option<HaltReason>,
+}
+
+/// Figures accumulated over the course of a job.
+///
+/// Pump times are counted as each action is started, so a job which is halted partway through an
+/// action is credited with the whole of it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct Metrics {
+    /// When the job started.
+    pub started: Option<SystemTime>,
+    /// How long the pump has run forward (perfusing or priming).
+    #[cfg_attr(feature = "use_serde", serde(with = "deoxy_core::duration"))]
+    pub pump_forward: Duration,
+    /// How long the pump has run backward (draining).
+    #[cfg_attr(feature = "use_serde", serde(with = "deoxy_core::duration"))]
+    pub pump_reverse: Duration,
+    /// How many times each buffer has been perfused.
+    pub perfusions: BTreeMap<MotorId, usize>,
+}
+
+/// A snapshot of the coordinator's current (or most recent) job.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    /// The job ID, if a job has been run.
+    pub uuid: Option<Uuid>,
+    /// The execution state.
+    pub state: State,
+    /// The program being run.
+    pub program: Option<Program>,
+    /// The actions remaining after the current one.
+    pub remaining: Vec<Action>,
+    /// The buffer the sample is in, if known.
+    pub buffer: Option<MotorId>,
+    /// The ambient conditions recorded during the job.
+    pub ambient: Vec<Reading>,
+    /// The estimated completion time of the job, if it's running.
+    pub estimated_completion: Option<SystemTime>,
+    /// Whether the completion estimate is uncertain (because the job waits on the user).
+    pub completion_uncertain: bool,
+    /// How much longer the job will run, if it's running and this can be known.
+    pub remaining_duration: Option<Duration>,
+    /// The labels of the motors (by id), for naming buffers.
+    pub labels: Vec<Option<String>>,
+    /// The metadata the job was started with.
+    pub metadata: HashMap<String, String>,
+    /// Why the job was halted, if it was.
+    pub halt_reason: Option<HaltReason>,
+    /// Each action of the job, with its planned timing and how far the job has gotten with it.
+    pub timeline: Vec<TimedAction>,
+}
+
+/// How far a job has gotten with one of its actions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "use_serde", serde(rename_all = "lowercase"))]
+pub enum Progress {
+    /// The action has been run.
+    Completed,
+    /// The action is being run.
+    Current,
+    /// The action has yet to be run.
+    Remaining,
+}
+
+/// An action of a job, as shown in its [timeline](struct.Snapshot.html#structfield.timeline).
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct TimedAction {
+    /// The action in question.
+    pub action: Action,
+    /// The kind of action (see [`Action::kind`](enum.Action.html#method.kind)).
+    pub kind: String,
+    /// The label of the buffer the action uses, if it uses one.
+    pub buffer: Option<String>,
+    /// How long the action is planned to take, unless it waits on the user.
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, with = "deoxy_core::duration::option")
+    )]
+    pub duration: Option<Duration>,
+    /// How far the job has gotten with the action.
+    pub progress: Progress,
+}
+
+/// The projected timing of a single protocol step.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct Projection {
+    /// The step in question.
+    pub step: Step,
+    /// When the step is projected to start.
+    pub start: SystemTime,
+    /// When the step is projected to end.
+    pub end: SystemTime,
+    /// Whether the step waits on the user, in which case the waits are counted as taking no time.
+    pub uncertain: bool,
+}
+
+/// What running a protocol would take: how long each step would run, and how much of each buffer
+/// would be used.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct Plan {
+    /// The projected timing of each step of the [expanded](struct.Protocol.html#method.expand)
+    /// protocol.
+    pub steps: Vec<Projection>,
+    /// The estimated volume of each buffer used, in milliliters.
+    pub volumes: BTreeMap<MotorId, f64>,
+}
+
 impl Default for State {
     fn default() -> Self {
         Self::Stopped { early: false }
@@ -132,10 +472,14 @@ impl Default for State {
 struct Addresses {
     /// The addresses of each motor.
     motors: Vec<Addr<Motor>>,
-    /// The address of the pump.
-    pump: Addr<Pump>,
+    /// Direct handles to each motor's valve, for closing them all at once.
+    valves: Vec<Valve>,
+    /// The addresses of each pump.
+    pumps: Vec<Addr<Pump>>,
     /// The address of the subscriber entry point.
     subscribers: Addr<Subscribers>,
+    /// The address of the mailer.
+    mailer: Addr<Mailer>,
 }
 
 impl Index<MotorId> for Addresses {
@@ -150,7 +494,7 @@ impl Index<MotorId> for Addresses {
 #[derive(Debug)]
 struct Devices {
     motors: Vec<Motor>,
-    pump: Pump,
+    pumps: Vec<Pump>,
 }
 
 /// Contains program and buffer states.
@@ -164,12 +508,188 @@ pub(crate) struct CoordState {
     pub(crate) current: Option<Action>,
     /// The most recent buffer.
     pub(crate) buffer: Option<MotorId>,
+    /// The pump the program is using.
+    pub(crate) pump: PumpId,
     /// The current status of program execution.
     pub(crate) status: State,
     /// The completed steps of the program.
     pub(crate) completed: Vec<Action>,
     /// The uuid associated with the running (or most recently-completed) job.
     pub(crate) uuid: Option<Uuid>,
+    /// When the running (or most recently-completed) job started.
+    pub(crate) started: Option<SystemTime>,
+    /// The ambient conditions recorded during the job.
+    pub(crate) ambient: Vec<Reading>,
+    /// The protocol (with the job label and metadata) to start once the current job finishes, if
+    /// any.
+    pub(crate) queued: Option<(Protocol, Uuid, HashMap<String, String>)>,
+    /// Records of recently-ended jobs, oldest first.
+    pub(crate) history: VecDeque<JobRecord>,
+    /// The metrics of the running (or most recently-completed) job.
+    pub(crate) metrics: Metrics,
+    /// The metadata the running (or most recently-completed) job was started with.
+    pub(crate) metadata: HashMap<String, String>,
+    /// Why the most recent job was halted, if it was.
+    pub(crate) halt_reason: Option<HaltReason>,
+}
+
+impl CoordState {
+    /// Moves on to the next action of the program, returning it along with the progress to
+    /// publish.
+    ///
+    /// If the program has run out, the job is stopped and `None` is returned. The action isn't
+    /// considered begun until it's been [entered](#method.enter).
+    fn take_next(&mut self) -> Option<(Action, StatusMessage)> {
+        if self.remaining.is_empty() {
+            self.status = State::Stopped { early: false };
+            self.current = None;
+            return None;
+        }
+        self.status = State::Running;
+        let action = self.remaining.remove(0);
+        let completed = self.completed.len();
+        let total = match &self.program {
+            Some(program) => program.len(),
+            None => completed + self.remaining.len() + 1,
+        };
+        let progress = StatusMessage::Advanced {
+            completed,
+            total,
+            action: action.clone(),
+        };
+        Some((action, progress))
+    }
+    /// Makes the state changes that come with beginning the given action, apart from anything
+    /// the devices have to do.
+    fn enter(&mut self, action: &Action) {
+        match *action {
+            Action::Perfuse(buffer) => self.buffer = Some(buffer),
+            Action::Hail => self.status = State::Waiting,
+            Action::Finish => {
+                self.buffer = None;
+                self.status = State::Stopped { early: false };
+            }
+            Action::SelectPump(pump) => self.pump = pump,
+            _ => {}
+        }
+    }
+    /// Records that the given action has begun.
+    fn record(&mut self, action: Action) {
+        self.completed.push(action.clone());
+        // Once the job has finished, there's nothing in progress.
+        self.current = if action == Action::Finish {
+            None
+        } else {
+            Some(action)
+        };
+    }
+    /// Clears the remaining program queue after the next perfusion.
+    ///
+    /// Notifications left dangling at the cut (such as the prompt of a `PerfusePrompt` that will
+    /// no longer be waited on) are dropped, and the job is then finished as usual.
+    fn clear(&mut self) -> Result<()> {
+        let remaining = &mut self.remaining;
+        if let Some(index) = remaining.iter().position(Action::is_disjoint) {
+            // Vec::truncate keeps n elements, but we don't want to keep the element at index.
+            remaining.truncate(index);
+            while let Some(Action::Notify(_)) = remaining.last() {
+                remaining.pop();
+            }
+            remaining.push(Action::Finish);
+        }
+        self.program = None;
+        Ok(())
+    }
+    /// Stop the program after the current step.
+    ///
+    /// If an end buffer is given, the current buffer will be replaced with that one before
+    /// stopping (if necessary).
+    fn stop<I>(&mut self, buffer: I) -> Result<()>
+    where
+        I: Into<Option<MotorId>>,
+    {
+        match buffer.into() {
+            None => self.clear()?,
+            Some(target) => {
+                if let Some(current) = self.buffer {
+                    if current == target {
+                        // We're already in the target buffer; we don't need to do much else.
+                        self.clear()?;
+                    } else {
+                        let program =
+                            Protocol::with_step(Step::Perfuse(target, None)).as_program()?;
+                        self.program = Some(program.clone());
+                        self.remaining = program.into();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Marks the job as aborted.
+    fn halt(&mut self) {
+        self.status = State::Stopped { early: true };
+        self.buffer = None;
+        // We didn't finish the last step, so remove it from the list
+        self.completed.pop();
+    }
+}
+
+/// The parts of the coordinator state which are saved so that an interrupted job can be resumed.
+#[cfg(feature = "use_serde")]
+#[derive(Debug, Deserialize, Serialize)]
+struct Saved {
+    program: Option<Program>,
+    remaining: Vec<Action>,
+    completed: Vec<Action>,
+    current: Option<Action>,
+    buffer: Option<MotorId>,
+    #[serde(default)]
+    pump: PumpId,
+    uuid: Option<Uuid>,
+    status: State,
+    #[serde(default)]
+    metadata: HashMap<String, String>,
+}
+
+/// Periodically records ambient conditions while a job runs.
+#[derive(Debug)]
+struct Ambient {
+    /// The sensor to sample.
+    sensor: Box<dyn Sensor>,
+    /// How often to sample the sensor.
+    interval: Duration,
+    /// The handle to the sampling loop, while it's running.
+    handle: Option<SpawnHandle>,
+}
+
+/// Watches a physical emergency stop button.
+#[derive(Debug)]
+struct EStop {
+    /// The input the button is connected to (high when pressed).
+    input: Input,
+    /// Whether the button was pressed when last checked.
+    pressed: bool,
+}
+
+/// The rest of an in-flight action, which runs once its delay has elapsed.
+struct Continuation(Box<dyn FnOnce(&mut Coordinator, &mut CoordContext)>);
+
+impl fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Continuation")
+    }
+}
+
+/// Where a job was when the user paused it.
+#[derive(Debug)]
+struct Paused {
+    /// How long the interrupted action had left before continuing.
+    remaining: Duration,
+    /// What the interrupted action was to do next.
+    then: Continuation,
+    /// The direction the pump was running in (forward if `true`), if it was running.
+    pumping: Option<bool>,
 }
 
 /// Contains all the actual logic for controlling the system based on a specified program.
@@ -188,31 +708,390 @@ pub struct Coordinator {
     pub(crate) state: CoordState,
     /// The contact emails of the administrators of this machine.
     admins: Vec<String>,
+    /// The labels of the motors (by id), for naming buffers.
+    labels: Vec<Option<String>>,
+    /// How notifications are emailed to the administrators.
+    mail: MailConfig,
+    /// Where status updates are posted, if anywhere.
+    #[cfg(feature = "use_serde")]
+    webhook: Option<Endpoint>,
+    /// The buffer used to keep the sample wet when aborting mid-exchange, if any.
+    rescue_buffer: Option<MotorId>,
+    /// Whether the sample is being rescued after a job was aborted (see `hcf`).
+    rescuing: bool,
+    /// Counts the continuations which have been scheduled, so that one which has since been
+    /// cancelled can tell when its time comes.
+    continuations: u64,
+    /// The number of the next scheduled step of the program, if one is pending.
+    pending: Option<u64>,
+    /// The pending step itself, along with when it's due.
+    next: Option<(Instant, Continuation)>,
+    /// Where the job was interrupted, if the user has paused it.
+    paused: Option<Paused>,
+    /// The direction the current pump is running in (forward if `true`), if it's running.
+    pumping: Option<bool>,
+    /// Counts the starts which have been scheduled or begun, so that one which has since been
+    /// cancelled can tell when its time comes.
+    starts: u64,
+    /// The number of the scheduled start of a job, if there is one.
+    scheduled: Option<u64>,
+    /// The number of the start of a job which is waiting for the valves to settle, if any.
+    starting: Option<u64>,
+    /// The handle to the closing of a jogged valve, if one is being jogged.
+    jogging: Option<SpawnHandle>,
+    /// The ambient sensor, if one is configured.
+    ambient: Option<Ambient>,
+    /// Pairs of valves which must never be open simultaneously.
+    interlocks: Vec<(MotorId, MotorId)>,
+    /// The (buffer) valves which are currently open.
+    opened: HashSet<MotorId>,
+    /// The motor (by index) controlling the waste valve.
+    waste_motor: usize,
+    /// How long each buffer sits after filling before it is drained.
+    soak: Duration,
+    /// The volume of buffer needed to fill the chamber.
+    volume: Volume,
+    /// How long the pump runs to fill the chamber.
+    fill_time: Duration,
+    /// How long the pump runs to drain the chamber.
+    drain_time: Duration,
+    /// The speed (duty cycle) at which the pump drains the chamber.
+    drain_speed: f32,
+    /// The emergency stop button, if one is configured.
+    estop: Option<EStop>,
+    /// How many ended jobs to keep records of.
+    history_limit: usize,
+    /// The file job progress is saved to, if any.
+    state_file: Option<PathBuf>,
+    /// Whether an interrupted job was loaded from the state file and should resume on start.
+    restored: bool,
+    /// How long valves are given to move before their motors are stopped.
+    settle_delay: Duration,
+    /// How long the waste line is cleared for after filling.
+    line_clear_delay: Duration,
+    /// How many more times halting is attempted if it fails.
+    halt_retries: u32,
+    /// How long to wait between attempts to halt.
+    halt_backoff: Duration,
+    /// The longest any perfusion, prompt, or wait may last, if there's a limit.
+    max_step_duration: Option<Duration>,
+    /// Whether device messages are withheld, so jobs run without moving anything.
+    simulate: bool,
+    /// How many pumps are configured.
+    pump_count: usize,
+    /// Subscribers added before the coordinator was started, to be handed over once it is.
+    pending_subscribers: Vec<Box<dyn Update>>,
+    /// The pins which couldn't be opened when the coordinator was initialized.
+    failed_pins: Vec<u16>,
 }
 
 impl Coordinator {
     /// Initializes a coordinator and prepares it for running.
+    ///
+    /// Pins which can't be opened don't cause an error; they're logged and kept (see
+    /// [`health`](#method.health)), and the coordinator is left without devices, so it can
+    /// report on itself but won't start jobs.
     pub fn try_new(config: Config) -> Result<Self> {
-        let mut pump = Pump::try_new(config.pump.pins)?;
-        pump.invert = config.pump.invert;
-        let motors = config
-            .motors
-            .into_iter()
-            .map(|spec| {
-                // TODO: Implement labels
-                let period = spec.period;
-                let range = spec.range[0]..=spec.range[1];
-                let pin = spec.pin;
-                Motor::try_new(period, range, pin)
-            })
-            .collect::<std::result::Result<Vec<_>, _>>()?;
-        let devices = Some(Devices { motors, pump });
-        Ok(Self {
+        config.validate()?;
+        let labels = config.labels();
+        let mut failed_pins = vec![];
+        let mut pumps = vec![];
+        for spec in &config.pumps {
+            match Pump::try_new(spec.pins) {
+                Ok(mut pump) => {
+                    pump.invert = spec.invert;
+                    if let Some(period) = spec.period {
+                        pump.period = period;
+                    }
+                    if let Some(dead_time) = spec.dead_time {
+                        pump.dead_time = dead_time;
+                    }
+                    pumps.push(pump);
+                }
+                Err(err) => {
+                    // The pump doesn't say which of its pins failed, so count them all.
+                    log::error!("Could not open pump pins {:?}: {}", spec.pins, err);
+                    failed_pins.extend_from_slice(&spec.pins);
+                }
+            }
+        }
+        let retries = config.motor_retries;
+        let mut motors = vec![];
+        for spec in config.motors {
+            let range = spec.range[0]..=spec.range[1];
+            match Motor::try_new(spec.period, range, spec.pin) {
+                Ok(mut motor) => {
+                    if let Some(degrees) = spec.range_degrees {
+                        motor.range_degrees = degrees;
+                    }
+                    if let Some(retries) = retries {
+                        motor.retries = retries;
+                    }
+                    motor.slew = spec.slew;
+                    motors.push(motor);
+                }
+                Err(err) => {
+                    log::error!("Could not open motor pin {}: {}", spec.pin, err);
+                    failed_pins.push(spec.pin);
+                }
+            }
+        }
+        let ambient = config.ambient.map(|spec| {
+            let sensor: Box<dyn Sensor> = match spec.sensor {
+                SensorConfig::Fixed {
+                    temperature,
+                    humidity,
+                } => Box::new(sensor::Fixed {
+                    temperature,
+                    humidity,
+                }),
+            };
+            Ambient {
+                sensor,
+                interval: spec.interval,
+                handle: None,
+            }
+        });
+        let volume = config
+            .volume
+            .unwrap_or_else(|| Volume::new::<milliliter>(DEFAULT_VOLUME));
+        let rate = config
+            .rate
+            .unwrap_or_else(|| VolumeRate::new::<milliliter_per_second>(DEFAULT_RATE));
+        let drain_rate = config.drain_rate.unwrap_or(rate);
+        let estop = config.estop_pin.and_then(|pin| match Input::try_new(pin) {
+            Ok(input) => Some(EStop {
+                input,
+                pressed: false,
+            }),
+            Err(err) => {
+                log::error!("Could not open emergency stop pin {}: {}", pin, err);
+                failed_pins.push(pin);
+                None
+            }
+        });
+        let devices = if failed_pins.is_empty() {
+            Some(Devices { motors, pumps })
+        } else {
+            None
+        };
+        let mut coord = Self {
             devices,
             addresses: None,
             state: CoordState::default(),
             admins: config.admins,
-        })
+            labels,
+            mail: config.mail.unwrap_or_default(),
+            #[cfg(feature = "use_serde")]
+            webhook: config
+                .webhook_url
+                .as_ref()
+                .and_then(|url| Endpoint::parse(url)),
+            rescue_buffer: config.rescue_buffer,
+            rescuing: false,
+            continuations: 0,
+            pending: None,
+            next: None,
+            paused: None,
+            pumping: None,
+            starts: 0,
+            scheduled: None,
+            starting: None,
+            jogging: None,
+            ambient,
+            interlocks: config.interlocks,
+            opened: HashSet::new(),
+            waste_motor: config.waste_motor,
+            soak: config.soak,
+            volume,
+            fill_time: as_duration(volume / rate),
+            // Drain for twice as long as it would take to empty, to be sure it's empty.
+            drain_time: as_duration(volume / drain_rate) * 2,
+            drain_speed: (drain_rate / rate).value as f32,
+            estop,
+            history_limit: config.history.unwrap_or(50),
+            state_file: config.state_file,
+            restored: false,
+            settle_delay: config.settle_delay.unwrap_or_else(|| Duration::new(5, 0)),
+            line_clear_delay: config
+                .line_clear_delay
+                .unwrap_or_else(|| Duration::new(10, 0)),
+            halt_retries: config.halt_retries.unwrap_or(5),
+            halt_backoff: config
+                .halt_backoff
+                .unwrap_or_else(|| Duration::from_millis(200)),
+            max_step_duration: config.max_step_duration,
+            simulate: config.simulate,
+            pump_count: config.pumps.len(),
+            pending_subscribers: vec![],
+            failed_pins,
+        };
+        coord.restore();
+        Ok(coord)
+    }
+    /// Saves the progress of the current job to the state file, if one is configured.
+    #[cfg(feature = "use_serde")]
+    fn persist(&self) {
+        if let Some(path) = &self.state_file {
+            let saved = Saved {
+                program: self.state.program.clone(),
+                remaining: self.state.remaining.clone(),
+                completed: self.state.completed.clone(),
+                current: self.state.current.clone(),
+                buffer: self.state.buffer,
+                pump: self.state.pump,
+                uuid: self.state.uuid,
+                status: self.state.status,
+                metadata: self.state.metadata.clone(),
+            };
+            let result = serde_json::to_string(&saved)
+                .map_err(io::Error::from)
+                .and_then(|json| write_atomically(path, json.as_bytes()));
+            if let Err(err) = result {
+                log::warn!("Could not save state to {}: {}", path.display(), err);
+            }
+        }
+    }
+    #[cfg(not(feature = "use_serde"))]
+    fn persist(&self) {}
+    /// Loads the job saved in the state file, if it was interrupted, so that it resumes once the
+    /// coordinator starts.
+    #[cfg(feature = "use_serde")]
+    fn restore(&mut self) {
+        let path = match &self.state_file {
+            Some(path) if path.exists() => path,
+            _ => return,
+        };
+        let saved = fs::read_to_string(path)
+            .and_then(|json| serde_json::from_str::<Saved>(&json).map_err(io::Error::from));
+        let saved = match saved {
+            Ok(saved) => saved,
+            Err(err) => {
+                log::warn!("Could not load state from {}: {}", path.display(), err);
+                return;
+            }
+        };
+        if let State::Stopped { .. } = saved.status {
+            return;
+        }
+        let mut remaining = saved.remaining;
+        let mut completed = saved.completed;
+        // The current action was interrupted, so it must be run again.
+        if let Some(current) = saved.current {
+            completed.pop();
+            remaining.insert(0, current);
+        }
+        self.state.program = saved.program;
+        self.state.remaining = remaining;
+        self.state.completed = completed;
+        self.state.buffer = saved.buffer;
+        self.state.pump = saved.pump;
+        self.state.uuid = saved.uuid;
+        self.state.status = saved.status;
+        self.state.metadata = saved.metadata;
+        self.restored = true;
+    }
+    #[cfg(not(feature = "use_serde"))]
+    fn restore(&mut self) {}
+    /// Picks up the job loaded from the state file.
+    fn resume_restored(&mut self, context: &mut CoordContext) {
+        log::warn!("Resuming interrupted job.");
+        self.restored = false;
+        self.stop_pumps();
+        self.close_all(context);
+        context.run_later(self.settle_delay, |coord, context| {
+            coord.start_sampling(context);
+            coord.try_advance(context);
+        });
+    }
+    /// The time the coordinator will spend running the given action, if it can be known in
+    /// advance.
+    ///
+    /// Waiting on the user (`Hail`) cannot be bounded, so `None` is returned for it.
+    fn action_duration(&self, action: &Action) -> Option<Duration> {
+        match action {
+            Action::Perfuse(_) => Some(*PUMP_DELAY + self.fill_time + self.line_clear_delay),
+            Action::Prime(_, duration)
+            | Action::Forward(_, duration)
+            | Action::Backward(_, duration) => Some(*PUMP_DELAY + *duration),
+            Action::Sleep(duration) => Some(*duration),
+            Action::Hail => None,
+            Action::Drain => Some(*PUMP_DELAY + self.drain_time),
+            Action::Finish | Action::Notify(_) | Action::SelectPump(_) => Some(Duration::new(0, 0)),
+        }
+    }
+    /// Checks the emergency stop button, halting if it's pressed while a job is running (or
+    /// about to start, or the sample is being rescued after one was aborted).
+    fn check_estop(&mut self, context: &mut CoordContext) {
+        let running = !self.is_stopped() || self.starting.is_some() || self.rescuing;
+        if let Some(estop) = &mut self.estop {
+            let pressed = match estop.input.is_high() {
+                Ok(pressed) => pressed,
+                Err(err) => {
+                    log::error!(
+                        "Could not read emergency stop button on pin {}: {}",
+                        estop.input.number,
+                        err
+                    );
+                    return;
+                }
+            };
+            let was_pressed = estop.pressed;
+            estop.pressed = pressed;
+            if pressed && !was_pressed {
+                log::warn!("Emergency stop button pressed.");
+            }
+            if pressed && running {
+                log::warn!("Halting, since the emergency stop button is pressed.");
+                context.notify(Halt(HaltReason::EmergencyStop));
+            }
+        }
+    }
+    /// Refuses to start anything while the emergency stop button is pressed.
+    ///
+    /// A button which can't be read is taken to be pressed.
+    fn check_estop_released(&self) -> Result<()> {
+        match self.estop.as_ref().map(|estop| estop.input.is_high()) {
+            None | Some(Ok(false)) => Ok(()),
+            Some(Ok(true)) => Err(Error::EmergencyStop),
+            Some(Err(err)) => {
+                log::error!("Could not read emergency stop button: {}", err);
+                Err(Error::EmergencyStop)
+            }
+        }
+    }
+    /// The ambient conditions recorded during the current (or most recent) job.
+    pub fn ambient(&self) -> &[Reading] {
+        &self.state.ambient
+    }
+    /// Records a single reading from the ambient sensor, if there is one.
+    fn sample_ambient(&mut self) {
+        if let Some(ref mut ambient) = self.ambient {
+            match ambient.sensor.read() {
+                Ok(reading) => self.state.ambient.push(reading),
+                Err(err) => log::warn!("Failed to read ambient sensor: {}", err),
+            }
+        }
+    }
+    /// Starts periodically sampling the ambient sensor, discarding any previous readings.
+    fn start_sampling(&mut self, context: &mut CoordContext) {
+        self.state.ambient.clear();
+        if let Some(ref mut ambient) = self.ambient {
+            let handle = context.run_interval(ambient.interval, |coord, _| coord.sample_ambient());
+            if let Some(old) = ambient.handle.replace(handle) {
+                context.cancel_future(old);
+            }
+        }
+    }
+    /// Stops sampling the ambient sensor.
+    fn stop_sampling(&mut self, context: &mut CoordContext) {
+        if let Some(handle) = self
+            .ambient
+            .as_mut()
+            .and_then(|ambient| ambient.handle.take())
+        {
+            context.cancel_future(handle);
+        }
     }
     /// The in-progress program, if appropriate.
     pub fn program(&self) -> Option<&Program> {
@@ -222,16 +1101,46 @@ impl Coordinator {
     pub fn status(&self) -> State {
         self.state.status
     }
+    /// The addresses to send motor and pump messages to, unless the job is only being simulated.
+    fn hardware(&self) -> Option<&Addresses> {
+        if self.simulate {
+            None
+        } else {
+            self.addresses.as_ref()
+        }
+    }
+    /// The index of the motor controlling the given (buffer) valve.
+    ///
+    /// The buffer valves are numbered in order, skipping over the waste valve.
+    fn motor_index(&self, valve: MotorId) -> usize {
+        if valve < self.waste_motor {
+            valve
+        } else {
+            valve + 1
+        }
+    }
     /// Closes all valves, shutting the waste valve.
-    fn close_all(&self, context: &mut CoordContext) {
-        if let Some(ref addresses) = self.addresses {
-            addresses[0].do_send(MotorMessage::Shut);
-            for addr in addresses.motors.iter().skip(1) {
-                addr.do_send(MotorMessage::Close);
+    ///
+    /// The valves are moved together in one batch, so no intermediate combination of open valves
+    /// is ever driven; if that fails, each motor is asked to close its own valve (with retries).
+    fn close_all(&mut self, context: &mut CoordContext) {
+        self.opened.clear();
+        if let Some(addresses) = self.hardware() {
+            let waste = self.waste_motor;
+            let valves = addresses.valves.iter().enumerate();
+            if let Err(err) = motor::close_valves(valves.map(|(i, valve)| (valve, i == waste))) {
+                log::warn!("Could not close valves together: {}", err);
+                for (index, addr) in addresses.motors.iter().enumerate() {
+                    if index == waste {
+                        addr.do_send(MotorMessage::Shut);
+                    } else {
+                        addr.do_send(MotorMessage::Close);
+                    }
+                }
             }
         }
-        context.run_later(Duration::new(5, 0), move |coord, _| {
-            if let Some(ref addresses) = coord.addresses {
+        context.run_later(self.settle_delay, move |coord, _| {
+            if let Some(addresses) = coord.hardware() {
                 for addr in &addresses.motors {
                     addr.do_send(MotorMessage::Stop);
                 }
@@ -239,200 +1148,834 @@ impl Coordinator {
         });
     }
     fn _close(&self, index: usize, context: &mut CoordContext) {
-        if let Some(ref addresses) = self.addresses {
+        if let Some(addresses) = self.hardware() {
             addresses[index].do_send(MotorMessage::Close);
-            context.run_later(Duration::new(5, 0), move |coord, _| {
-                if let Some(ref addresses) = coord.addresses {
+            context.run_later(self.settle_delay, move |coord, _| {
+                if let Some(addresses) = coord.hardware() {
                     addresses[index].do_send(MotorMessage::Stop);
                 }
             });
         }
     }
-    fn close(&self, valve: usize, context: &mut CoordContext) {
-        let index = valve + 1; // Valve 0 is waste
-        self._close(index, context);
+    fn close(&mut self, valve: usize, context: &mut CoordContext) {
+        self.opened.remove(&valve);
+        self._close(self.motor_index(valve), context);
     }
     fn _open(&self, index: usize, context: &mut CoordContext) {
-        if let Some(ref addresses) = self.addresses {
+        if let Some(addresses) = self.hardware() {
             addresses[index].do_send(MotorMessage::Open);
-            context.run_later(Duration::new(5, 0), move |coord, _| {
-                if let Some(ref addresses) = coord.addresses {
+            context.run_later(self.settle_delay, move |coord, _| {
+                if let Some(addresses) = coord.hardware() {
                     addresses[index].do_send(MotorMessage::Stop);
                 }
             });
         }
     }
-    fn open(&self, valve: usize, context: &mut CoordContext) {
-        let index = valve + 1; // Valve 0 is waste
-        self._open(index, context);
+    /// Ensures that no valve interlocked with the given valve is open.
+    fn check_interlocks(&self, valve: MotorId) -> Result<()> {
+        let partner = self
+            .interlocks
+            .iter()
+            .filter_map(|&(a, b)| {
+                if a == valve {
+                    Some(b)
+                } else if b == valve {
+                    Some(a)
+                } else {
+                    None
+                }
+            })
+            .find(|partner| self.opened.contains(partner));
+        match partner {
+            Some(partner) => Err(Error::Interlock(valve, partner)),
+            None => Ok(()),
+        }
+    }
+    /// Opens the given valve, refusing if an interlocked valve is open.
+    fn open(&mut self, valve: usize, context: &mut CoordContext) -> Result<()> {
+        self.check_interlocks(valve)?;
+        self.opened.insert(valve);
+        self._open(self.motor_index(valve), context);
+        Ok(())
     }
     fn shut_waste(&self, context: &mut CoordContext) {
-        if let Some(ref addresses) = self.addresses {
-            addresses[0].do_send(MotorMessage::Shut);
-            context.run_later(Duration::new(5, 0), move |coord, _| {
-                if let Some(ref addresses) = coord.addresses {
-                    addresses[0].do_send(MotorMessage::Stop);
+        let waste = self.waste_motor;
+        if let Some(addresses) = self.hardware() {
+            addresses[waste].do_send(MotorMessage::Shut);
+            context.run_later(self.settle_delay, move |coord, _| {
+                if let Some(addresses) = coord.hardware() {
+                    addresses[waste].do_send(MotorMessage::Stop);
                 }
             });
         }
     }
     fn open_waste(&self, context: &mut CoordContext) {
-        self._open(0, context);
+        self._open(self.waste_motor, context);
     }
     fn close_waste(&self, context: &mut CoordContext) {
-        self._close(0, context);
+        self._close(self.waste_motor, context);
     }
-    fn perfuse(&self) {
-        if let Some(ref addresses) = self.addresses {
-            addresses.pump.do_send(PumpMessage::Perfuse);
+    /// The address of the given pump, unless the job is only being simulated.
+    fn pump(&self, pump: PumpId) -> Option<&Addr<Pump>> {
+        self.hardware()
+            .and_then(|addresses| addresses.pumps.get(pump))
+    }
+    fn perfuse(&mut self, pump: PumpId) {
+        if let Some(pump) = self.pump(pump) {
+            pump.do_send(PumpMessage::SetSpeed(1.0));
+            pump.do_send(PumpMessage::Perfuse);
         }
+        self.pumping = Some(true);
     }
-    fn drain(&self) {
-        if let Some(ref addresses) = self.addresses {
-            addresses.pump.do_send(PumpMessage::Drain);
+    fn drain(&mut self, pump: PumpId) {
+        if let Some(pump) = self.pump(pump) {
+            pump.do_send(PumpMessage::SetSpeed(self.drain_speed));
+            pump.do_send(PumpMessage::Drain);
         }
+        self.pumping = Some(false);
     }
-    fn stop_pump(&self) {
-        if let Some(ref addresses) = self.addresses {
-            addresses.pump.do_send(PumpMessage::Stop);
+    fn stop_pump(&mut self, pump: PumpId) {
+        if let Some(pump) = self.pump(pump) {
+            pump.do_send(PumpMessage::Stop);
+        }
+        self.pumping = None;
+    }
+    /// Stops every pump.
+    fn stop_pumps(&mut self) {
+        if let Some(addresses) = self.hardware() {
+            for pump in &addresses.pumps {
+                pump.do_send(PumpMessage::Stop);
+            }
+        }
+        self.pumping = None;
+    }
+    /// Stops every pump, failing if a message can't be delivered.
+    fn try_stop_pumps(&mut self) -> Result<()> {
+        if let Some(addresses) = self.hardware() {
+            for pump in &addresses.pumps {
+                pump.try_send(PumpMessage::Stop)
+                    .map_err(|_| Error::Unreachable)?;
+            }
+        }
+        self.pumping = None;
+        Ok(())
+    }
+    /// Schedules the next part of the program to run after the given delay.
+    ///
+    /// Only one such continuation is tracked at a time; aborting cancels it, and pausing sets it
+    /// aside.
+    fn schedule<F>(&mut self, delay: Duration, context: &mut CoordContext, f: F)
+    where
+        F: FnOnce(&mut Self, &mut CoordContext) + 'static,
+    {
+        self.continuations = self.continuations.wrapping_add(1);
+        let continuation = self.continuations;
+        self.next = Some((Instant::now() + delay, Continuation(Box::new(f))));
+        self.pending = Some(continuation);
+        context.run_later(delay, move |coord, context| {
+            coord.run_next(continuation, context)
+        });
+    }
+    /// Runs the pending continuation of the program, now that it's due, unless it has since been
+    /// cancelled.
+    fn run_next(&mut self, continuation: u64, context: &mut CoordContext) {
+        if self.pending != Some(continuation) {
+            return;
+        }
+        self.pending = None;
+        if let Some((_, Continuation(then))) = self.next.take() {
+            then(self, context);
+        }
+    }
+    /// Cancels the pending continuation of the program (or the one set aside by pausing), if any.
+    fn cancel_pending(&mut self) {
+        self.pending = None;
+        self.next = None;
+        self.paused = None;
+    }
+    /// Fills the chamber from the given buffer, clears the line, and then calls `then`.
+    fn fill<F>(&mut self, buffer: MotorId, context: &mut CoordContext, then: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self, &mut CoordContext) + 'static,
+    {
+        self.open(buffer, context)?;
+        self.shut_waste(context);
+        self.schedule(*PUMP_DELAY, context, move |coord, context| {
+            coord.perfuse(coord.state.pump);
+            let fill_time = coord.fill_time;
+            coord.schedule(fill_time, context, move |coord, context| {
+                coord.close(buffer, context);
+                coord.open_waste(context);
+                let line_clear_delay = coord.line_clear_delay;
+                coord.schedule(line_clear_delay, context, move |coord, context| {
+                    coord.stop_pump(coord.state.pump);
+                    coord.close_waste(context);
+                    then(coord, context);
+                });
+            });
+        });
+        Ok(())
+    }
+    /// Runs the given buffer through the lines for the given duration, and then calls `then`.
+    ///
+    /// Unlike filling, this isn't timed by volume, and the line isn't cleared afterward; the
+    /// primed buffer is expected to be drained to waste.
+    fn prime<F>(
+        &mut self,
+        buffer: MotorId,
+        duration: Duration,
+        context: &mut CoordContext,
+        then: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut Self, &mut CoordContext) + 'static,
+    {
+        self.open(buffer, context)?;
+        self.shut_waste(context);
+        self.schedule(*PUMP_DELAY, context, move |coord, context| {
+            coord.perfuse(coord.state.pump);
+            coord.schedule(duration, context, move |coord, context| {
+                coord.stop_pump(coord.state.pump);
+                coord.close(buffer, context);
+                then(coord, context);
+            });
+        });
+        Ok(())
+    }
+    /// Runs the pump in the given direction for the given duration with the given buffer's valve
+    /// open, and then calls `then`.
+    ///
+    /// The valve is left open afterward, so that recirculation can continue; draining closes it.
+    fn pulse<F>(
+        &mut self,
+        buffer: MotorId,
+        forward: bool,
+        duration: Duration,
+        context: &mut CoordContext,
+        then: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut Self, &mut CoordContext) + 'static,
+    {
+        if !self.opened.contains(&buffer) {
+            self.open(buffer, context)?;
         }
+        self.shut_waste(context);
+        self.schedule(*PUMP_DELAY, context, move |coord, context| {
+            if forward {
+                coord.perfuse(coord.state.pump);
+            } else {
+                coord.drain(coord.state.pump);
+            }
+            coord.schedule(duration, context, move |coord, context| {
+                coord.stop_pump(coord.state.pump);
+                then(coord, context);
+            });
+        });
+        Ok(())
     }
     /// Attempts to run the next step of the program, aborting and cleaning up on failure.
     fn try_advance(&mut self, context: &mut CoordContext) {
+        let action = self.state.remaining.first().cloned();
         let result = self.advance(context);
         if let Err(err) = result {
-            // TODO: Notify user
-            log::error!("Aborting due to program advance error: {:?}", err);
-            let mut tries = 0;
-            let mut result = self.hcf();
-            while tries < 5 && result.is_err() {
-                std::thread::sleep(Duration::from_millis(200));
-                result = self.hcf();
-                tries += 1;
+            job_log!(
+                self,
+                error,
+                "Aborting due to program advance error: {:?}",
+                err
+            );
+            let description = match action {
+                Some(action) => format!("Failed to run {:?}: {}", action, err),
+                None => format!("Failed to advance: {}", err),
+            };
+            self.publish(StatusMessage::Errored(description.clone()), context);
+            self.notify(mail::Status::Custom {
+                subject: "Errored",
+                message: &format!(
+                    "The decellularization run has been aborted due to an error. {}",
+                    description
+                ),
+                html: None,
+            });
+            self.retry_hcf(HaltReason::Error(description), 0, context);
+        }
+    }
+    /// Halts, retrying after the configured backoff if that fails.
+    ///
+    /// `tries` is the number of retries already made. Once they're exhausted, the failure is
+    /// published and the admins are told.
+    fn retry_hcf(&mut self, reason: HaltReason, tries: u32, context: &mut CoordContext) {
+        match self.hcf(&reason, context) {
+            Ok(()) => self.publish(StatusMessage::Halted { reason }, context),
+            Err(_) if tries < self.halt_retries => {
+                let backoff = self.halt_backoff;
+                context.run_later(backoff, move |coord, context| {
+                    coord.retry_hcf(reason, tries + 1, context)
+                });
             }
-            if result.is_err() {
-                log::error!("Could not fully stop program; please take caution!");
+            Err(err) => {
+                let description = format!(
+                    "Could not fully stop program after {} attempts: {}",
+                    tries + 1,
+                    err
+                );
+                job_log!(self, error, "{}; please take caution!", description);
+                self.publish(StatusMessage::Errored(description.clone()), context);
+                self.notify(mail::Status::Custom {
+                    subject: "Could not stop",
+                    message: &format!(
+                        "{}. The machine may still be running; please take caution!",
+                        description
+                    ),
+                    html: None,
+                });
             }
         }
     }
     /// Moves to the next step of the program, returning the new current action.
-    fn advance(&mut self, context: &mut CoordContext) -> Result<Option<Action>> {
-        if !self.state.remaining.is_empty() {
-            self.state.status = State::Running;
-            let action = self.state.remaining.remove(0);
+    pub(crate) fn advance(&mut self, context: &mut CoordContext) -> Result<Option<Action>> {
+        if let Some((action, progress)) = self.state.take_next() {
+            job_log!(self, debug, "Advancing to {}.", action);
+            self.publish(progress, context);
+            self.state.enter(&action);
             // Make sure to message something that will call advance again later!
             // Usually this will be try_advance.
             match action.clone() {
                 Action::Perfuse(buffer) => {
-                    self.shut_waste(context);
-                    self.open(buffer, context);
-                    context.run_later(*PUMP_DELAY, move |coord, context| {
-                        coord.perfuse();
-                        context.run_later(*DURATION, move |coord, context| {
-                            coord.close(buffer, context);
-                            coord.open_waste(context);
-                            // Clear the line for ten seconds
-                            context.run_later(Duration::new(10, 0), move |coord, context| {
-                                coord.stop_pump();
-                                coord.close_waste(context);
-                                coord.try_advance(context);
-                            });
-                        });
-                    });
+                    self.fill(buffer, context, Self::try_advance)?;
+                    let metrics = &mut self.state.metrics;
+                    *metrics.perfusions.entry(buffer).or_insert(0) += 1;
+                    metrics.pump_forward += self.fill_time + self.line_clear_delay;
+                }
+                Action::Prime(buffer, duration) => {
+                    self.prime(buffer, duration, context, Self::try_advance)?;
+                    self.state.metrics.pump_forward += duration;
                 }
                 Action::Sleep(duration) => {
-                    context.run_later(duration, Self::try_advance);
+                    self.schedule(duration, context, Self::try_advance);
                 }
                 Action::Hail => {
-                    self.state.status = State::Waiting;
-                    // TODO: Publish for other actions as well
                     self.publish(StatusMessage::Paused, context);
                 }
                 Action::Drain => {
+                    self.state.metrics.pump_reverse += self.drain_time;
+                    // Recirculation leaves its buffer's valve open.
+                    for valve in self.opened.clone() {
+                        self.close(valve, context);
+                    }
                     self.close_waste(context);
-                    context.run_later(*PUMP_DELAY, move |coord, context| {
-                        coord.drain();
-                        context.run_later(*DURATION * 2, |coord, context| {
-                            coord.stop_pump();
+                    self.schedule(*PUMP_DELAY, context, move |coord, context| {
+                        coord.drain(coord.state.pump);
+                        let drain_time = coord.drain_time;
+                        coord.schedule(drain_time, context, |coord, context| {
+                            coord.stop_pump(coord.state.pump);
                             coord.shut_waste(context);
                             coord.try_advance(context);
                         });
                     });
                 }
                 Action::Finish => {
-                    self.stop_pump();
+                    self.stop_pumps();
                     self.close_all(context);
-                    // TODO: Handle error
-                    let _ = mail::notify(&self.admins, mail::Status::Finished);
-                    // TODO: Update coordinator state
+                    self.stop_sampling(context);
+                    let mut completed = self.state.completed.clone();
+                    completed.push(Action::Finish);
+                    self.archive(State::Stopped { early: false }, completed);
+                    self.notify_finished();
                 }
                 Action::Notify(msg) => {
-                    log::trace!("Notifying user (subject: {}).", msg.subject);
-                    // TODO: Handle error
-                    let _ = mail::mail(&self.admins, msg.subject, msg.message);
-                    self.try_advance(context);
+                    job_log!(self, trace, "Notifying user (subject: {}).", msg.subject);
+                    self.publish(StatusMessage::Notified(msg.clone()), context);
+                    let email = mail::compose(&self.mail, &self.admins, msg.subject, msg.message);
+                    self.send_mail(email);
+                }
+                // Entering the action selected the pump.
+                Action::SelectPump(_) => {}
+                Action::Forward(buffer, duration) => {
+                    self.pulse(buffer, true, duration, context, Self::try_advance)?;
+                    self.state.metrics.pump_forward += duration;
+                }
+                Action::Backward(buffer, duration) => {
+                    self.pulse(buffer, false, duration, context, Self::try_advance)?;
+                    self.state.metrics.pump_reverse += duration;
                 }
             }
-            self.state.completed.push(action.clone());
-            self.state.current = Some(action);
-        } else {
-            self.state.status = State::Stopped { early: false };
-            self.state.current = None;
+            let instant = matches!(action, Action::Notify(_) | Action::SelectPump(_));
+            let finished = action == Action::Finish;
+            self.state.record(action);
+            if instant {
+                // Nothing to wait for, so move straight on (once this action is recorded).
+                self.try_advance(context);
+            } else if finished {
+                self.start_queued(context);
+            }
         }
+        self.persist();
         Ok(self.state.current.clone())
     }
-    /// Clears the remaining program queue after the next perfusion.
-    fn clear(&mut self) -> Result<()> {
-        if let Some(index) = self.state.remaining.iter().position(Action::is_disjoint) {
-            // Vec::truncate keeps n elements, but we don't want to keep the element at index.
-            self.state.remaining.truncate(index);
+    /// Pauses the job partway through the action in progress, as with
+    /// [`Message::Pause`](enum.Message.html#variant.Pause).
+    ///
+    /// Returns whether the job was paused.
+    fn pause(&mut self) -> bool {
+        if self.status() != State::Running {
+            log::warn!("Coordinator told to pause while not running; ignoring.");
+            return false;
         }
-        self.state.program = None;
-        Ok(())
+        job_log!(self, info, "Pausing.");
+        self.pending = None;
+        if let Some((due, then)) = self.next.take() {
+            self.paused = Some(Paused {
+                remaining: due.saturating_duration_since(Instant::now()),
+                then,
+                pumping: self.pumping,
+            });
+        }
+        self.stop_pumps();
+        self.state.status = State::Waiting;
+        self.persist();
+        true
     }
-    /// Stop the program after the current step.
+    /// Continue the program.
     ///
-    /// If an end buffer is given, the current buffer will be replaced with that one before
-    /// stopping (if necessary).
-    fn stop<I>(&mut self, buffer: I) -> Result<()>
-    where
-        I: Into<Option<MotorId>>,
-    {
-        if let Some(target) = buffer.into() {
-            if let Some(current) = self.state.buffer {
-                if current == target {
-                    // We're already in the target buffer; we don't need to do much else.
-                    self.clear()?;
-                } else {
-                    let program = Protocol::with_step(Step::Perfuse(target, None)).as_program()?;
-                    self.state.program = Some(program.clone());
-                    self.state.remaining = program.into();
+    /// If the user paused the job partway through an action, the action picks up where it left
+    /// off; otherwise, the program moves on to the next action.
+    fn resume(&mut self, context: &mut CoordContext) -> Result<()> {
+        if self.status() != State::Waiting {
+            log::warn!("Coordinator told to resume while not paused; ignoring.");
+            return Ok(());
+        }
+        self.state.status = State::Running;
+        match self.paused.take() {
+            Some(paused) => {
+                let pump = self.state.pump;
+                match paused.pumping {
+                    Some(true) => self.perfuse(pump),
+                    Some(false) => self.drain(pump),
+                    None => {}
                 }
+                let Continuation(then) = paused.then;
+                self.schedule(paused.remaining, context, then);
+                self.persist();
+            }
+            None => {
+                self.advance(context)?;
             }
         }
         Ok(())
     }
-    /// Continue the program.
-    fn resume(&mut self, context: &mut CoordContext) -> Result<()> {
+    /// Skips the action being waited on, along with any waits immediately following it.
+    ///
+    /// Returns whether anything was skipped.
+    fn skip(&mut self, context: &mut CoordContext) -> Result<bool> {
         if self.status() != State::Waiting {
-            log::warn!("Coordinator told to resume while not paused; ignoring.");
-            return Ok(());
+            log::warn!("Coordinator told to skip while not paused; ignoring.");
+            return Ok(false);
         }
+        if self.paused.is_some() {
+            log::warn!("Coordinator told to skip a paused action; ignoring.");
+            return Ok(false);
+        }
+        let waits = self
+            .state
+            .remaining
+            .iter()
+            .take_while(|action| matches!(action, Action::Sleep(_) | Action::Hail))
+            .count();
+        self.state.remaining.drain(..waits);
         self.state.status = State::Running;
         self.advance(context)?;
+        Ok(true)
+    }
+    /// The buffer to rescue the sample with when aborting, if the chamber might be dry.
+    ///
+    /// The chamber is only considered at risk mid-drain; once drained, the program perfuses
+    /// immediately afterward, so only an interruption here leaves the sample exposed.
+    fn rescue_target(&self) -> Option<MotorId> {
+        match self.state.current {
+            Some(Action::Drain) => self.rescue_buffer,
+            _ => None,
+        }
+    }
+    /// Cancels any scheduled or starting job and halts the running one (if any), publishing why.
+    ///
+    /// A job which has already ended is left as it was archived.
+    fn halt(&mut self, reason: HaltReason, context: &mut CoordContext) -> Result<()> {
+        self.cancel_scheduled();
+        if !self.cancel_start() && !self.cancel_rescue(context) && !self.is_stopped() {
+            self.hcf(&reason, context)?;
+        }
+        self.publish(StatusMessage::Halted { reason }, context);
         Ok(())
     }
     /// Abort the program no matter where we are.
-    fn hcf(&mut self) -> Result<()> {
-        self.stop_pump();
+    ///
+    /// If the sample might be left dry and a rescue buffer is configured, a single perfusion of
+    /// the rescue buffer is performed before parking the valves, unless the emergency stop
+    /// button was pressed or a motor has failed (when nothing more should move).
+    ///
+    /// Stopping the pump comes first, so that if it fails, nothing else has changed and halting
+    /// can be attempted again.
+    fn hcf(&mut self, reason: &HaltReason, context: &mut CoordContext) -> Result<()> {
+        job_log!(self, warn, "Halting ({}).", reason);
+        self.cancel_pending();
+        self.try_stop_pumps()?;
+        self.stop_sampling(context);
+        let rescue = match reason {
+            HaltReason::EmergencyStop | HaltReason::MotorFault => None,
+            HaltReason::Operator | HaltReason::Error(_) => self.rescue_target(),
+        };
         // TODO: Reset motors?
-        self.state.status = State::Stopped { early: true };
-        // We didn't finish the last step, so remove it from the list
-        self.state.completed.pop();
-        // TODO: Handle error
-        let _ = mail::notify(&self.admins, mail::Status::Aborted);
+        self.state.halt();
+        if let Some(buffer) = rescue {
+            job_log!(
+                self,
+                warn,
+                "Aborting mid-drain; rescuing sample with buffer {}.",
+                buffer
+            );
+            self.state.current = Some(Action::Perfuse(buffer));
+            self.rescuing = true;
+            let result = self.fill(buffer, context, |coord, context| {
+                coord.rescuing = false;
+                coord.state.current = None;
+                coord.close_all(context);
+            });
+            if let Err(err) = result {
+                job_log!(self, error, "Could not rescue sample: {}", err);
+                self.rescuing = false;
+                self.state.current = None;
+            }
+        }
+        self.state.halt_reason = Some(reason.clone());
+        let completed = self.state.completed.clone();
+        self.archive(State::Stopped { early: true }, completed);
+        self.persist();
+        self.notify(mail::Status::Aborted(reason));
         Ok(())
     }
+    /// Stops rescuing the sample after an aborted job, returning whether it was being rescued.
+    ///
+    /// The job was already archived when it was aborted, so the pump is just stopped and the
+    /// valves closed.
+    fn cancel_rescue(&mut self, context: &mut CoordContext) -> bool {
+        if !self.rescuing {
+            return false;
+        }
+        job_log!(self, warn, "Stopping the rescue of the sample.");
+        self.rescuing = false;
+        self.cancel_pending();
+        self.stop_pumps();
+        self.state.current = None;
+        self.close_all(context);
+        true
+    }
+    /// Parks the machine and then stops the actix system.
+    ///
+    /// See [`Message::Shutdown`](enum.Message.html#variant.Shutdown).
+    fn shutdown(&mut self, context: &mut CoordContext) {
+        log::info!("Shutting down.");
+        self.cancel_start();
+        self.cancel_jog(context);
+        self.cancel_pending();
+        self.stop_sampling(context);
+        self.stop_pumps();
+        self.close_all(context);
+        context.run_later(self.settle_delay, |coord, _| {
+            let motors = coord
+                .hardware()
+                .map(|addresses| addresses.motors.clone())
+                .unwrap_or_default();
+            // Make sure every motor has stopped before the system goes down with it.
+            let stopped = futures::future::join_all(
+                motors
+                    .into_iter()
+                    .map(|addr| addr.send(MotorMessage::Stop))
+                    .collect::<Vec<_>>(),
+            );
+            Arbiter::spawn(stopped.then(|_| {
+                System::current().stop();
+                Ok(())
+            }));
+        });
+    }
+    /// Hands the given email to the mailer, which sends it in the background.
+    fn send_mail(&self, email: Email) {
+        if self.simulate {
+            log::info!("Simulated email to {:?}: {}", email.to, email.subject);
+            return;
+        }
+        if let Some(ref addresses) = self.addresses {
+            addresses.mailer.do_send(Deliver(email));
+        }
+    }
+    /// Emails the administrators a summary of the job that just finished.
+    ///
+    /// If the job can't be summarized (for example, if it was cleared early), a plain completion
+    /// notice is sent instead.
+    fn notify_finished(&self) {
+        let summary = match (self.state.uuid, &self.state.program, self.state.started) {
+            (Some(id), Some(program), Some(started)) => JobSummary {
+                id,
+                program: program.clone(),
+                started,
+                ended: SystemTime::now(),
+            },
+            _ => return self.notify(mail::Status::Finished),
+        };
+        self.send_mail(mail::finished_email(&self.mail, &self.admins, &summary));
+    }
+    /// Emails the administrators about a change in status.
+    fn notify(&self, status: mail::Status) {
+        self.send_mail(mail::status_email(&self.mail, &self.admins, status));
+    }
+    /// Records the end of the current job, discarding the oldest record if there are too many.
+    fn archive(&mut self, state: State, completed: Vec<Action>) {
+        if let Some(id) = self.state.uuid {
+            let history = &mut self.state.history;
+            history.push_back(JobRecord {
+                id,
+                state,
+                completed,
+                ended: SystemTime::now(),
+                metrics: self.state.metrics.clone(),
+                metadata: self.state.metadata.clone(),
+                halt_reason: self.state.halt_reason.clone(),
+            });
+            while history.len() > self.history_limit {
+                history.pop_front();
+            }
+        }
+    }
+    /// Records of recently-ended jobs, newest first.
+    pub fn history(&self) -> Vec<JobRecord> {
+        self.state.history.iter().rev().cloned().collect()
+    }
+    /// The metrics of the given job, if it's the current (or most recent) job or in the history.
+    pub fn metrics(&self, id: Uuid) -> Option<Metrics> {
+        if self.state.uuid == Some(id) {
+            return Some(self.state.metrics.clone());
+        }
+        self.state
+            .history
+            .iter()
+            .find(|record| record.id == id)
+            .map(|record| record.metrics.clone())
+    }
+    /// The estimated time at which the current job will complete.
+    ///
+    /// The estimate covers the actions remaining after the current one. The second element is
+    /// `true` when the estimate is uncertain because the program will wait on the user; such waits
+    /// are counted as taking no time. If no job is running, no estimate is given.
+    pub fn estimated_completion(&self) -> (Option<SystemTime>, bool) {
+        self.estimated_completion_from(SystemTime::now())
+    }
+    /// Estimates the completion time of the current job as of the given time.
+    fn estimated_completion_from(&self, now: SystemTime) -> (Option<SystemTime>, bool) {
+        if self.is_stopped() {
+            return (None, false);
+        }
+        let mut uncertain = false;
+        let remaining = self
+            .state
+            .remaining
+            .iter()
+            .map(|action| {
+                self.action_duration(action).unwrap_or_else(|| {
+                    uncertain = true;
+                    Duration::new(0, 0)
+                })
+            })
+            .fold(Duration::new(0, 0), |total, duration| total + duration);
+        (Some(now + remaining), uncertain)
+    }
+    /// How much longer the current job will take to run the actions remaining after the current
+    /// one.
+    ///
+    /// If no job is running, or the job will wait on the user, no estimate can be given.
+    pub fn remaining_duration(&self) -> Option<Duration> {
+        if self.is_stopped() {
+            return None;
+        }
+        self.state
+            .remaining
+            .iter()
+            .try_fold(Duration::new(0, 0), |total, action| {
+                self.action_duration(action)
+                    .map(|duration| total + duration)
+            })
+    }
+    /// Projects the timeline of the given protocol, were it to be started at the given time.
+    ///
+    /// The final bath never ends on its own, so its projected end is when it has filled.
+    pub fn project(&self, protocol: &Protocol, start: SystemTime) -> Result<Vec<Projection>> {
+        let mut time = start;
+        Ok(protocol
+            .step_actions(self.soak)?
+            .into_iter()
+            .zip(protocol.expand())
+            .map(|(actions, step)| {
+                let mut uncertain = false;
+                let begin = time;
+                for action in &actions {
+                    time += self.action_duration(action).unwrap_or_else(|| {
+                        uncertain = true;
+                        Duration::new(0, 0)
+                    });
+                }
+                Projection {
+                    step,
+                    start: begin,
+                    end: time,
+                    uncertain,
+                }
+            })
+            .collect())
+    }
+    /// Projects the timeline of the given protocol, as with [`project`](#method.project), and
+    /// estimates how much of each buffer it would use.
+    pub fn plan(&self, protocol: &Protocol, start: SystemTime) -> Result<Plan> {
+        Ok(Plan {
+            steps: self.project(protocol, start)?,
+            volumes: protocol.volume_per_buffer(self.volume.get::<milliliter>()),
+        })
+    }
+    /// Walks through the given program without any devices or event loop, returning the status
+    /// messages that would be published along the way.
+    ///
+    /// Each of the given messages is handled once that many actions have been started. Only
+    /// `Continue`, `Stop`, `ExchangeStop`, and `Halt` have any effect; the rest are ignored.
+    /// Timed actions finish immediately, but waiting on the user ends the walk unless a
+    /// `Continue` is given for it. This is meant for testing the coordinator's decisions.
+    pub fn simulate<I>(program: Program, messages: I) -> Vec<StatusMessage>
+    where
+        I: IntoIterator<Item = (usize, Message)>,
+    {
+        let mut state = CoordState {
+            program: Some(program.clone()),
+            remaining: program.into(),
+            status: State::Running,
+            ..CoordState::default()
+        };
+        let mut messages = messages.into_iter().peekable();
+        let mut published = vec![];
+        let mut started = 0;
+        loop {
+            while let Some((_, message)) = match messages.peek() {
+                Some(&(after, _)) if after <= started => messages.next(),
+                _ => None,
+            } {
+                let result = match message {
+                    Message::Continue if state.status == State::Waiting => {
+                        state.status = State::Running;
+                        published.push(StatusMessage::Continued);
+                        Ok(())
+                    }
+                    Message::Stop => state.stop(None).map(|()| {
+                        published.push(StatusMessage::StopQueued { early: false });
+                    }),
+                    Message::ExchangeStop(buffer) => state.stop(buffer).map(|()| {
+                        published.push(StatusMessage::StopQueued { early: false });
+                    }),
+                    Message::Halt => {
+                        state.halt();
+                        published.push(StatusMessage::Halted {
+                            reason: HaltReason::Operator,
+                        });
+                        return published;
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    published.push(StatusMessage::Errored(err.to_string()));
+                }
+            }
+            if state.status != State::Running {
+                return published;
+            }
+            let (action, progress) = match state.take_next() {
+                Some(next) => next,
+                None => return published,
+            };
+            published.push(progress);
+            state.enter(&action);
+            match &action {
+                Action::Hail => published.push(StatusMessage::Paused),
+                Action::Notify(notification) => {
+                    published.push(StatusMessage::Notified(notification.clone()))
+                }
+                _ => {}
+            }
+            state.record(action);
+            started += 1;
+        }
+    }
+    /// Lists each action of the current (or most recent) job, with its planned duration and how
+    /// far the job has gotten with it.
+    ///
+    /// An action is current from when it begins until the next one does; once the job has
+    /// stopped, nothing is.
+    pub fn timeline(&self) -> Vec<TimedAction> {
+        let completed = &self.state.completed;
+        let current = !self.is_stopped() && self.state.current.is_some();
+        let entry = |action: &Action, progress| TimedAction {
+            action: action.clone(),
+            kind: action.kind().to_owned(),
+            buffer: action
+                .buffer()
+                .map(|buffer| buffer_label(&self.labels, buffer)),
+            duration: self.action_duration(action),
+            progress,
+        };
+        completed
+            .iter()
+            .enumerate()
+            .map(|(index, action)| {
+                if current && index + 1 == completed.len() {
+                    entry(action, Progress::Current)
+                } else {
+                    entry(action, Progress::Completed)
+                }
+            })
+            .chain(
+                self.state
+                    .remaining
+                    .iter()
+                    .map(|action| entry(action, Progress::Remaining)),
+            )
+            .collect()
+    }
+    /// Takes a snapshot of the current (or most recent) job.
+    pub fn snapshot(&self) -> Snapshot {
+        let (estimated_completion, completion_uncertain) = self.estimated_completion();
+        Snapshot {
+            uuid: self.state.uuid,
+            state: self.status(),
+            program: self.state.program.clone(),
+            remaining: self.state.remaining.clone(),
+            buffer: self.state.buffer,
+            ambient: self.state.ambient.clone(),
+            estimated_completion,
+            completion_uncertain,
+            remaining_duration: self.remaining_duration(),
+            labels: self.labels.clone(),
+            metadata: self.state.metadata.clone(),
+            halt_reason: self.state.halt_reason.clone(),
+            timeline: self.timeline(),
+        }
+    }
+    /// Whether the coordinator's devices have been started and are ready to be driven.
+    pub fn is_ready(&self) -> bool {
+        self.addresses.is_some()
+    }
+    /// Reports whether the devices are ready, and which pins (if any) couldn't be opened.
+    pub fn health(&self) -> Health {
+        Health {
+            ready: self.is_ready(),
+            failed_pins: self.failed_pins.clone(),
+        }
+    }
     /// Whether we're in the stopped state.
     pub fn is_stopped(&self) -> bool {
         match self.state.status {
@@ -440,42 +1983,251 @@ impl Coordinator {
             State::Running | State::Waiting => false,
         }
     }
+    /// Ensures that each of the buffers the protocol uses has a motor.
+    fn check_buffers(&self, protocol: &Protocol) -> Result<()> {
+        // There's one label (or lack thereof) for each configured motor.
+        let unknown = protocol
+            .buffers()
+            .into_iter()
+            .filter(|&buffer| buffer >= self.labels.len())
+            .collect::<Vec<_>>();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnknownBuffers(unknown))
+        }
+    }
+    /// Ensures that each of the pumps the protocol uses is configured.
+    fn check_pumps(&self, protocol: &Protocol) -> Result<()> {
+        let unknown = protocol
+            .pumps()
+            .into_iter()
+            .filter(|&pump| pump >= self.pump_count)
+            .collect::<Vec<_>>();
+        if unknown.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::UnknownPumps(unknown))
+        }
+    }
     /// Start the given protocol, if we can.
+    ///
+    /// The metadata is only recorded; see [`Message::Start`](enum.Message.html#variant.Start).
     fn start(
         &mut self,
         protocol: &Protocol,
         label: Option<Uuid>,
+        metadata: HashMap<String, String>,
         context: &mut CoordContext,
     ) -> Result<()> {
-        let program = protocol.as_program()?;
-        if self.is_stopped() {
-            self.stop_pump();
-            self.close_all(context);
-            context.run_later(Duration::new(10, 0), move |coord, context| {
-                let id = label.unwrap_or_else(Uuid::new_v4);
-                coord.state.program = Some(program.clone());
-                coord.state.remaining = program.into();
-                coord.state.current = None;
-                coord.state.buffer = None;
-                coord.state.status = State::Running;
-                coord.state.completed.clear();
-                coord.state.uuid = Some(id);
-                coord.advance(context).unwrap();
-            });
+        if !self.is_ready() {
+            return Err(Error::NotReady);
+        }
+        let program = protocol.as_program_with_soak(self.soak)?;
+        protocol.validate_with_limit(self.max_step_duration)?;
+        self.check_buffers(protocol)?;
+        self.check_pumps(protocol)?;
+        self.check_estop_released()?;
+        if !self.is_stopped() || self.starting.is_some() || self.rescuing {
+            return Err(Error::Busy);
+        }
+        self.cancel_jog(context);
+        self.stop_pumps();
+        self.close_all(context);
+        let start = self.next_start();
+        self.starting = Some(start);
+        context.run_later(self.settle_delay, move |coord, context| {
+            if coord.starting != Some(start) {
+                return;
+            }
+            coord.starting = None;
+            let id = label.unwrap_or_else(Uuid::new_v4);
+            coord.state.program = Some(program.clone());
+            coord.state.remaining = program.into();
+            coord.state.current = None;
+            coord.state.buffer = None;
+            coord.state.pump = 0;
+            coord.state.status = State::Running;
+            coord.state.completed.clear();
+            coord.state.uuid = Some(id);
+            coord.state.metadata = metadata;
+            coord.state.halt_reason = None;
+            coord.state.started = Some(SystemTime::now());
+            coord.state.metrics = Metrics {
+                started: coord.state.started,
+                ..Metrics::default()
+            };
+            coord.start_sampling(context);
+            coord.try_advance(context);
+        });
+        Ok(())
+    }
+    /// Numbers a new start, so that it can tell whether it has been cancelled when its time comes.
+    fn next_start(&mut self) -> u64 {
+        self.starts = self.starts.wrapping_add(1);
+        self.starts
+    }
+    /// Cancels the start of a job which is waiting for the valves to settle, returning whether
+    /// there was one.
+    ///
+    /// The job never ran, so there's nothing to halt or archive; the valves are closed and the
+    /// pumps stopped already.
+    fn cancel_start(&mut self) -> bool {
+        let cancelled = self.starting.take().is_some();
+        if cancelled {
+            log::info!("Canceling the job before it starts.");
+        }
+        cancelled
+    }
+    /// Starts the queued job, if any, now that the current job has finished.
+    ///
+    /// The finished job has already been archived, so failing to start the queued one is reported
+    /// to subscribers rather than treated as an error in the finished job.
+    fn start_queued(&mut self, context: &mut CoordContext) {
+        if let Some((protocol, id, metadata)) = self.state.queued.take() {
+            match self.start(&protocol, Some(id), metadata, context) {
+                Ok(()) => self.publish(StatusMessage::Started(protocol), context),
+                Err(err) => {
+                    let description = format!("Could not start queued job: {}", err);
+                    job_log!(self, error, "{}", description);
+                    self.publish(StatusMessage::Errored(description), context);
+                }
+            }
+        }
+    }
+    /// Queues the given protocol to be started once the current job finishes.
+    fn queue(
+        &mut self,
+        protocol: Protocol,
+        label: Option<Uuid>,
+        metadata: HashMap<String, String>,
+    ) -> Result<()> {
+        // Make sure the protocol is valid now rather than when it's due to start.
+        protocol.as_program_with_soak(self.soak)?;
+        protocol.validate_with_limit(self.max_step_duration)?;
+        self.check_buffers(&protocol)?;
+        self.check_pumps(&protocol)?;
+        let id = label.unwrap_or_else(Uuid::new_v4);
+        self.state.queued = Some((protocol, id, metadata));
+        Ok(())
+    }
+    /// Schedules the given protocol to be started at the given time, replacing any scheduled start.
+    ///
+    /// Returns the label of the scheduled job.
+    fn schedule_start(
+        &mut self,
+        protocol: Protocol,
+        label: Option<Uuid>,
+        at: SystemTime,
+        context: &mut CoordContext,
+    ) -> Result<Uuid> {
+        // Make sure the protocol is valid now rather than when it's due to start.
+        protocol.as_program_with_soak(self.soak)?;
+        protocol.validate_with_limit(self.max_step_duration)?;
+        self.check_buffers(&protocol)?;
+        self.check_pumps(&protocol)?;
+        self.check_estop_released()?;
+        self.cancel_scheduled();
+        let id = label.unwrap_or_else(Uuid::new_v4);
+        let delay = at
+            .duration_since(SystemTime::now())
+            .unwrap_or_else(|_| Duration::new(0, 0));
+        let start = self.next_start();
+        self.scheduled = Some(start);
+        context.run_later(delay, move |coord, context| {
+            if coord.scheduled != Some(start) {
+                return;
+            }
+            coord.scheduled = None;
+            match coord.start(&protocol, Some(id), HashMap::new(), context) {
+                Ok(()) => coord.publish(StatusMessage::Started(protocol), context),
+                Err(err) => {
+                    let description = format!("Could not start scheduled job: {}", err);
+                    job_log!(coord, error, "{}", description);
+                    coord.publish(StatusMessage::Errored(description), context);
+                }
+            }
+        });
+        Ok(id)
+    }
+    /// Cancels the scheduled start, if any, returning whether there was one.
+    fn cancel_scheduled(&mut self) -> bool {
+        self.scheduled.take().is_some()
+    }
+    /// Cancels the queued job without affecting the current one.
+    fn cancel_queued(&mut self) -> Result<()> {
+        self.state
+            .queued
+            .take()
+            .map(|_| ())
+            .ok_or(Error::NothingQueued)
+    }
+    /// Opens (or closes) the given valve for the given duration and then closes it, as with
+    /// [`Message::Jog`](enum.Message.html#variant.Jog).
+    fn jog(
+        &mut self,
+        motor: MotorId,
+        open: bool,
+        duration: Duration,
+        context: &mut CoordContext,
+    ) -> Result<()> {
+        if !self.is_ready() {
+            return Err(Error::NotReady);
+        }
+        let busy = self.starting.is_some() || self.jogging.is_some() || self.rescuing;
+        if !self.is_stopped() || busy {
+            log::warn!("Coordinator told to jog a valve while busy; refusing.");
+            return Err(Error::Busy);
+        }
+        // There's one label for each motor, including the waste valve.
+        if self.motor_index(motor) >= self.labels.len() {
+            return Err(Error::UnknownBuffers(vec![motor]));
         }
+        if open {
+            self.open(motor, context)?;
+        } else {
+            self.close(motor, context);
+        }
+        log::info!("Jogging valve {} for {:?}.", motor, duration);
+        self.jogging = Some(context.run_later(duration, move |coord, context| {
+            coord.jogging = None;
+            coord.close(motor, context);
+        }));
         Ok(())
     }
+    /// Cancels the closing of a jogged valve, as when all valves are about to be closed anyway.
+    fn cancel_jog(&mut self, context: &mut CoordContext) {
+        if let Some(handle) = self.jogging.take() {
+            context.cancel_future(handle);
+        }
+    }
     /// Subscribes the given object to updates from the coordinator.
-    pub fn subscribe(&self, sub: Box<dyn Update>) {
-        if let Some(addr) = &self.addresses {
-            addr.subscribers.do_send(SubscribersMessage::Add(sub));
+    ///
+    /// If the coordinator hasn't been started yet, the subscriber is kept until it is.
+    pub fn subscribe(&mut self, sub: Box<dyn Update>) {
+        match &self.addresses {
+            Some(addr) => addr.subscribers.do_send(SubscribersMessage::Add(sub)),
+            None => self.pending_subscribers.push(sub),
+        }
+    }
+    /// Subscribes the configured webhook (if any) to updates, unless jobs are only simulated.
+    #[cfg(feature = "use_serde")]
+    fn subscribe_webhook(&mut self) {
+        if let Some(endpoint) = self.webhook.take() {
+            if self.simulate {
+                log::info!("Simulating; status updates won't be posted to the webhook.");
+            } else {
+                self.subscribe(Box::new(Webhook::spawn(endpoint)));
+            }
         }
     }
     /// Publishes a status change to all subscribers.
     fn publish(&self, message: StatusMessage, context: &mut <Self as Actor>::Context) {
+        job_log!(self, debug, "Publishing status: {:?}", message);
         if let Some(addr) = &self.addresses {
             let message = Status {
                 address: context.address(),
+                job: self.state.uuid,
                 message,
             };
             addr.subscribers
@@ -488,24 +2240,42 @@ impl Actor for Coordinator {
     type Context = CoordContext;
     fn started(&mut self, ctx: &mut Self::Context) {
         let subscribers = Subscribers {
-            subs: vec![],
+            subs: self
+                .pending_subscribers
+                .drain(..)
+                .map(Listener::new)
+                .collect(),
             coord: ctx.address(),
         }
         .start();
         if let Some(devices) = self.devices.take() {
+            let valves = devices.motors.iter().map(Motor::valve).collect();
             let motors = devices
                 .motors
                 .into_iter()
-                .map(Actor::start)
+                .map(|mut motor| {
+                    motor.supervisor = Some(ctx.address());
+                    motor.start()
+                })
                 .collect::<Vec<_>>();
-            let pump = devices.pump.start();
+            let pumps = devices.pumps.into_iter().map(Pump::start).collect();
             let addresses = Addresses {
-                pump,
+                pumps,
                 motors,
+                valves,
                 subscribers,
+                mailer: Mailer::spawn(&self.mail),
             };
             self.addresses = Some(addresses);
         }
+        #[cfg(feature = "use_serde")]
+        self.subscribe_webhook();
+        if self.estop.is_some() {
+            ctx.run_interval(*ESTOP_INTERVAL, Self::check_estop);
+        }
+        if self.restored {
+            self.resume_restored(ctx);
+        }
     }
     fn stopped(&mut self, _ctx: &mut Self::Context) {
         // Redundant due to the impending drop, but I like to be explicit
@@ -521,28 +2291,118 @@ impl Handle<Message> for Coordinator {
                 self.resume(context)?;
                 self.publish(StatusMessage::Continued, context);
             }
+            Message::Pause => {
+                if self.pause() {
+                    self.publish(StatusMessage::Paused, context);
+                }
+            }
             Message::Stop => {
-                self.stop(None)?;
+                if !self.cancel_start() {
+                    self.state.stop(None)?;
+                }
                 self.publish(StatusMessage::StopQueued { early: false }, context);
             }
-            Message::Halt => {
-                self.hcf()?;
-                self.publish(StatusMessage::Halted, context);
-            }
+            Message::Halt => self.halt(HaltReason::Operator, context)?,
             Message::ExchangeStop(id) => {
-                self.stop(id)?;
+                self.state.stop(id)?;
                 self.publish(StatusMessage::StopQueued { early: false }, context);
             }
-            Message::Start(proto, label) => {
-                self.start(&proto, label, context)?;
+            Message::Start(proto, label, metadata) => {
+                self.start(&proto, label, metadata, context)?;
                 self.publish(StatusMessage::Started(proto), context);
             }
+            Message::Queue(proto, label, metadata) => self.queue(proto, label, metadata)?,
+            Message::ScheduleStart(proto, label, at) => {
+                let id = self.schedule_start(proto, label, at, context)?;
+                self.publish(StatusMessage::Scheduled { id, at }, context);
+            }
+            Message::CancelQueued => {
+                self.cancel_queued()?;
+                self.publish(StatusMessage::QueueCleared, context);
+            }
+            Message::Skip => {
+                if self.skip(context)? {
+                    self.publish(StatusMessage::Skipped, context);
+                }
+            }
+            Message::Jog {
+                motor,
+                open,
+                duration,
+            } => self.jog(motor, open, duration, context)?,
             Message::Subscribe(sub) => self.subscribe(sub),
+            Message::Shutdown => self.shutdown(context),
         }
         Ok(())
     }
 }
 
+impl Handle<MotorFailure> for Coordinator {
+    type Result = ();
+    fn handle(&mut self, failure: MotorFailure, context: &mut Self::Context) -> Self::Result {
+        let description = format!(
+            "The motor on pin {} could not be moved ({:?}) after {} attempts: {}",
+            failure.pin, failure.message, failure.attempts, failure.error
+        );
+        log::error!("{}", description);
+        self.publish(StatusMessage::Errored(description.clone()), context);
+        self.notify(mail::Status::Custom {
+            subject: "Motor failure",
+            message: &format!(
+                "{}. Any running job has been halted; please check the valve before continuing.",
+                description
+            ),
+            html: None,
+        });
+        // A valve that can't be moved can't be trusted to be where the program expects.
+        if !matches!(self.status(), State::Stopped { .. }) {
+            context.notify(Halt(HaltReason::MotorFault));
+        }
+    }
+}
+
+impl Handle<Halt> for Coordinator {
+    type Result = Result<()>;
+    fn handle(&mut self, Halt(reason): Halt, context: &mut Self::Context) -> Self::Result {
+        self.halt(reason, context)
+    }
+}
+
+impl Handle<GetSnapshot> for Coordinator {
+    type Result = MessageResult<GetSnapshot>;
+    fn handle(&mut self, _: GetSnapshot, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.snapshot())
+    }
+}
+
+impl Handle<GetHistory> for Coordinator {
+    type Result = MessageResult<GetHistory>;
+    fn handle(&mut self, _: GetHistory, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.history())
+    }
+}
+
+impl Handle<GetMetrics> for Coordinator {
+    type Result = MessageResult<GetMetrics>;
+    fn handle(&mut self, message: GetMetrics, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.metrics(message.0))
+    }
+}
+
+impl Handle<GetHealth> for Coordinator {
+    type Result = MessageResult<GetHealth>;
+    fn handle(&mut self, _message: GetHealth, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.health())
+    }
+}
+
+impl Handle<Project> for Coordinator {
+    type Result = Result<Plan>;
+    fn handle(&mut self, message: Project, _context: &mut Self::Context) -> Self::Result {
+        self.plan(&message.0, message.1)
+    }
+}
+
 #[derive(Debug)]
 enum SubscribersMessage {
     /// Register a new listener.
@@ -555,11 +2415,48 @@ impl ActixMessage for SubscribersMessage {
     type Result = ();
 }
 
+/// How many times in a row a subscriber may panic before it's dropped.
+const MAX_SUBSCRIBER_FAILURES: u32 = 3;
+
+/// A subscriber, along with how many times in a row it has panicked.
+#[derive(Debug)]
+struct Listener {
+    update: Box<dyn Update>,
+    failures: u32,
+}
+
+impl Listener {
+    fn new(update: Box<dyn Update>) -> Self {
+        Self {
+            update,
+            failures: 0,
+        }
+    }
+    /// Hands a status update to the subscriber, catching (and counting) any panic so that the
+    /// other subscribers still get it.
+    fn forward(&mut self, status: &Status, subscribers: &Subscribers) {
+        let update = &self.update;
+        match panic::catch_unwind(AssertUnwindSafe(|| update.handle(status, subscribers))) {
+            Ok(()) => self.failures = 0,
+            Err(_) => {
+                self.failures += 1;
+                log::warn!(
+                    "Subscriber panicked while handling a status update ({} in a row).",
+                    self.failures
+                );
+            }
+        }
+    }
+}
+
 /// Handles all subscription and responding to events.
+///
+/// A subscriber which panics doesn't keep the others from getting updates, but it's dropped if
+/// it keeps panicking.
 #[derive(Debug)]
 pub struct Subscribers {
     coord: Addr<Coordinator>,
-    subs: Vec<Box<dyn Update>>,
+    subs: Vec<Listener>,
 }
 
 impl Actor for Subscribers {
@@ -571,12 +2468,26 @@ impl Handle<SubscribersMessage> for Subscribers {
     fn handle(&mut self, message: SubscribersMessage, _context: &mut Self::Context) {
         match message {
             SubscribersMessage::Forward(message) => {
-                for sub in self.subs.iter() {
-                    sub.handle(&message, &self);
+                // Subscribers are handed ourselves, so they're set aside while they're handling.
+                let mut subs = mem::take(&mut self.subs);
+                subs.retain(|sub| !sub.update.is_closed());
+                for sub in &mut subs {
+                    sub.forward(&message, self);
                 }
+                subs.retain(|sub| {
+                    let failing = sub.failures >= MAX_SUBSCRIBER_FAILURES;
+                    if failing {
+                        log::error!(
+                            "Dropping subscriber {:?}, which keeps panicking.",
+                            sub.update
+                        );
+                    }
+                    !failing
+                });
+                self.subs = subs;
             }
             SubscribersMessage::Add(listener) => {
-                self.subs.push(listener);
+                self.subs.push(Listener::new(listener));
             }
         }
     }
@@ -595,7 +2506,13 @@ impl Respond for Subscribers {
 /// Trait for receiving updates on coordinator status.
 pub trait Update: std::fmt::Debug + Send {
     /// Handles the change in coordinator status.
+    ///
+    /// Every subscriber is handed each update in turn, so this shouldn't block.
     fn handle(&self, msg: &Status, coord: &Subscribers);
+    /// Whether the subscriber has gone away and should be dropped.
+    fn is_closed(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -603,6 +2520,8 @@ pub trait Update: std::fmt::Debug + Send {
 pub struct Status {
     /// The address of the coordinator in question.
     pub address: Addr<Coordinator>,
+    /// The running (or most recently-completed) job, if there's been one.
+    pub job: Option<Uuid>,
     /// The information the coordinator wishes to convey.
     pub message: StatusMessage,
 }
@@ -622,7 +2541,35 @@ pub enum StatusMessage {
         early: bool,
     },
     /// The coordinator has been halted.
-    Halted,
+    Halted {
+        /// Why it was halted.
+        reason: HaltReason,
+    },
+    /// The queued job has been cancelled.
+    QueueCleared,
+    /// A job has been scheduled to start later.
+    Scheduled {
+        /// The label of the scheduled job.
+        id: Uuid,
+        /// When the job is to start.
+        at: SystemTime,
+    },
+    /// The step being waited on has been skipped.
+    Skipped,
+    /// The program could not be advanced, and so has been aborted; the error is described.
+    Errored(String),
+    /// The program has notified the user, as with a prompt; the notification is also emailed to
+    /// the administrators.
+    Notified(Notification),
+    /// The coordinator has begun running the given action.
+    Advanced {
+        /// How many actions of the program have been completed.
+        completed: usize,
+        /// How many actions the program has in total.
+        total: usize,
+        /// The action now running.
+        action: Action,
+    },
 }
 
 impl ActixMessage for Status {
@@ -657,13 +2604,1654 @@ pub mod tui {
                 }
                 StatusMessage::Continued => log::debug!("Coordinator continuing."),
                 StatusMessage::Started(proto) => {
-                    log::debug!("Coordinator starting protocol: {:?}", proto)
+                    log::debug!("Coordinator starting protocol:\n{}", proto)
                 }
                 StatusMessage::StopQueued { early } => {
                     log::debug!("Coordinator stop queued (early: {})", early)
                 }
-                StatusMessage::Halted => log::warn!("Coordinator halted!"),
+                StatusMessage::Halted { reason } => log::warn!("Coordinator halted ({})!", reason),
+                StatusMessage::QueueCleared => log::debug!("Coordinator queue cleared."),
+                StatusMessage::Scheduled { id, at } => log::info!(
+                    "Job {} scheduled for {}.",
+                    id,
+                    humantime::format_rfc3339_seconds(*at)
+                ),
+                StatusMessage::Skipped => log::debug!("Coordinator skipped waiting step."),
+                StatusMessage::Errored(description) => {
+                    log::error!("Coordinator aborted due to error: {}", description)
+                }
+                StatusMessage::Notified(notification) => {
+                    println!("{}: {}", notification.subject, notification.message)
+                }
+                StatusMessage::Advanced {
+                    completed,
+                    total,
+                    action,
+                } => log::info!("Step {} of {}: {}", completed + 1, total, action),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AmbientConfig, ProtocolBuilder};
+    use futures::Future;
+    use std::sync::mpsc;
+
+    /// A subscriber which sends along whichever status messages it's interested in.
+    struct Collect<T> {
+        sender: mpsc::Sender<T>,
+        filter: fn(&StatusMessage) -> Option<T>,
+    }
+
+    impl<T> fmt::Debug for Collect<T> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("Collect")
+        }
+    }
+
+    impl<T: fmt::Debug + Send> Update for Collect<T> {
+        fn handle(&self, status: &Status, _coord: &Subscribers) {
+            if let Some(item) = (self.filter)(&status.message) {
+                let _ = self.sender.send(item);
+            }
+        }
+    }
+
+    #[test]
+    fn rescue_only_mid_drain() {
+        let mut config = Config::stub();
+        config.rescue_buffer = Some(2);
+        let mut coord = Coordinator::try_new(config).unwrap();
+        coord.state.current = Some(Action::Drain);
+        assert_eq!(coord.rescue_target(), Some(2));
+        coord.state.current = Some(Action::Sleep(Duration::new(5, 0)));
+        assert_eq!(coord.rescue_target(), None);
+        coord.state.current = Some(Action::Perfuse(1));
+        assert_eq!(coord.rescue_target(), None);
+    }
+
+    /// Halts a job mid-drain (with buffer 2 configured for rescuing the sample) for the given
+    /// reason, calling `check` straight afterward.
+    ///
+    /// Returns the positions the motors were last commanded to and the pump's direction, once
+    /// the pump has had time to start (but not to fill the chamber).
+    fn halt_mid_drain(
+        reason: HaltReason,
+        check: fn(&mut Coordinator, &mut CoordContext),
+    ) -> (Vec<Option<u16>>, Option<crate::PumpDirection>) {
+        let mut config = Config::stub();
+        config.rescue_buffer = Some(2);
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(10), |coord, context| {
+                    coord.state.status = State::Running;
+                    coord.state.remaining = vec![Action::Drain, Action::Perfuse(1), Action::Finish];
+                    coord.advance(context).unwrap();
+                });
+                context.run_later(Duration::from_millis(50), move |coord, context| {
+                    coord.halt(reason, context).unwrap();
+                    check(coord, context);
+                });
+                context.run_later(Duration::from_millis(2300), move |coord, _| {
+                    let addresses = coord.addresses.as_ref().unwrap();
+                    let positions = futures::future::join_all(
+                        addresses
+                            .motors
+                            .iter()
+                            .map(|motor| motor.send(MotorMessage::GetAngle))
+                            .collect::<Vec<_>>(),
+                    );
+                    let direction = addresses.pumps[0].send(PumpMessage::GetDirection);
+                    Arbiter::spawn(positions.join(direction).then(move |result| {
+                        let (positions, direction) = result.unwrap();
+                        let commanded = positions.into_iter().map(|p| p.commanded).collect();
+                        tx.send((commanded, direction.unwrap())).unwrap();
+                        System::current().stop();
+                        Ok(())
+                    }));
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn rescued_mid_drain() {
+        let (positions, direction) = halt_mid_drain(HaltReason::Operator, |coord, context| {
+            // Nothing else can be done with the chamber while the sample is rescued.
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            let started = coord.start(&protocol, None, HashMap::new(), context);
+            assert!(matches!(started, Err(Error::Busy)));
+            let jogged = coord.jog(1, true, Duration::from_millis(100), context);
+            assert!(matches!(jogged, Err(Error::Busy)));
+        });
+        // Buffer 2 is on the motor after the waste valve, which is shut while filling.
+        assert_eq!(positions, vec![Some(180), None, None, Some(0)]);
+        assert_eq!(direction, Some(crate::PumpDirection::Forward));
+    }
+
+    #[test]
+    fn no_rescue_after_emergency_stop() {
+        for reason in vec![HaltReason::EmergencyStop, HaltReason::MotorFault] {
+            let (positions, direction) = halt_mid_drain(reason, |coord, _| {
+                assert!(!coord.rescuing);
+            });
+            assert_ne!(positions[3], Some(0));
+            assert_eq!(direction, None);
+        }
+    }
+
+    #[test]
+    fn no_rescue_without_buffer() {
+        let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+        coord.state.current = Some(Action::Drain);
+        assert_eq!(coord.rescue_target(), None);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn ambient_sampling() {
+        let mut config = Config::stub();
+        config.ambient = Some(AmbientConfig {
+            interval: Duration::from_millis(20),
+            sensor: SensorConfig::Fixed {
+                temperature: 21.5,
+                humidity: 40.0,
+            },
+        });
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(config).unwrap();
+                coord.start_sampling(context);
+                context.run_later(Duration::from_millis(110), move |coord, context| {
+                    coord.stop_sampling(context);
+                    tx.send(coord.ambient().to_vec()).unwrap();
+                    System::current().stop();
+                });
+                coord
+            });
+        });
+        let readings = rx.recv().unwrap();
+        assert_eq!(readings.len(), 5);
+        assert!(readings
+            .iter()
+            .all(|&(_, temperature, humidity)| temperature == 21.5 && humidity == 40.0));
+    }
+
+    #[test]
+    fn interlocked_valves() {
+        let mut config = Config::stub();
+        config.interlocks = vec![(0, 1)];
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(config).unwrap();
+                let first = coord.open(0, context);
+                let conflict = coord.open(1, context);
+                let unrelated = coord.open(2, context);
+                coord.close(0, context);
+                let after = coord.open(1, context);
+                tx.send((first, conflict, unrelated, after)).unwrap();
+                System::current().stop();
+                coord
+            });
+        });
+        let (first, conflict, unrelated, after) = rx.recv().unwrap();
+        assert!(first.is_ok());
+        assert!(matches!(conflict, Err(Error::Interlock(1, 0))));
+        assert!(unrelated.is_ok());
+        assert!(after.is_ok());
+    }
+
+    #[test]
+    fn estimated_completion() {
+        let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        assert_eq!(coord.estimated_completion_from(now), (None, false));
+        coord.state.status = State::Running;
+        coord.state.remaining = vec![Action::Sleep(Duration::from_secs(600)), Action::Finish];
+        assert_eq!(
+            coord.estimated_completion_from(now),
+            (Some(now + Duration::from_secs(600)), false)
+        );
+        coord.state.remaining.insert(1, Action::Hail);
+        assert_eq!(
+            coord.estimated_completion_from(now),
+            (Some(now + Duration::from_secs(600)), true)
+        );
+    }
+
+    #[test]
+    fn start_requires_devices() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let ready = Coordinator::try_new(Config::stub()).unwrap().start();
+            let unready = Coordinator::create(|_| {
+                let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+                // Simulate the devices having failed to initialize.
+                coord.devices = None;
+                coord
+            });
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            let results = ready
+                .send(Message::Start(protocol.clone(), None, HashMap::new()))
+                .join(unready.send(Message::Start(protocol, None, HashMap::new())))
+                .map(move |results| {
+                    tx.send(results).unwrap();
+                    System::current().stop();
+                })
+                .map_err(|_| System::current().stop());
+            Arbiter::spawn(results);
+        });
+        let (ready, unready) = rx.recv().unwrap();
+        assert!(ready.is_ok());
+        assert!(matches!(unready, Err(Error::NotReady)));
+    }
+
+    #[test]
+    fn unknown_buffers() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::try_new(Config::stub()).unwrap().start();
+            let protocol = Protocol::from(vec![
+                Step::Perfuse(9, Some(Duration::new(5, 0))),
+                Step::Perfuse(0, None),
+            ]);
+            let status = addr.clone();
+            let result = addr
+                .send(Message::Start(protocol, None, HashMap::new()))
+                .and_then(move |result| status.send(GetSnapshot).map(|snapshot| (result, snapshot)))
+                .map(move |results| {
+                    tx.send(results).unwrap();
+                    System::current().stop();
+                })
+                .map_err(|_| System::current().stop());
+            Arbiter::spawn(result);
+        });
+        let (result, snapshot) = rx.recv().unwrap();
+        assert!(matches!(result, Err(Error::UnknownBuffers(ref buffers)) if buffers == &[9]));
+        assert_eq!(snapshot.state, State::Stopped { early: false });
+        assert!(snapshot.uuid.is_none());
+        let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+        let protocol = Protocol::with_step(Step::Perfuse(4, None));
+        assert!(matches!(
+            coord.queue(protocol, None, HashMap::new()),
+            Err(Error::UnknownBuffers(_))
+        ));
+    }
+
+    #[test]
+    fn shutdown_stops_pump() {
+        let mut config = Config::stub();
+        config.settle_delay = Some(Duration::from_millis(20));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(100), move |coord, context| {
+                    let pump = coord.addresses.as_ref().unwrap().pumps[0].clone();
+                    coord.shutdown(context);
+                    // The pump handles messages in order, so this sees it after stopping.
+                    let direction = pump
+                        .send(PumpMessage::SetSpeed(1.0))
+                        .map(move |direction| tx.send(direction.unwrap().is_none()).unwrap())
+                        .map_err(|_| ());
+                    Arbiter::spawn(direction);
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+            addr.do_send(Message::Start(
+                Protocol::with_step(Step::Perfuse(0, None)),
+                None,
+                HashMap::new(),
+            ));
+        });
+        // The system stopped on its own, once the valves settled.
+        assert!(rx.recv().unwrap());
+    }
+
+    #[test]
+    fn close_all_in_one_batch() {
+        use crate::PinWrite;
+        let mut config = Config::stub();
+        config.settle_delay = Some(Duration::from_secs(10));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(100), move |coord, context| {
+                    let valves = coord.addresses.as_ref().unwrap().valves.clone();
+                    for valve in &valves {
+                        valve.output.lock().unwrap().pin.clear_writes();
+                    }
+                    coord.close_all(context);
+                    // Every valve is written before the coordinator yields to any motor.
+                    let writes = valves
+                        .iter()
+                        .map(|valve| {
+                            let output = valve.output.lock().unwrap();
+                            (output.pin.writes(), valve.closed, valve.shut)
+                        })
+                        .collect::<Vec<_>>();
+                    tx.send(writes).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        let writes = rx.recv().unwrap();
+        let waste = Config::stub().waste_motor;
+        assert!(!writes.is_empty());
+        for (index, (writes, closed, shut)) in writes.into_iter().enumerate() {
+            let width = if index == waste { shut } else { closed };
+            assert!(matches!(writes.as_slice(), [PinWrite::Pwm(_, w)] if *w == width));
+        }
+    }
+
+    #[test]
+    fn configured_waste_motor() {
+        let mut config = Config::stub();
+        config.waste_motor = 3;
+        // Long enough that the motors aren't stopped before they're checked.
+        config.settle_delay = Some(Duration::from_secs(10));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(100), move |coord, context| {
+                    coord.shut_waste(context);
+                    coord.open(0, context).unwrap();
+                    coord.open(2, context).unwrap();
+                    let motors = coord.addresses.as_ref().unwrap().motors.clone();
+                    let positions = futures::future::join_all(
+                        motors
+                            .iter()
+                            .map(|motor| motor.send(MotorMessage::GetAngle))
+                            .collect::<Vec<_>>(),
+                    );
+                    Arbiter::spawn(positions.then(move |positions| {
+                        tx.send(positions.unwrap()).unwrap();
+                        System::current().stop();
+                        Ok(())
+                    }));
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        let commanded = rx
+            .recv()
+            .unwrap()
+            .into_iter()
+            .map(|position| position.commanded)
+            .collect::<Vec<_>>();
+        // Buffers 0 and 2 are on the motors either side of the untouched buffer 1; the waste
+        // valve (shut) is last.
+        assert_eq!(commanded, vec![Some(0), None, Some(0), Some(180)]);
+    }
+
+    #[test]
+    fn jog_valve() {
+        /// Sends along the positions each motor was last commanded to.
+        fn send_positions(coord: &Coordinator, tx: mpsc::Sender<Vec<Option<u16>>>, stop: bool) {
+            let motors = coord.addresses.as_ref().unwrap().motors.clone();
+            let positions = futures::future::join_all(
+                motors
+                    .iter()
+                    .map(|motor| motor.send(MotorMessage::GetAngle))
+                    .collect::<Vec<_>>(),
+            );
+            Arbiter::spawn(positions.then(move |positions| {
+                let commanded = positions.unwrap().into_iter().map(|p| p.commanded);
+                tx.send(commanded.collect()).unwrap();
+                if stop {
+                    System::current().stop();
+                }
+                Ok(())
+            }));
+        }
+        let mut config = Config::stub();
+        // Long enough that the motors aren't stopped before they're checked.
+        config.settle_delay = Some(Duration::from_secs(10));
+        let (tx, rx) = mpsc::channel();
+        let (refused_tx, refused_rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let later_tx = tx.clone();
+                context.run_later(Duration::from_millis(100), move |coord, context| {
+                    let jog = |coord: &mut Coordinator, context: &mut CoordContext| {
+                        coord.jog(1, true, Duration::from_millis(100), context)
+                    };
+                    coord.state.status = State::Running;
+                    refused_tx.send(jog(coord, context)).unwrap();
+                    coord.state.status = State::Stopped { early: false };
+                    jog(coord, context).unwrap();
+                    // Only one valve can be jogged at a time.
+                    refused_tx.send(jog(coord, context)).unwrap();
+                    send_positions(coord, tx, false);
+                });
+                context.run_later(Duration::from_millis(300), move |coord, _| {
+                    send_positions(coord, later_tx, true);
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        assert!(matches!(refused_rx.recv().unwrap(), Err(Error::Busy)));
+        assert!(matches!(refused_rx.recv().unwrap(), Err(Error::Busy)));
+        // Buffer 1 is on the motor after the waste valve; it's opened, and then closed again.
+        assert_eq!(rx.recv().unwrap(), vec![None, None, Some(0), None]);
+        assert_eq!(rx.recv().unwrap(), vec![None, None, Some(90), None]);
+    }
+
+    #[test]
+    fn second_pump() {
+        let mut config = Config::stub();
+        let mut wash = config.pumps[0];
+        wash.pins = [9, 10, 11, 12];
+        config.pumps.push(wash);
+        config.volume = Some(Volume::new::<milliliter>(0.01));
+        config.line_clear_delay = Some(Duration::from_millis(500));
+        let protocol = Protocol::from(vec![
+            Step::Perfuse(0, Some(Duration::from_millis(10))),
+            Step::Pumped(1, Box::new(Step::Perfuse(1, None))),
+        ]);
+        let mut unknown = protocol.clone();
+        unknown.remove_step(1);
+        unknown.insert_step(1, Step::Pumped(2, Box::new(Step::Perfuse(1, None))));
+        assert!(matches!(
+            Coordinator::try_new(config.clone()).unwrap().queue(unknown, None, HashMap::new()),
+            Err(Error::UnknownPumps(ref pumps)) if pumps == &[2]
+        ));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    // Skip ahead to the step using the second pump.
+                    let mut remaining: Vec<Action> = protocol.as_program().unwrap().into();
+                    let start = remaining
+                        .iter()
+                        .position(|action| matches!(action, Action::SelectPump(1)))
+                        .unwrap();
+                    coord.state.remaining = remaining.split_off(start);
+                    coord.advance(context).unwrap();
+                });
+                // Long enough for the pump delay to pass, but not the line clearing.
+                context.run_later(Duration::from_millis(2300), move |coord, _| {
+                    assert_eq!(coord.state.pump, 1);
+                    let pumps = coord.addresses.as_ref().unwrap().pumps.clone();
+                    let directions = futures::future::join_all(
+                        pumps
+                            .iter()
+                            .map(|pump| pump.send(PumpMessage::GetDirection))
+                            .collect::<Vec<_>>(),
+                    );
+                    Arbiter::spawn(directions.then(move |directions| {
+                        let directions = directions
+                            .unwrap()
+                            .into_iter()
+                            .map(|direction| direction.unwrap())
+                            .collect::<Vec<_>>();
+                        tx.send(directions).unwrap();
+                        System::current().stop();
+                        Ok(())
+                    }));
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        let directions = rx.recv().unwrap();
+        assert_eq!(directions, vec![None, Some(crate::PumpDirection::Forward)]);
+    }
+
+    #[test]
+    fn cancel_queued_job() {
+        let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+        assert!(matches!(coord.cancel_queued(), Err(Error::NothingQueued)));
+        let running = vec![Action::Sleep(Duration::new(5, 0)), Action::Finish];
+        coord.state.status = State::Running;
+        coord.state.remaining = running.clone();
+        coord
+            .queue(
+                Protocol::with_step(Step::Perfuse(1, None)),
+                None,
+                HashMap::new(),
+            )
+            .unwrap();
+        assert!(coord.state.queued.is_some());
+        assert!(coord.cancel_queued().is_ok());
+        assert!(coord.state.queued.is_none());
+        assert_eq!(coord.state.remaining, running);
+        assert_eq!(coord.status(), State::Running);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn slower_drain() {
+        let normal = Coordinator::try_new(Config::stub()).unwrap();
+        let mut config = Config::stub();
+        config.drain_rate = Some(VolumeRate::new::<milliliter_per_second>(DEFAULT_RATE / 2.0));
+        let slow = Coordinator::try_new(config).unwrap();
+        assert_eq!(normal.drain_speed, 1.0);
+        assert_eq!(slow.drain_speed, 0.5);
+        let (normal, slow) = (normal.drain_time, slow.drain_time);
+        assert!(slow > normal * 2 - Duration::from_millis(1));
+        assert!(slow < normal * 2 + Duration::from_millis(1));
+    }
+
+    #[test]
+    fn projected_timeline() {
+        let coord = Coordinator::try_new(Config::stub()).unwrap();
+        let protocol = Protocol::from(vec![
+            Step::Perfuse(0, Some(Duration::new(60, 0))),
+            Step::Perfuse(1, None),
+        ]);
+        let start = SystemTime::now();
+        let timeline = coord.project(&protocol, start).unwrap();
+        assert_eq!(timeline.len(), 2);
+        assert_eq!(timeline[0].start, start);
+        assert_eq!(timeline[0].end, timeline[1].start);
+        assert!(timeline[0].end > start + Duration::new(60, 0));
+        assert!(timeline[1].end > timeline[1].start);
+        assert!(timeline.iter().all(|projection| !projection.uncertain));
+    }
+
+    #[test]
+    fn exchange_stop() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+                let protocol = Protocol::from(vec![
+                    Step::Perfuse(0, Some(Duration::new(60, 0))),
+                    Step::Perfuse(1, None),
+                ]);
+                coord.state.remaining = protocol.as_program().unwrap().into();
+                coord.advance(context).unwrap();
+                let buffer = coord.state.buffer;
+                coord.state.stop(2).unwrap();
+                tx.send((buffer, coord.state.remaining.clone())).unwrap();
+                System::current().stop();
+                coord
+            });
+        });
+        let (buffer, remaining) = rx.recv().unwrap();
+        assert_eq!(buffer, Some(0));
+        assert_eq!(remaining, vec![Action::Perfuse(2), Action::Finish]);
+    }
+
+    #[test]
+    fn configured_rate() {
+        let normal = Coordinator::try_new(Config::stub()).unwrap();
+        let mut config = Config::stub();
+        config.rate = Some(VolumeRate::new::<milliliter_per_second>(DEFAULT_RATE * 2.0));
+        let fast = Coordinator::try_new(config).unwrap();
+        let perfusion = Action::Perfuse(0);
+        assert!(fast.action_duration(&perfusion) < normal.action_duration(&perfusion));
+        assert!(fast.action_duration(&Action::Drain) < normal.action_duration(&Action::Drain));
+        assert!(fast.fill_time > normal.fill_time / 2 - Duration::from_millis(1));
+        assert!(fast.fill_time < normal.fill_time / 2 + Duration::from_millis(1));
+    }
+
+    #[test]
+    #[cfg(feature = "stub")]
+    fn estop_halts() {
+        let mut config = Config::stub();
+        config.estop_pin = Some(9);
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(config).unwrap();
+                coord.state.status = State::Running;
+                context.run_later(Duration::from_millis(100), |coord, _| {
+                    coord.estop.as_mut().unwrap().input.simulate(true);
+                });
+                context.run_later(Duration::from_millis(300), move |coord, _| {
+                    tx.send(coord.status()).unwrap();
+                    System::current().stop();
+                });
+                coord
+            });
+        });
+        assert_eq!(rx.recv().unwrap(), State::Stopped { early: true });
+    }
+
+    #[test]
+    #[cfg(feature = "stub")]
+    fn estop_held() {
+        let mut config = Config::stub();
+        config.estop_pin = Some(9);
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(config).unwrap();
+                coord.estop.as_mut().unwrap().input.simulate(true);
+                context.run_later(Duration::from_millis(100), |coord, context| {
+                    let protocol = Protocol::with_step(Step::Perfuse(0, None));
+                    let started = coord.start(&protocol, None, HashMap::new(), context);
+                    let at = SystemTime::now() + Duration::from_secs(60);
+                    let scheduled = coord.schedule_start(protocol, None, at, context);
+                    assert!(matches!(started, Err(Error::EmergencyStop)));
+                    assert!(matches!(scheduled, Err(Error::EmergencyStop)));
+                    // A job running while the button is held down is halted, even though the
+                    // button was pressed beforehand.
+                    coord.state.status = State::Running;
+                });
+                context.run_later(Duration::from_millis(300), move |coord, _| {
+                    tx.send(coord.status()).unwrap();
+                    System::current().stop();
+                });
+                coord
+            });
+        });
+        assert_eq!(rx.recv().unwrap(), State::Stopped { early: true });
+    }
+
+    #[test]
+    #[cfg(feature = "stub")]
+    fn motor_failure_halts() {
+        let mut config = Config::stub();
+        config.motor_retries = Some(2);
+        let (tx, rx) = mpsc::channel();
+        let (status_tx, status_rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(config).unwrap();
+                // The valve for buffer 0 (motor 0 is waste).
+                coord.devices.as_mut().unwrap().motors[1].simulate_failure(true);
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    coord.subscribe(Box::new(Collect {
+                        sender: tx,
+                        filter: |message| match message {
+                            StatusMessage::Errored(description) => Some(description.clone()),
+                            _ => None,
+                        },
+                    }));
+                    coord.state.status = State::Running;
+                    coord.open(0, context).unwrap();
+                });
+                context.run_later(Duration::from_millis(200), move |coord, _| {
+                    status_tx.send(coord.status()).unwrap();
+                    System::current().stop();
+                });
+                coord
+            });
+        });
+        let description = rx.recv().unwrap();
+        assert!(description.contains("pin 6"));
+        assert!(description.contains("after 3 attempts"));
+        assert_eq!(status_rx.recv().unwrap(), State::Stopped { early: true });
+    }
+
+    #[test]
+    fn start_queued_job() {
+        let mut config = Config::stub();
+        config.settle_delay = Some(Duration::from_millis(20));
+        let mut metadata = HashMap::new();
+        metadata.insert("sample".to_owned(), "A1".to_owned());
+        let queued = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let finish = |coord: &mut Coordinator, context: &mut CoordContext| {
+                    coord.state.uuid = Some(Uuid::new_v4());
+                    coord.state.status = State::Running;
+                    coord.state.remaining = vec![Action::Finish];
+                    coord.advance(context).unwrap();
+                };
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    // The queued job can't start, but the finished job is still recorded once.
+                    let unknown = Protocol::with_step(Step::Perfuse(9, None));
+                    coord.state.queued = Some((unknown, Uuid::new_v4(), HashMap::new()));
+                    finish(coord, context);
+                    assert_eq!(coord.history().len(), 1);
+                    assert_eq!(coord.status(), State::Stopped { early: false });
+                    let protocol = Protocol::with_step(Step::Perfuse(0, None));
+                    coord.state.queued = Some((protocol, queued, metadata));
+                    finish(coord, context);
+                });
+                context.run_later(Duration::from_millis(100), move |coord, _| {
+                    tx.send((
+                        coord.history(),
+                        coord.state.uuid,
+                        coord.state.metadata.clone(),
+                    ))
+                    .unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        let (history, uuid, metadata) = rx.recv().unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(history
+            .iter()
+            .all(|record| record.state == State::Stopped { early: false }));
+        assert_eq!(uuid, Some(queued));
+        assert_eq!(metadata.get("sample").map(String::as_str), Some("A1"));
+    }
+
+    #[test]
+    fn idle_halt() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    coord.state.uuid = Some(Uuid::new_v4());
+                    coord.state.status = State::Running;
+                    coord.state.completed = vec![Action::Perfuse(0), Action::Drain];
+                    coord.halt(HaltReason::Operator, context).unwrap();
+                    // Nothing is running any more, so this only publishes the halt.
+                    coord.halt(HaltReason::EmergencyStop, context).unwrap();
+                    tx.send(coord.history()).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(Config::stub()).unwrap()
+            });
+        });
+        let history = rx.recv().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].completed, vec![Action::Perfuse(0)]);
+        assert_eq!(history[0].halt_reason, Some(HaltReason::Operator));
+    }
+
+    #[test]
+    fn capped_history() {
+        let mut config = Config::stub();
+        config.history = Some(2);
+        let mut coord = Coordinator::try_new(config).unwrap();
+        let ids = (0..3).map(|_| Uuid::new_v4()).collect::<Vec<_>>();
+        for &id in &ids {
+            coord.state.uuid = Some(id);
+            coord.archive(State::Stopped { early: false }, vec![Action::Finish]);
+        }
+        let history = coord.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].id, ids[2]);
+        assert_eq!(history[1].id, ids[1]);
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn resume_saved_job() {
+        let path = std::env::temp_dir().join(format!("deoxy-{}.json", Uuid::new_v4()));
+        let mut config = Config::stub();
+        config.state_file = Some(path.clone());
+        let protocol = Protocol::from(vec![
+            Step::Perfuse(0, Some(Duration::new(60, 0))),
+            Step::Perfuse(1, None),
+        ]);
+        let program = protocol.as_program().unwrap();
+        let actions: Vec<Action> = program.clone().into();
+        let id = Uuid::new_v4();
+        let mut coord = Coordinator::try_new(config.clone()).unwrap();
+        coord.state.program = Some(program);
+        coord.state.remaining = actions[3..].to_vec();
+        coord.state.completed = actions[..3].to_vec();
+        coord.state.current = Some(actions[2].clone());
+        coord.state.buffer = Some(0);
+        coord.state.uuid = Some(id);
+        coord.state.status = State::Running;
+        coord.persist();
+        let resumed = Coordinator::try_new(config.clone()).unwrap();
+        assert!(resumed.restored);
+        assert_eq!(resumed.state.uuid, Some(id));
+        assert_eq!(resumed.state.buffer, Some(0));
+        // The interrupted action is run again.
+        assert_eq!(resumed.state.completed, actions[..2].to_vec());
+        assert_eq!(resumed.state.remaining, actions[2..].to_vec());
+        // Finished jobs aren't resumed.
+        coord.state.status = State::Stopped { early: false };
+        coord.persist();
+        let fresh = Coordinator::try_new(config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!fresh.restored);
+        assert!(fresh.state.remaining.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn resume_after_notification() {
+        let path = std::env::temp_dir().join(format!("deoxy-{}.json", Uuid::new_v4()));
+        let mut config = Config::stub();
+        config.state_file = Some(path.clone());
+        let notify = Action::Notify(Notification {
+            subject: "Check".to_owned(),
+            message: "Check the sample.".to_owned(),
+        });
+        let sleep = Action::Sleep(Duration::new(60, 0));
+        let remaining = vec![notify.clone(), sleep.clone(), Action::Finish];
+        let saving = config.clone();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(saving).unwrap();
+                coord.state.status = State::Running;
+                coord.state.remaining = remaining;
+                // Notifying moves straight on to sleeping, which is where the job is saved.
+                coord.advance(context).unwrap();
+                System::current().stop();
+                coord
+            });
+        });
+        let resumed = Coordinator::try_new(config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(resumed.restored);
+        // Only the sleep is run again.
+        assert_eq!(resumed.state.completed, vec![notify]);
+        assert_eq!(resumed.state.remaining, vec![sleep, Action::Finish]);
+    }
+
+    #[test]
+    fn skip_hail() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+                coord.state.buffer = Some(0);
+                coord.state.remaining = vec![
+                    Action::Hail,
+                    Action::Sleep(Duration::new(60, 0)),
+                    Action::Drain,
+                    Action::Perfuse(1),
+                    Action::Finish,
+                ];
+                coord.advance(context).unwrap();
+                let waiting = coord.status();
+                let skipped = coord.skip(context).unwrap();
+                let state = &coord.state;
+                let progress = (state.current.clone(), state.remaining.clone(), state.buffer);
+                // Now that we're running, there's nothing to skip.
+                let again = coord.skip(context).unwrap();
+                tx.send((waiting, skipped, again, progress)).unwrap();
+                System::current().stop();
+                coord
+            });
+        });
+        let (waiting, skipped, again, (current, remaining, buffer)) = rx.recv().unwrap();
+        assert_eq!(waiting, State::Waiting);
+        assert!(skipped);
+        assert!(!again);
+        assert_eq!(current, Some(Action::Drain));
+        assert_eq!(remaining, vec![Action::Perfuse(1), Action::Finish]);
+        assert_eq!(buffer, Some(0));
+    }
+
+    #[test]
+    fn remaining_duration() {
+        let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+        coord.state.status = State::Running;
+        coord.state.remaining = vec![
+            Action::Sleep(Duration::from_secs(600)),
+            Action::Drain,
+            Action::Finish,
+        ];
+        let drain = coord.action_duration(&Action::Drain).unwrap();
+        assert_eq!(
+            coord.remaining_duration(),
+            Some(Duration::from_secs(600) + drain)
+        );
+        coord.state.remaining.insert(1, Action::Hail);
+        assert_eq!(coord.remaining_duration(), None);
+    }
+
+    #[test]
+    fn prime_is_timed() {
+        let coord = Coordinator::try_new(Config::stub()).unwrap();
+        let prime = Action::Prime(0, Duration::from_secs(30));
+        assert_eq!(
+            coord.action_duration(&prime),
+            Some(*PUMP_DELAY + Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn configured_settle_delay() {
+        let mut config = Config::stub();
+        config.settle_delay = Some(Duration::from_millis(20));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(100), move |coord, _| {
+                    tx.send(coord.state.current.clone()).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Start(protocol, None, HashMap::new()));
+        });
+        // With the default five-second delay, the job wouldn't have started yet.
+        assert_eq!(rx.recv().unwrap(), Some(Action::Perfuse(0)));
+    }
+
+    #[test]
+    fn halted_while_settling() {
+        let mut config = Config::stub();
+        config.settle_delay = Some(Duration::from_millis(50));
+        let (tx, rx) = mpsc::channel();
+        let (busy_tx, busy_rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(150), move |coord, context| {
+                    let stopped = coord.is_stopped();
+                    let uuid = coord.state.uuid;
+                    let completed = coord.state.completed.clone();
+                    let protocol = Protocol::with_step(Step::Perfuse(1, None));
+                    let restart = coord.start(&protocol, None, HashMap::new(), context);
+                    tx.send((stopped, uuid, completed, restart)).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Start(protocol.clone(), None, HashMap::new()));
+            // Starting again while the valves settle is refused, like starting mid-job.
+            let again = addr
+                .send(Message::Start(protocol, None, HashMap::new()))
+                .map(move |result| busy_tx.send(result).unwrap())
+                .map_err(|_| ());
+            Arbiter::spawn(again);
+            addr.do_send(Message::Halt);
+        });
+        assert!(matches!(busy_rx.recv().unwrap(), Err(Error::Busy)));
+        let (stopped, uuid, completed, restart) = rx.recv().unwrap();
+        // The job was never given an ID, so advance never ran.
+        assert!(stopped);
+        assert_eq!(uuid, None);
+        assert!(completed.is_empty());
+        assert!(restart.is_ok());
+    }
+
+    #[test]
+    fn stopped_after_finish() {
+        let mut config = Config::stub();
+        config.volume = Some(Volume::new::<milliliter>(0.01));
+        config.settle_delay = Some(Duration::from_millis(10));
+        config.line_clear_delay = Some(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                // Long enough for the pump delay and the fill to pass.
+                context.run_later(Duration::from_millis(2500), move |coord, context| {
+                    let stopped = coord.is_stopped();
+                    let current = coord.state.current.clone();
+                    let protocol = Protocol::with_step(Step::Perfuse(1, None));
+                    let restart = coord.start(&protocol, None, HashMap::new(), context);
+                    tx.send((stopped, current, restart)).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Start(protocol, None, HashMap::new()));
+        });
+        let (stopped, current, restart) = rx.recv().unwrap();
+        assert!(stopped);
+        assert_eq!(current, None);
+        assert!(restart.is_ok());
+    }
+
+    #[test]
+    fn clear_mid_prompt() {
+        let notification = |subject: &str| Notification {
+            subject: subject.to_owned(),
+            message: String::new(),
+        };
+        let protocol = Protocol::from(vec![
+            Step::PerfusePrompt(
+                0,
+                notification("begin"),
+                Duration::new(60, 0),
+                notification("end"),
+            ),
+            Step::Perfuse(1, None),
+        ]);
+        let actions: Vec<Action> = protocol.as_program().unwrap().into();
+        let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+        // Stopping while perfusing, before the prompt has been sent
+        coord.state.remaining = actions[1..].to_vec();
+        coord.state.clear().unwrap();
+        assert_eq!(coord.state.remaining, vec![Action::Finish]);
+        // Stopping while sleeping, before the second prompt has been sent
+        coord.state.remaining = actions[4..].to_vec();
+        coord.state.clear().unwrap();
+        assert_eq!(coord.state.remaining, vec![Action::Finish]);
+        // Stopping while draining still perfuses the next buffer
+        let drain = actions
+            .iter()
+            .position(|action| *action == Action::Drain)
+            .unwrap();
+        coord.state.remaining = actions[drain + 1..].to_vec();
+        coord.state.clear().unwrap();
+        assert_eq!(
+            coord.state.remaining,
+            vec![Action::Perfuse(1), Action::Finish]
+        );
+    }
+
+    /// The actions a simulation advanced through.
+    fn advanced(published: &[StatusMessage]) -> Vec<Action> {
+        published
+            .iter()
+            .filter_map(|message| match message {
+                StatusMessage::Advanced { action, .. } => Some(action.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn simulated_stop() {
+        let minute = Duration::new(60, 0);
+        let program = ProtocolBuilder::new()
+            .perfuse(0, minute)
+            .perfuse(1, minute)
+            .bath(2)
+            .build()
+            .and_then(|protocol| protocol.as_program())
+            .unwrap();
+        let published = Coordinator::simulate(program.clone(), vec![]);
+        let actions: Vec<Action> = program.clone().into();
+        assert_eq!(advanced(&published), actions);
+        // Stopping while perfusing finishes once the perfusion is done.
+        let published = Coordinator::simulate(program.clone(), vec![(1, Message::Stop)]);
+        assert_eq!(
+            advanced(&published),
+            vec![Action::Perfuse(0), Action::Finish]
+        );
+        assert!(matches!(
+            published[1],
+            StatusMessage::StopQueued { early: false }
+        ));
+        // Exchanging into another buffer perfuses it before finishing.
+        let published = Coordinator::simulate(program, vec![(1, Message::ExchangeStop(2))]);
+        assert_eq!(
+            advanced(&published),
+            vec![Action::Perfuse(0), Action::Perfuse(2), Action::Finish]
+        );
+    }
+
+    #[test]
+    fn simulated_halt() {
+        let notification = Notification {
+            subject: "Check".to_owned(),
+            message: String::new(),
+        };
+        let program = ProtocolBuilder::new()
+            .prompt(0, notification.clone(), Duration::new(60, 0), notification)
+            .bath(1)
+            .build()
+            .and_then(|protocol| protocol.as_program())
+            .unwrap();
+        // Without anyone to continue it, the program waits at the first prompt.
+        let published = Coordinator::simulate(program.clone(), vec![]);
+        assert!(matches!(published.last(), Some(StatusMessage::Paused)));
+        assert_eq!(advanced(&published).len(), 3);
+        let published =
+            Coordinator::simulate(program, vec![(3, Message::Continue), (4, Message::Halt)]);
+        assert!(published
+            .iter()
+            .any(|message| matches!(message, StatusMessage::Continued)));
+        assert!(matches!(
+            published.last(),
+            Some(StatusMessage::Halted { .. })
+        ));
+        assert_eq!(
+            advanced(&published).last(),
+            Some(&Action::Sleep(Duration::new(60, 0)))
+        );
+        assert!(!advanced(&published).contains(&Action::Finish));
+    }
+
+    #[test]
+    fn subscribed_before_start() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+                // The coordinator has yet to start, so there's nowhere to send this yet.
+                coord.subscribe(Box::new(Collect {
+                    sender: tx,
+                    filter: |message| match message {
+                        StatusMessage::Started(protocol) => Some(protocol.steps().to_vec()),
+                        _ => None,
+                    },
+                }));
+                context.run_later(Duration::from_millis(100), |_, _| System::current().stop());
+                coord
+            });
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Start(protocol, None, HashMap::new()));
+        });
+        assert_eq!(rx.recv().unwrap(), vec![Step::Perfuse(0, None)]);
+    }
+
+    #[test]
+    fn panicking_subscriber() {
+        /// A subscriber which reports each update it's handed, then panics.
+        #[derive(Debug)]
+        struct Panicky(mpsc::Sender<()>);
+        impl Update for Panicky {
+            fn handle(&self, _status: &Status, _coord: &Subscribers) {
+                let _ = self.0.send(());
+                panic!("Not today");
             }
         }
+        let (panicked_tx, panicked_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+                coord.subscribe(Box::new(Panicky(panicked_tx)));
+                coord.subscribe(Box::new(Collect {
+                    sender: tx,
+                    filter: |message| match message {
+                        StatusMessage::Skipped => Some(()),
+                        _ => None,
+                    },
+                }));
+                context.run_later(Duration::from_millis(10), |coord, context| {
+                    for _ in 0..5 {
+                        coord.publish(StatusMessage::Skipped, context);
+                    }
+                });
+                context.run_later(Duration::from_millis(100), |_, _| System::current().stop());
+                coord
+            });
+        });
+        // The other subscriber got every update, while the panicking one was dropped once it had
+        // panicked too many times.
+        assert_eq!(rx.try_iter().count(), 5);
+        assert_eq!(
+            panicked_rx.try_iter().count(),
+            MAX_SUBSCRIBER_FAILURES as usize
+        );
+    }
+
+    #[test]
+    fn notifications_published() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                // Wait for the subscribers to start.
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    coord.subscribe(Box::new(Collect {
+                        sender: tx,
+                        filter: |message| match message {
+                            StatusMessage::Notified(notification) => Some(notification.clone()),
+                            _ => None,
+                        },
+                    }));
+                    coord.state.status = State::Running;
+                    coord.state.remaining = vec![
+                        Action::Notify(Notification {
+                            subject: "Add stain".to_owned(),
+                            message: "Add the stain, then continue.".to_owned(),
+                        }),
+                        Action::Hail,
+                        Action::Finish,
+                    ];
+                    coord.try_advance(context);
+                });
+                context.run_later(Duration::from_millis(100), |_, _| System::current().stop());
+                Coordinator::try_new(Config::stub()).unwrap()
+            });
+        });
+        let notification = rx.recv().unwrap();
+        assert_eq!(notification.subject, "Add stain");
+        assert_eq!(notification.message, "Add the stain, then continue.");
+    }
+
+    #[test]
+    fn paused_mid_sleep() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(10), |coord, context| {
+                    coord.state.status = State::Running;
+                    coord.state.remaining =
+                        vec![Action::Sleep(Duration::from_millis(200)), Action::Finish];
+                    coord.try_advance(context);
+                });
+                // Halfway through the wait, pause for longer than the rest of it.
+                context.run_later(Duration::from_millis(110), |coord, _| {
+                    coord.pause();
+                });
+                let status = tx.clone();
+                context.run_later(Duration::from_millis(260), move |coord, context| {
+                    status.send(coord.status()).unwrap();
+                    coord.resume(context).unwrap();
+                });
+                // The rest of the wait (about 100 ms) hasn't yet elapsed since resuming...
+                let status = tx.clone();
+                context.run_later(Duration::from_millis(320), move |coord, _| {
+                    status.send(coord.status()).unwrap();
+                });
+                // ...but now it has.
+                context.run_later(Duration::from_millis(420), move |coord, _| {
+                    tx.send(coord.status()).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(Config::stub()).unwrap()
+            });
+        });
+        let statuses = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                State::Waiting,
+                State::Running,
+                State::Stopped { early: false }
+            ]
+        );
+    }
+
+    #[test]
+    fn timeline_mid_run() {
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(10), |coord, context| {
+                    coord.state.status = State::Running;
+                    coord.state.remaining = vec![
+                        Action::Notify(Notification {
+                            subject: "Check".to_owned(),
+                            message: "Check the sample.".to_owned(),
+                        }),
+                        Action::Sleep(Duration::from_millis(200)),
+                        Action::Perfuse(1),
+                        Action::Finish,
+                    ];
+                    coord.try_advance(context);
+                });
+                context.run_later(Duration::from_millis(60), move |coord, _| {
+                    tx.send(coord.timeline()).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(Config::stub()).unwrap()
+            });
+        });
+        let timeline = rx.recv().unwrap();
+        let progress = timeline
+            .iter()
+            .map(|entry| entry.progress)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            progress,
+            vec![
+                Progress::Completed,
+                Progress::Current,
+                Progress::Remaining,
+                Progress::Remaining
+            ]
+        );
+        assert_eq!(timeline[1].kind, "sleep");
+        assert_eq!(timeline[1].duration, Some(Duration::from_millis(200)));
+        assert_eq!(timeline[2].buffer.as_deref(), Some("Buffer 1"));
+        assert_eq!(timeline[3].duration, Some(Duration::new(0, 0)));
+    }
+
+    #[test]
+    fn errors_published() {
+        let mut config = Config::stub();
+        config.interlocks = vec![(0, 1)];
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                // Wait for the subscribers to start.
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    coord.subscribe(Box::new(Collect {
+                        sender: tx,
+                        filter: |message| match message {
+                            StatusMessage::Errored(description) => Some(description.clone()),
+                            _ => None,
+                        },
+                    }));
+                    // Perfusing will fail, since an interlocked valve is open.
+                    coord.opened.insert(1);
+                    coord.state.status = State::Running;
+                    coord.state.remaining = vec![Action::Perfuse(0), Action::Finish];
+                    coord.try_advance(context);
+                });
+                context.run_later(Duration::from_millis(100), |_, _| System::current().stop());
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        let description = rx.recv().unwrap();
+        assert!(description.contains("Perfuse(0)"));
+        assert!(description.contains("Interlock"));
+    }
+
+    #[test]
+    fn first_action_fails() {
+        let mut config = Config::stub();
+        config.interlocks = vec![(0, 1)];
+        config.settle_delay = Some(Duration::from_millis(20));
+        let (tx, rx) = mpsc::channel();
+        let (stopped_tx, stopped_rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                // Wait for the subscribers to start.
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    coord.subscribe(Box::new(Collect {
+                        sender: tx,
+                        filter: |message| match message {
+                            StatusMessage::Errored(description) => Some(description.clone()),
+                            _ => None,
+                        },
+                    }));
+                    let protocol = Protocol::with_step(Step::Perfuse(0, None));
+                    coord
+                        .start(&protocol, None, HashMap::new(), context)
+                        .unwrap();
+                    // Perfusing will fail once the valves have settled, since an interlocked
+                    // valve is open by then.
+                    coord.opened.insert(1);
+                });
+                context.run_later(Duration::from_millis(100), move |coord, _| {
+                    stopped_tx.send(coord.is_stopped()).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        assert!(rx.recv().unwrap().contains("Interlock"));
+        assert!(stopped_rx.recv().unwrap());
+    }
+
+    #[test]
+    fn halt_reasons() {
+        let mut config = Config::stub();
+        config.interlocks = vec![(0, 1)];
+        let (tx, rx) = mpsc::channel();
+        let (history_tx, history_rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    coord.subscribe(Box::new(Collect {
+                        sender: tx,
+                        filter: |message| match message {
+                            StatusMessage::Halted { reason } => Some(reason.clone()),
+                            _ => None,
+                        },
+                    }));
+                    // Perfusing will fail, since an interlocked valve is open.
+                    coord.opened.insert(1);
+                    coord.state.uuid = Some(Uuid::new_v4());
+                    coord.state.status = State::Running;
+                    coord.state.remaining = vec![Action::Perfuse(0), Action::Finish];
+                    coord.try_advance(context);
+                });
+                context.run_later(Duration::from_millis(50), |coord, context| {
+                    coord.state.uuid = Some(Uuid::new_v4());
+                    coord.state.status = State::Running;
+                    context.notify(Message::Halt);
+                });
+                context.run_later(Duration::from_millis(100), move |coord, _| {
+                    history_tx.send(coord.history()).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        let reasons = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(reasons.len(), 2);
+        assert!(matches!(
+            &reasons[0],
+            HaltReason::Error(description) if description.contains("Interlock")
+        ));
+        assert_eq!(reasons[1], HaltReason::Operator);
+        // The reasons are kept with the jobs' records, newest first.
+        let history = history_rx.recv().unwrap();
+        assert_eq!(history[0].halt_reason, Some(HaltReason::Operator));
+        assert_eq!(history[1].halt_reason, Some(reasons[0].clone()));
+    }
+
+    #[test]
+    fn persistent_halt_failure_published() {
+        use actix_web::actix::ActorContext;
+        let mut config = Config::stub();
+        config.interlocks = vec![(0, 1)];
+        config.halt_retries = Some(2);
+        config.halt_backoff = Some(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(10), move |coord, _| {
+                    coord.subscribe(Box::new(Collect {
+                        sender: tx,
+                        filter: |message| match message {
+                            StatusMessage::Errored(description) => Some(description.clone()),
+                            _ => None,
+                        },
+                    }));
+                    // A pump that has already stopped can't be told to stop.
+                    let pump = Pump::create(|context| {
+                        context.stop();
+                        Pump::try_new([1, 2, 3, 4]).unwrap()
+                    });
+                    coord.addresses.as_mut().unwrap().pumps = vec![pump];
+                });
+                context.run_later(Duration::from_millis(50), |coord, context| {
+                    // Perfusing will fail, since an interlocked valve is open.
+                    coord.opened.insert(1);
+                    coord.state.status = State::Running;
+                    coord.state.remaining = vec![Action::Perfuse(0), Action::Finish];
+                    coord.try_advance(context);
+                });
+                context.run_later(Duration::from_millis(200), |_, _| System::current().stop());
+                Coordinator::try_new(config).unwrap()
+            });
+        });
+        let descriptions = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(descriptions.len(), 2);
+        assert!(descriptions[0].contains("Interlock"));
+        assert!(descriptions[1].contains("after 3 attempts"));
+    }
+
+    #[test]
+    fn progress_published() {
+        let mut config = Config::stub();
+        config.volume = Some(Volume::new::<milliliter>(0.01));
+        config.settle_delay = Some(Duration::from_millis(10));
+        config.line_clear_delay = Some(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(|context| {
+                // Long enough for the pump delay and the fill to pass.
+                context.run_later(Duration::from_millis(2500), |_, _| System::current().stop());
+                Coordinator::try_new(config).unwrap()
+            });
+            addr.do_send(Message::Subscribe(Box::new(Collect {
+                sender: tx,
+                filter: |message| match message {
+                    StatusMessage::Advanced {
+                        completed, total, ..
+                    } => Some((*completed, *total)),
+                    _ => None,
+                },
+            })));
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Start(protocol, None, HashMap::new()));
+        });
+        let progress = rx.iter().collect::<Vec<_>>();
+        assert_eq!(progress, vec![(0, 2), (1, 2)]);
+    }
+
+    #[test]
+    fn simulated_run() {
+        let mut config = Config::stub();
+        config.simulate = true;
+        config.volume = Some(Volume::new::<milliliter>(0.01));
+        config.settle_delay = Some(Duration::from_millis(10));
+        config.line_clear_delay = Some(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+        let (positions_tx, positions_rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(|context| {
+                // Long enough for the pump delay and the fill to pass.
+                context.run_later(Duration::from_millis(2500), move |coord, _| {
+                    let motors = coord.addresses.as_ref().unwrap().motors.clone();
+                    let positions = futures::future::join_all(
+                        motors
+                            .iter()
+                            .map(|motor| motor.send(MotorMessage::GetAngle))
+                            .collect::<Vec<_>>(),
+                    );
+                    Arbiter::spawn(positions.then(move |positions| {
+                        positions_tx.send(positions.unwrap()).unwrap();
+                        System::current().stop();
+                        Ok(())
+                    }));
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+            addr.do_send(Message::Subscribe(Box::new(Collect {
+                sender: tx,
+                filter: |message| match message {
+                    StatusMessage::Advanced { action, .. } => Some(action.clone()),
+                    _ => None,
+                },
+            })));
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Start(protocol, None, HashMap::new()));
+        });
+        let actions = rx.iter().collect::<Vec<_>>();
+        assert_eq!(actions, vec![Action::Perfuse(0), Action::Finish]);
+        // No motor was ever told where to go.
+        let positions = positions_rx.recv().unwrap();
+        assert_eq!(positions.len(), 4);
+        assert!(positions
+            .iter()
+            .all(|position| position.commanded.is_none()));
+    }
+
+    #[test]
+    fn perfusions_counted() {
+        let mut config = Config::stub();
+        config.volume = Some(Volume::new::<milliliter>(0.01));
+        config.settle_delay = Some(Duration::from_millis(10));
+        config.line_clear_delay = Some(Duration::from_millis(10));
+        let protocol = Protocol::from(vec![
+            Step::Perfuse(1, Some(Duration::from_millis(10))),
+            Step::Perfuse(0, Some(Duration::from_millis(10))),
+            Step::Perfuse(1, None),
+        ]);
+        let expected = protocol
+            .steps()
+            .iter()
+            .filter(|step| matches!(step, Step::Perfuse(_, _)))
+            .count();
+        let id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                context.run_interval(Duration::from_millis(100), move |coord, _| {
+                    if let Some(record) = coord.state.history.back() {
+                        tx.send((record.metrics.clone(), coord.metrics(id)))
+                            .unwrap();
+                        System::current().stop();
+                    }
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+            addr.do_send(Message::Start(protocol, Some(id), HashMap::new()));
+        });
+        let (metrics, found) = rx.recv().unwrap();
+        assert_eq!(metrics.perfusions.values().sum::<usize>(), expected);
+        assert_eq!(metrics.perfusions.get(&1), Some(&2));
+        assert!(metrics.started.is_some());
+        assert!(metrics.pump_forward > Duration::new(0, 0));
+        assert_eq!(found, Some(metrics));
+    }
+
+    /// Schedules a bath to start shortly, optionally halting right away, and returns the
+    /// published events of interest along with the label of the job that ended up running.
+    fn run_scheduled(halt: bool) -> (Vec<&'static str>, Option<Uuid>) {
+        let mut config = Config::stub();
+        config.settle_delay = Some(Duration::from_millis(10));
+        let (tx, rx) = mpsc::channel();
+        let (uuid_tx, uuid_rx) = mpsc::channel();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(400), move |coord, _| {
+                    uuid_tx.send(coord.state.uuid).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(config).unwrap()
+            });
+            addr.do_send(Message::Subscribe(Box::new(Collect {
+                sender: tx,
+                filter: |message| match message {
+                    StatusMessage::Scheduled { .. } => Some("scheduled"),
+                    StatusMessage::Started(_) => Some("started"),
+                    StatusMessage::Halted { .. } => Some("halted"),
+                    _ => None,
+                },
+            })));
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            let at = SystemTime::now() + Duration::from_millis(200);
+            addr.do_send(Message::ScheduleStart(protocol, None, at));
+            if halt {
+                addr.do_send(Message::Halt);
+            }
+        });
+        (rx.iter().collect(), uuid_rx.recv().unwrap())
+    }
+
+    #[test]
+    fn scheduled_start() {
+        let (events, uuid) = run_scheduled(false);
+        assert_eq!(events, vec!["scheduled", "started"]);
+        assert!(uuid.is_some());
+    }
+
+    #[test]
+    fn scheduled_start_cancelled() {
+        let (events, uuid) = run_scheduled(true);
+        assert_eq!(events, vec!["scheduled", "halted"]);
+        assert_eq!(uuid, None);
+    }
+
+    #[test]
+    fn slow_mail() {
+        /// A transport which takes a long time to deliver anything.
+        #[derive(Debug)]
+        struct Slow;
+        impl mail::Transport for Slow {
+            fn send(&mut self, _: &Email) -> std::io::Result<()> {
+                std::thread::sleep(Duration::from_secs(1));
+                Ok(())
+            }
+        }
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Coordinator::create(|context| {
+                context.run_later(Duration::from_millis(10), move |coord, context| {
+                    coord.addresses.as_mut().unwrap().mailer =
+                        actix_web::actix::SyncArbiter::start(1, || Mailer {
+                            transport: Box::new(Slow),
+                        });
+                    coord.state.remaining = vec![
+                        Action::Notify(Notification {
+                            subject: "Hello".to_owned(),
+                            message: String::new(),
+                        }),
+                        Action::Hail,
+                        Action::Finish,
+                    ];
+                    let start = std::time::Instant::now();
+                    coord.advance(context).unwrap();
+                    tx.send((start.elapsed(), coord.status())).unwrap();
+                    System::current().stop();
+                });
+                Coordinator::try_new(Config::stub()).unwrap()
+            });
+        });
+        let (elapsed, status) = rx.recv().unwrap();
+        assert!(elapsed < Duration::from_millis(500));
+        // The program moved on to the next step without waiting for the email to be sent.
+        assert_eq!(status, State::Waiting);
     }
 }
@@ -1,20 +1,454 @@
-use std::time::Duration;
+use crate::{webhook::Endpoint, MotorId, DEFAULT_RATE};
+
+use uom::si::{
+    f64::{Volume, VolumeRate},
+    volume_rate::milliliter_per_second,
+};
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    path::PathBuf,
+    time::Duration,
+};
+#[cfg(feature = "use_serde")]
+use std::{fs, io, path::Path};
 
 /// Encodes the system configuration.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
 pub struct Config {
-    /// The pump configuration.
-    pub pump: PumpConfig,
+    /// The pump configurations.
+    ///
+    /// The first pump is used unless a step says otherwise. A single pump may also be given
+    /// (as `pump`), as in configurations written before more than one was supported.
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(alias = "pump", deserialize_with = "one_or_many")
+    )]
+    pub pumps: Vec<PumpConfig>,
     /// The motor configurations.
     pub motors: Vec<MotorConfig>,
+    /// The motor (by index) controlling the waste valve (the first, if unspecified).
+    ///
+    /// The rest of the motors control the buffer valves, in order.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub waste_motor: MotorId,
     /// The administrative users of the machine.
     #[cfg_attr(feature = "use_serde", serde(default))]
     pub admins: Vec<String>,
+    /// The buffer to perfuse if a run is aborted while the sample might be dry.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub rescue_buffer: Option<MotorId>,
+    /// The ambient sensor configuration, if conditions should be recorded during runs.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub ambient: Option<AmbientConfig>,
+    /// Pairs of valves (by buffer) which must never be open at the same time.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub interlocks: Vec<(MotorId, MotorId)>,
+    /// How long each buffer should sit (without flow) after filling, before it is drained.
+    #[cfg_attr(feature = "use_serde", serde(default, with = "deoxy_core::duration"))]
+    pub soak: Duration,
+    /// The volume of buffer needed to fill the chamber (in SI units, m³).
+    ///
+    /// If unspecified, the default of 500 mL is used.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub volume: Option<Volume>,
+    /// The rate at which buffers are perfused (in SI units, m³/s).
+    ///
+    /// If unspecified, the default of 3.75 mL/s is used.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub rate: Option<VolumeRate>,
+    /// The rate at which buffers should be drained (in SI units, m³/s).
+    ///
+    /// If unspecified, buffers are drained at the same rate as they are perfused. A slower rate
+    /// slows the pump accordingly when draining; since the pump can't run faster than the
+    /// perfusion rate, a faster one is refused.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub drain_rate: Option<VolumeRate>,
+    /// The input pin connected to the emergency stop button (high when pressed), if any.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub estop_pin: Option<u16>,
+    /// How many ended jobs to keep records of (50 if unspecified).
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub history: Option<usize>,
+    /// The file to save job progress to, so that an interrupted job can be resumed.
+    ///
+    /// This has no effect unless the `use_serde` feature is enabled.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub state_file: Option<PathBuf>,
+    /// How long valves are given to move before their motors are stopped (5 s if unspecified).
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, with = "deoxy_core::duration::option")
+    )]
+    pub settle_delay: Option<Duration>,
+    /// How long the waste line is cleared for after filling (10 s if unspecified).
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, with = "deoxy_core::duration::option")
+    )]
+    pub line_clear_delay: Option<Duration>,
+    /// How email notifications are sent (through `sendmail`, from `deoxy@hmltn.me`, if
+    /// unspecified).
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub mail: Option<MailConfig>,
+    /// The URL each status update is posted to, as JSON (`{"uuid", "state", "message"}`), if any.
+    ///
+    /// Only plain `http://` URLs are supported. This has no effect unless the `use_serde` feature
+    /// is enabled.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub webhook_url: Option<String>,
+    /// Who may control the machine through the server (anyone, if unspecified).
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub auth: Option<AuthConfig>,
+    /// Where the server listens (`127.0.0.1:1957`, if unspecified), and which web frontends may
+    /// use it.
+    ///
+    /// With several chambers, the first chamber's setting is used.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub server: Option<ServerConfig>,
+    /// How many times a motor retries a move it couldn't make before giving up and halting the
+    /// job (3 if unspecified).
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub motor_retries: Option<u32>,
+    /// How many more times halting is attempted if it fails (5 if unspecified).
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub halt_retries: Option<u32>,
+    /// How long to wait between attempts to halt (200 ms if unspecified).
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, with = "deoxy_core::duration::option")
+    )]
+    pub halt_backoff: Option<Duration>,
+    /// The longest any perfusion, prompt, or wait may last, so that a mistyped duration can't
+    /// leave a sample perfusing overnight (no limit if unspecified).
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, with = "deoxy_core::duration::option")
+    )]
+    pub max_step_duration: Option<Duration>,
+    /// Whether jobs should only be simulated, running through every step without moving the
+    /// motors or pump (or sending email or webhook notifications).
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub simulate: bool,
+}
+
+/// Represents a mistake in the configuration.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigError {
+    /// The given pins are each assigned to more than one device.
+    DuplicatePins(Vec<u16>),
+    /// The signal range of the given motor (by index) doesn't increase.
+    InvertedRange(usize),
+    /// The given motor (by index) has a period of zero.
+    ZeroPeriod(usize),
+    /// The given motor (by index) has a range of motion of zero degrees.
+    ZeroRangeDegrees(usize),
+    /// No pumps are configured.
+    NoPumps,
+    /// The waste valve is assigned to the given motor (by index), which isn't configured.
+    UnknownWasteMotor(usize),
+    /// The sample is to be rescued with the given buffer, which isn't configured.
+    UnknownRescueBuffer(MotorId),
+    /// The given webhook URL isn't a plain `http://` URL.
+    InvalidWebhook(String),
+    /// The volume needed to fill the chamber isn't positive.
+    InvalidVolume,
+    /// The perfusion or drain rate isn't positive.
+    InvalidRate,
+    /// The drain rate is faster than the perfusion rate.
+    FastDrain,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DuplicatePins(pins) => {
+                let pins = pins.iter().map(u16::to_string).collect::<Vec<_>>();
+                write!(f, "Pins assigned more than once: {}", pins.join(", "))
+            }
+            Self::InvertedRange(motor) => {
+                write!(
+                    f,
+                    "Motor {} has a signal range that doesn't increase",
+                    motor
+                )
+            }
+            Self::ZeroPeriod(motor) => write!(f, "Motor {} has a period of zero", motor),
+            Self::ZeroRangeDegrees(motor) => {
+                write!(f, "Motor {} has a range of motion of zero degrees", motor)
+            }
+            Self::NoPumps => write!(f, "No pumps are configured"),
+            Self::UnknownWasteMotor(motor) => {
+                write!(
+                    f,
+                    "The waste valve is assigned to motor {}, which isn't configured",
+                    motor
+                )
+            }
+            Self::UnknownRescueBuffer(buffer) => {
+                write!(
+                    f,
+                    "The sample is to be rescued with buffer {}, which isn't configured",
+                    buffer
+                )
+            }
+            Self::InvalidWebhook(url) => write!(f, "The webhook URL {} isn't an http:// URL", url),
+            Self::InvalidVolume => write!(f, "The chamber's volume must be positive"),
+            Self::InvalidRate => write!(f, "The perfusion and drain rates must be positive"),
+            Self::FastDrain => write!(f, "The drain rate is faster than the perfusion rate"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// The labels of the motors, by id.
+    ///
+    /// These can be used to name buffers for display, as with
+    /// [`Protocol::resolve_labels`](struct.Protocol.html#method.resolve_labels).
+    pub fn labels(&self) -> Vec<Option<String>> {
+        self.motors
+            .iter()
+            .map(|motor| motor.label.clone())
+            .collect()
+    }
+    /// Checks the configuration for mistakes that would make the hardware misbehave.
+    ///
+    /// There must be at least one pump, no pin may be used by more than one device (pump, motor,
+    /// or emergency stop button), the waste valve must be one of the motors (and the rescue
+    /// buffer one of the others), each motor must have a nonzero period and range of motion and an
+    /// increasing signal range, and the chamber's volume and the perfusion and drain rates must be
+    /// positive (with draining no faster than perfusion).
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.pumps.is_empty() {
+            return Err(ConfigError::NoPumps);
+        }
+        let mut seen = HashSet::new();
+        let duplicates = self
+            .pumps
+            .iter()
+            .flat_map(|pump| pump.pins.iter())
+            .chain(self.motors.iter().map(|motor| &motor.pin))
+            .chain(self.estop_pin.iter())
+            .filter(|&&pin| !seen.insert(pin))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        if !duplicates.is_empty() {
+            return Err(ConfigError::DuplicatePins(duplicates.into_iter().collect()));
+        }
+        if self.waste_motor >= self.motors.len() {
+            return Err(ConfigError::UnknownWasteMotor(self.waste_motor));
+        }
+        // Every motor but the waste valve controls a buffer.
+        if let Some(buffer) = self
+            .rescue_buffer
+            .filter(|&buffer| buffer + 1 >= self.motors.len())
+        {
+            return Err(ConfigError::UnknownRescueBuffer(buffer));
+        }
+        for (index, motor) in self.motors.iter().enumerate() {
+            if motor.period == Duration::new(0, 0) {
+                return Err(ConfigError::ZeroPeriod(index));
+            }
+            if motor.range[0] >= motor.range[1] {
+                return Err(ConfigError::InvertedRange(index));
+            }
+            if motor.range_degrees == Some(0) {
+                return Err(ConfigError::ZeroRangeDegrees(index));
+            }
+        }
+        if let Some(url) = &self.webhook_url {
+            if Endpoint::parse(url).is_none() {
+                return Err(ConfigError::InvalidWebhook(url.clone()));
+            }
+        }
+        let positive = |value: f64| value.is_finite() && value > 0.0;
+        if !self.volume.map_or(true, |volume| positive(volume.value)) {
+            return Err(ConfigError::InvalidVolume);
+        }
+        let rates = self.rate.iter().chain(self.drain_rate.iter());
+        if !rates.map(|rate| rate.value).all(positive) {
+            return Err(ConfigError::InvalidRate);
+        }
+        let rate = self
+            .rate
+            .unwrap_or_else(|| VolumeRate::new::<milliliter_per_second>(DEFAULT_RATE));
+        if self
+            .drain_rate
+            .map_or(false, |drain_rate| drain_rate > rate)
+        {
+            return Err(ConfigError::FastDrain);
+        }
+        Ok(())
+    }
+}
+
+/// Configuration loading error type.
+#[cfg(feature = "use_serde")]
+#[derive(Debug)]
+pub enum LoadError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file's extension doesn't name a supported format.
+    UnknownFormat(PathBuf),
+    /// The file could not be parsed as JSON.
+    Json(serde_json::Error),
+    /// The file could not be parsed as TOML.
+    Toml(toml::de::Error),
+    /// The file could not be parsed as YAML.
+    Yaml(serde_yaml::Error),
+}
+
+#[cfg(feature = "use_serde")]
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl From<serde_json::Error> for LoadError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl From<toml::de::Error> for LoadError {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Toml(err)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl From<serde_yaml::Error> for LoadError {
+    fn from(err: serde_yaml::Error) -> Self {
+        Self::Yaml(err)
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "Could not read configuration: {}", err),
+            Self::UnknownFormat(path) => write!(
+                f,
+                "Unknown configuration format for {} (expected .json, .toml, .yaml, or .yml)",
+                path.display()
+            ),
+            Self::Json(err) => write!(f, "Invalid JSON configuration: {}", err),
+            Self::Toml(err) => write!(f, "Invalid TOML configuration: {}", err),
+            Self::Yaml(err) => write!(f, "Invalid YAML configuration: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl std::error::Error for LoadError {}
+
+#[cfg(feature = "use_serde")]
+impl Config {
+    /// Loads the configuration from the given file.
+    ///
+    /// The format (JSON, TOML, or YAML) is determined by the file's extension.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        // Check the format first, so there's no need to read files we can't parse.
+        match extension {
+            Some("json") | Some("toml") | Some("yaml") | Some("yml") => {}
+            _ => return Err(LoadError::UnknownFormat(path.to_owned())),
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(match extension {
+            Some("toml") => toml::from_str(&contents)?,
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents)?,
+            _ => serde_json::from_str(&contents)?,
+        })
+    }
+}
+
+#[cfg(feature = "use_serde")]
+/// The commentary placed at the top of generated example configurations.
+const EXAMPLE_HEADER: &str = "\
+# deoxy configuration
+#
+# Each pump is driven by four pins (in order from 0–3); set `invert` if it runs backwards.
+# Steps use the first pump unless they name another.
+# Each motor opens the valve for one buffer, except the one chosen by `waste_motor`, which
+# controls the waste line. Motor periods and signal ranges are given as durations (such as
+# `20ms` or `600us`); the defaults below suit most hobby servos.
+#
+# Optional settings (all omitted here) include `admins`, `rescue_buffer`, `interlocks`,
+# `soak`, `volume`, `rate`, `drain_rate`, `estop_pin`, `history`, `state_file`,
+# `settle_delay`, `line_clear_delay`, `max_step_duration`, `ambient`, `mail`, `webhook_url`,
+# `auth`, and `server`.
+
+";
+
+impl Config {
+    /// Returns an example configuration with one pump and four motors.
+    ///
+    /// The motors use a 20 ms period and a 600–2400 µs signal range, and no admins are set.
+    pub fn example() -> Self {
+        let motor = |pin| MotorConfig {
+            pin,
+            label: None,
+            period: Duration::from_millis(20),
+            range: [Duration::from_micros(600), Duration::from_micros(2400)],
+            range_degrees: None,
+            slew: None,
+        };
+        Self {
+            pumps: vec![PumpConfig {
+                pins: [24, 25, 5, 6],
+                invert: false,
+                period: None,
+                dead_time: None,
+            }],
+            motors: vec![motor(4), motor(27), motor(21), motor(13)],
+            waste_motor: 0,
+            admins: vec![],
+            rescue_buffer: None,
+            ambient: None,
+            interlocks: vec![],
+            soak: Duration::new(0, 0),
+            volume: None,
+            rate: None,
+            drain_rate: None,
+            estop_pin: None,
+            history: None,
+            state_file: None,
+            settle_delay: None,
+            line_clear_delay: None,
+            mail: None,
+            webhook_url: None,
+            auth: None,
+            server: None,
+            motor_retries: None,
+            halt_retries: None,
+            halt_backoff: None,
+            max_step_duration: None,
+            simulate: false,
+        }
+    }
+
+    /// Renders the example configuration as commented TOML, ready to be written to a new
+    /// configuration file.
+    #[cfg(feature = "use_serde")]
+    pub fn example_toml() -> Result<String, toml::ser::Error> {
+        // Going through `toml::Value` puts plain values ahead of tables, as TOML requires.
+        let value = toml::Value::try_from(Self::example())?;
+        Ok(format!("{}{}", EXAMPLE_HEADER, toml::to_string(&value)?))
+    }
 }
 
 /// Specifies a single motor.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
 pub struct MotorConfig {
     /// The pin associated with this motor.
@@ -23,13 +457,51 @@ pub struct MotorConfig {
     #[cfg_attr(feature = "use_serde", serde(skip_serializing_if = "Option::is_none"))]
     pub label: Option<String>,
     /// The characteristic period of the motor.
+    #[cfg_attr(feature = "use_serde", serde(with = "deoxy_core::duration"))]
     pub period: Duration,
     /// The limits of acceptable signal length.
+    #[cfg_attr(feature = "use_serde", serde(with = "deoxy_core::duration::array"))]
     pub range: [Duration; 2],
+    /// The range of motion of the motor (in degrees) over its signal range.
+    ///
+    /// If unspecified, a range of 180º is assumed.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub range_degrees: Option<u16>,
+    /// How long the motor should take to move across its whole signal range, to spare its gears
+    /// the shock of jumping between positions (moves are made all at once if unspecified).
+    ///
+    /// This should be well under the `settle_delay`, since the motor is stopped once that elapses.
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(
+            default,
+            skip_serializing_if = "Option::is_none",
+            with = "deoxy_core::duration::option"
+        )
+    )]
+    pub slew: Option<Duration>,
+}
+
+/// Deserializes either a single pump or a list of them.
+#[cfg(feature = "use_serde")]
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<PumpConfig>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Pumps {
+        One(PumpConfig),
+        Many(Vec<PumpConfig>),
+    }
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        Pumps::One(pump) => vec![pump],
+        Pumps::Many(pumps) => pumps,
+    })
 }
 
 /// Encodes the pump configuration.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
 pub struct PumpConfig {
     /// The pins used for the pump, in order from 0–3.
@@ -37,4 +509,379 @@ pub struct PumpConfig {
     /// If true, the pump's "forward" direction will be the reverse direction
     #[cfg_attr(feature = "use_serde", serde(default, alias = "reverse"))]
     pub invert: bool,
+    /// The period of the PWM signal used to control the pump's speed (10 ms if unspecified).
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, with = "deoxy_core::duration::option")
+    )]
+    pub period: Option<Duration>,
+    /// How long the pump is left stopped before reversing, so the H-bridge isn't shorted (20 ms
+    /// if unspecified).
+    #[cfg_attr(
+        feature = "use_serde",
+        serde(default, with = "deoxy_core::duration::option")
+    )]
+    pub dead_time: Option<Duration>,
+}
+
+/// Encodes the ambient sensor configuration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct AmbientConfig {
+    /// How often the sensor should be sampled while a job is running.
+    #[cfg_attr(feature = "use_serde", serde(with = "deoxy_core::duration"))]
+    pub interval: Duration,
+    /// The sensor backend to use.
+    pub sensor: SensorConfig,
+}
+
+/// Specifies an ambient sensor backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "use_serde", serde(rename_all = "lowercase"))]
+pub enum SensorConfig {
+    /// A stub sensor which always reports the given conditions.
+    Fixed {
+        /// The temperature to report (ºC).
+        temperature: f64,
+        /// The relative humidity to report (%).
+        humidity: f64,
+    },
+}
+
+/// Encodes the server's authentication configuration.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct AuthConfig {
+    /// The bearer tokens which grant access.
+    pub tokens: Vec<String>,
+    /// Whether the status of the machine may be read without a token.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub public_status: bool,
+}
+
+/// Encodes the server's network configuration.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct ServerConfig {
+    /// The address (as `host:port`) to listen on.
+    pub bind: String,
+    /// The origins (such as `https://lab.example.com`) of web frontends which may make requests
+    /// from a browser (`localhost` and `127.0.0.1` on ports 8000 and 8080, if unspecified).
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:1957".to_owned(),
+            allowed_origins: None,
+        }
+    }
+}
+
+/// Encodes the email notification configuration.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct MailConfig {
+    /// The address notifications are sent from.
+    pub from: String,
+    /// A prefix for notification subjects (such as `[deoxy]`), to make them easier to filter.
+    #[cfg_attr(feature = "use_serde", serde(default))]
+    pub subject_prefix: Option<String>,
+    /// The means by which notifications are delivered.
+    pub backend: MailBackend,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            from: "deoxy@hmltn.me".to_owned(),
+            subject_prefix: None,
+            backend: MailBackend::Sendmail,
+        }
+    }
+}
+
+/// Specifies an email backend.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "use_serde", serde(rename_all = "lowercase"))]
+pub enum MailBackend {
+    /// Hand messages to the local `sendmail` binary.
+    Sendmail,
+    /// Send messages through an SMTP relay.
+    Smtp {
+        /// The relay's host name.
+        host: String,
+        /// The relay's port.
+        port: u16,
+        /// The credentials to authenticate with, if the relay requires them (only ever sent once
+        /// the connection has been upgraded to TLS).
+        #[cfg_attr(feature = "use_serde", serde(default))]
+        credentials: Option<SmtpCredentials>,
+    },
+}
+
+/// Credentials for authenticating with an SMTP relay.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "use_serde", derive(Deserialize, Serialize))]
+pub struct SmtpCredentials {
+    /// The user name.
+    pub username: String,
+    /// The password.
+    pub password: String,
+}
+
+#[cfg(test)]
+impl Config {
+    /// A four-motor configuration suitable for stub-backed tests.
+    pub(crate) fn stub() -> Self {
+        let motor = |pin| MotorConfig {
+            pin,
+            label: None,
+            period: Duration::from_millis(20),
+            range: [Duration::from_micros(600), Duration::from_micros(2400)],
+            range_degrees: None,
+            slew: None,
+        };
+        Self {
+            pumps: vec![PumpConfig {
+                pins: [1, 2, 3, 4],
+                invert: false,
+                period: None,
+                dead_time: None,
+            }],
+            motors: vec![motor(5), motor(6), motor(7), motor(8)],
+            waste_motor: 0,
+            admins: vec![],
+            rescue_buffer: None,
+            ambient: None,
+            interlocks: vec![],
+            soak: Duration::new(0, 0),
+            volume: None,
+            rate: None,
+            drain_rate: None,
+            estop_pin: None,
+            history: None,
+            state_file: None,
+            settle_delay: None,
+            line_clear_delay: None,
+            mail: None,
+            webhook_url: None,
+            auth: None,
+            server: None,
+            motor_retries: None,
+            halt_retries: None,
+            halt_backoff: None,
+            max_step_duration: None,
+            simulate: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "use_serde")]
+    use std::env::temp_dir;
+
+    #[cfg(feature = "use_serde")]
+    const JSON: &str = r#"{
+        "pump": { "pins": [1, 2, 3, 4] },
+        "motors": [
+            {
+                "pin": 5,
+                "period": "20ms",
+                "range": ["600us", "2400us"]
+            }
+        ],
+        "admins": ["admin@example.com"],
+        "interlocks": [[0, 1]]
+    }"#;
+
+    #[cfg(feature = "use_serde")]
+    const TOML: &str = r#"
+        admins = ["admin@example.com"]
+        interlocks = [[0, 1]]
+
+        [pump]
+        pins = [1, 2, 3, 4]
+
+        [[motors]]
+        pin = 5
+        period = "20ms"
+        range = ["600us", "2400us"]
+    "#;
+
+    #[cfg(feature = "use_serde")]
+    const YAML: &str = "
+pump:
+  pins: [1, 2, 3, 4]
+motors:
+  - pin: 5
+    period: 20ms
+    range: [600us, 2400us]
+admins: [admin@example.com]
+interlocks: [[0, 1]]
+";
+
+    #[cfg(feature = "use_serde")]
+    /// Writes the given contents to a temporary file and loads it.
+    fn load(contents: &str, extension: &str) -> Result<Config, LoadError> {
+        let path = temp_dir().join(format!("deoxy-{}.{}", uuid::Uuid::new_v4(), extension));
+        fs::write(&path, contents).unwrap();
+        let config = Config::from_path(&path);
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn formats_agree() {
+        let json = load(JSON, "json").unwrap();
+        assert_eq!(json.motors[0].period, Duration::from_millis(20));
+        assert_eq!(json.interlocks, vec![(0, 1)]);
+        assert_eq!(load(TOML, "toml").unwrap(), json);
+        assert_eq!(load(YAML, "yaml").unwrap(), json);
+        assert_eq!(load(YAML, "yml").unwrap(), json);
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn load_errors() {
+        assert!(matches!(
+            Config::from_path("config.ini"),
+            Err(LoadError::UnknownFormat(_))
+        ));
+        assert!(matches!(load("pump = 1", "toml"), Err(LoadError::Toml(_))));
+        assert!(matches!(load(TOML, "json"), Err(LoadError::Json(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn example_round_trips() {
+        let example = Config::example_toml().unwrap();
+        assert!(example.starts_with("# deoxy configuration"));
+        let config = load(&example, "toml").unwrap();
+        assert_eq!(config, Config::example());
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    #[cfg(feature = "use_serde")]
+    fn several_pumps() {
+        const PUMPS: &str = r#"
+            [[pumps]]
+            pins = [1, 2, 3, 4]
+
+            [[pumps]]
+            pins = [10, 11, 12, 13]
+            invert = true
+
+            [[motors]]
+            pin = 5
+            period = "20ms"
+            range = ["600us", "2400us"]
+        "#;
+        let config = load(PUMPS, "toml").unwrap();
+        assert_eq!(config.pumps.len(), 2);
+        assert_eq!(config.pumps[1].pins, [10, 11, 12, 13]);
+        assert!(config.pumps[1].invert);
+        assert_eq!(config.pumps[0], load(TOML, "toml").unwrap().pumps[0]);
+    }
+
+    #[test]
+    fn stub_is_valid() {
+        assert_eq!(Config::stub().validate(), Ok(()));
+    }
+
+    #[test]
+    fn resolve_labels() {
+        let mut config = Config::stub();
+        config.motors[1].label = Some("Water".to_owned());
+        let protocol = crate::Protocol::from(vec![
+            crate::Step::Perfuse(0, Some(Duration::new(5, 0))),
+            crate::Step::Perfuse(1, None),
+        ]);
+        let labels = protocol.resolve_labels(&config.labels());
+        assert_eq!(labels[&0], "Buffer 0");
+        assert_eq!(labels[&1], "Water");
+    }
+
+    #[test]
+    fn duplicate_pins() {
+        let mut config = Config::stub();
+        config.motors[1].pin = 2;
+        config.estop_pin = Some(5);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::DuplicatePins(vec![2, 5]))
+        );
+        let mut config = Config::stub();
+        config.pumps.push(config.pumps[0]);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::DuplicatePins(vec![1, 2, 3, 4]))
+        );
+        config.pumps.clear();
+        assert_eq!(config.validate(), Err(ConfigError::NoPumps));
+    }
+
+    #[test]
+    fn inverted_range() {
+        let mut config = Config::stub();
+        config.motors[2].range.reverse();
+        assert_eq!(config.validate(), Err(ConfigError::InvertedRange(2)));
+        config.motors[2].range.reverse();
+        config.motors[3].period = Duration::new(0, 0);
+        assert_eq!(config.validate(), Err(ConfigError::ZeroPeriod(3)));
+        config.motors[3].period = Duration::from_millis(20);
+        config.motors[1].range_degrees = Some(0);
+        assert_eq!(config.validate(), Err(ConfigError::ZeroRangeDegrees(1)));
+    }
+
+    #[test]
+    fn unknown_waste_motor() {
+        let mut config = Config::stub();
+        config.waste_motor = 3;
+        assert_eq!(config.validate(), Ok(()));
+        config.waste_motor = 4;
+        assert_eq!(config.validate(), Err(ConfigError::UnknownWasteMotor(4)));
+    }
+
+    #[test]
+    fn unknown_rescue_buffer() {
+        let mut config = Config::stub();
+        config.rescue_buffer = Some(2);
+        assert_eq!(config.validate(), Ok(()));
+        config.rescue_buffer = Some(3);
+        assert_eq!(config.validate(), Err(ConfigError::UnknownRescueBuffer(3)));
+    }
+
+    #[test]
+    fn invalid_rates() {
+        use uom::si::volume::milliliter;
+
+        let mut config = Config::stub();
+        config.volume = Some(Volume::new::<milliliter>(250.0));
+        config.rate = Some(VolumeRate::new::<milliliter_per_second>(2.5));
+        config.drain_rate = Some(VolumeRate::new::<milliliter_per_second>(1.0));
+        assert_eq!(config.validate(), Ok(()));
+        config.drain_rate = Some(VolumeRate::new::<milliliter_per_second>(3.0));
+        assert_eq!(config.validate(), Err(ConfigError::FastDrain));
+        config.rate = None;
+        assert_eq!(config.validate(), Ok(()));
+        config.drain_rate = Some(VolumeRate::new::<milliliter_per_second>(0.0));
+        assert_eq!(config.validate(), Err(ConfigError::InvalidRate));
+        config.drain_rate = None;
+        config.rate = Some(VolumeRate::new::<milliliter_per_second>(-2.5));
+        assert_eq!(config.validate(), Err(ConfigError::InvalidRate));
+        config.rate = Some(VolumeRate::new::<milliliter_per_second>(f64::NAN));
+        assert_eq!(config.validate(), Err(ConfigError::InvalidRate));
+        config.rate = None;
+        config.volume = Some(Volume::new::<milliliter>(0.0));
+        assert_eq!(config.validate(), Err(ConfigError::InvalidVolume));
+    }
 }
@@ -35,25 +35,43 @@ pub mod actix {
 /// Re-export of `actix-web`.
 pub use actix_web;
 
+#[cfg(feature = "use_serde")]
+pub mod check;
 mod comm;
 mod config;
+pub mod logging;
 pub mod mail;
 mod motor;
 pub(crate) mod pin;
 mod pump;
+pub mod sensor;
 #[cfg(feature = "server")]
 pub mod server;
+pub mod webhook;
 
 pub use self::{
     comm::{
-        Coordinator, Error as CoordError, Message as CoordMessage, State as ExecState, Status,
-        StatusMessage, Update,
+        Coordinator, Error as CoordError, HaltReason, Health, JobRecord, Message as CoordMessage,
+        Metrics as JobMetrics, Plan, Progress as ActionProgress, Projection, State as ExecState,
+        Status, StatusMessage, TimedAction, Update,
+    },
+    config::{
+        AmbientConfig, AuthConfig, Config, ConfigError, MailBackend, MailConfig, MotorConfig,
+        PumpConfig, SensorConfig, ServerConfig, SmtpCredentials,
+    },
+    motor::{
+        Calibration as MotorCalibration, CalibrationError, Error as MotorError,
+        Failure as MotorFailure, Message as MotorMessage, Motor, Position as MotorPosition,
     },
-    config::{Config, MotorConfig, PumpConfig},
-    motor::{Message as MotorMessage, Motor},
-    pin::{Error as PinError, Out, Pin, Pwm},
+    pin::{set_batch, Error as PinError, In, Input, Out, Pin, Pwm},
     pump::{Direction as PumpDirection, Message as PumpMessage, Pump},
 };
 
+#[cfg(feature = "use_serde")]
+pub use self::config::LoadError as ConfigLoadError;
+
+#[cfg(feature = "stub")]
+pub use self::pin::Write as PinWrite;
+
 #[cfg(not(feature = "server"))]
 pub use self::comm::tui::Tui;
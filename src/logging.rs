@@ -0,0 +1,180 @@
+//! Structured logging.
+//!
+//! The coordinator logs through [`scoped`](fn.scoped.html), which attaches the ID and state of the
+//! job it's running to everything logged in the meantime. [`JsonLogger`](struct.JsonLogger.html)
+//! (which requires the `use_serde` feature) writes these out as one JSON object per line, for
+//! collectors that expect machine-readable logs; other loggers simply ignore them.
+use crate::comm::State;
+use uuid::Uuid;
+
+use std::cell::Cell;
+
+/// The job a log event belongs to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Job {
+    /// The job ID, if a job has been run.
+    pub id: Option<Uuid>,
+    /// The coordinator's state.
+    pub state: State,
+}
+
+thread_local! {
+    static JOB: Cell<Option<Job>> = Cell::new(None);
+}
+
+/// Runs `f` with the given job attached to anything it logs.
+pub fn scoped<T, F: FnOnce() -> T>(id: Option<Uuid>, state: State, f: F) -> T {
+    let previous = JOB.with(|job| job.replace(Some(Job { id, state })));
+    let result = f();
+    JOB.with(|job| job.set(previous));
+    result
+}
+
+/// The job attached to events logged on this thread, if any.
+pub fn current() -> Option<Job> {
+    JOB.with(Cell::get)
+}
+
+#[cfg(feature = "use_serde")]
+pub use self::json::JsonLogger;
+
+#[cfg(feature = "use_serde")]
+mod json {
+    use super::current;
+    use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+    use serde_json::json;
+
+    use std::{
+        fmt,
+        io::{self, Write},
+        sync::Mutex,
+        time::SystemTime,
+    };
+
+    /// A logger which writes each event as a single line of JSON.
+    ///
+    /// Each line has the fields `time`, `level`, `target`, `message`, `job_id`, and `state`; the
+    /// last two are `null` for events not logged on behalf of a job.
+    pub struct JsonLogger {
+        /// The most verbose level to log.
+        level: LevelFilter,
+        /// Where to write the events.
+        out: Mutex<Box<dyn Write + Send>>,
+    }
+
+    impl fmt::Debug for JsonLogger {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_struct("JsonLogger")
+                .field("level", &self.level)
+                .finish()
+        }
+    }
+
+    impl JsonLogger {
+        /// Creates a logger writing events up to the given level to `out`.
+        pub fn new<W: Write + Send + 'static>(out: W, level: LevelFilter) -> Self {
+            Self {
+                level,
+                out: Mutex::new(Box::new(out)),
+            }
+        }
+        /// Installs a logger writing to standard error as the global logger.
+        pub fn init(level: LevelFilter) -> Result<(), SetLoggerError> {
+            log::set_logger(Box::leak(Box::new(Self::new(io::stderr(), level))))?;
+            log::set_max_level(level);
+            Ok(())
+        }
+    }
+
+    impl Log for JsonLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= self.level
+        }
+        fn log(&self, record: &Record) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+            let job = current();
+            let line = json!({
+                "time": humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+                "job_id": job.and_then(|job| job.id),
+                "state": job.map(|job| job.state),
+            });
+            if let Ok(mut out) = self.out.lock() {
+                // There's nowhere to report a failure to log.
+                let _ = writeln!(out, "{}", line);
+            }
+        }
+        fn flush(&self) {
+            if let Ok(mut out) = self.out.lock() {
+                let _ = out.flush();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::super::scoped;
+        use super::*;
+        use crate::comm::State;
+        use log::Level;
+        use std::sync::Arc;
+        use uuid::Uuid;
+
+        /// A writer whose output can be read back.
+        #[derive(Clone, Default)]
+        struct Captured(Arc<Mutex<Vec<u8>>>);
+
+        impl Write for Captured {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn json_lines() {
+            let captured = Captured::default();
+            let logger = JsonLogger::new(captured.clone(), LevelFilter::Info);
+            let id = Uuid::new_v4();
+            scoped(Some(id), State::Running, || {
+                logger.log(
+                    &Record::builder()
+                        .level(Level::Info)
+                        .target("deoxy::comm")
+                        .args(format_args!("Step {} of {}", 1, 3))
+                        .build(),
+                );
+            });
+            logger.log(
+                &Record::builder()
+                    .level(Level::Debug)
+                    .args(format_args!("Too verbose"))
+                    .build(),
+            );
+            logger.log(
+                &Record::builder()
+                    .level(Level::Warn)
+                    .args(format_args!("No job"))
+                    .build(),
+            );
+            let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+            let lines = output.lines().collect::<Vec<_>>();
+            assert_eq!(lines.len(), 2);
+            let event: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+            assert_eq!(event["job_id"], id.to_string());
+            assert_eq!(event["state"], "running");
+            assert_eq!(event["message"], "Step 1 of 3");
+            assert_eq!(event["level"], "INFO");
+            assert_eq!(event["target"], "deoxy::comm");
+            let event: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+            assert!(event["job_id"].is_null());
+            assert!(event["state"].is_null());
+        }
+    }
+}
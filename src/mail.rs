@@ -1,78 +1,620 @@
 //! Contains utilities for sending email notifications.
 
+use crate::{
+    config::{MailBackend, MailConfig, SmtpCredentials},
+    Action, HaltReason, Program,
+};
+
+use actix_web::actix::{Actor, Addr, Handler, Message, SyncArbiter, SyncContext};
+
+use native_tls::TlsConnector;
+use uuid::Uuid;
+
 use std::{
-    io::{BufWriter, Write},
+    fmt,
+    io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write},
+    net::TcpStream,
     process::{Command, Stdio},
+    time::{Duration, SystemTime},
 };
 
+/// Separates the plain-text and HTML parts of a message.
+const BOUNDARY: &str = "deoxy-alternative";
+
+/// How long an SMTP relay is given to accept each command and respond.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Encodes the status of the decell machine.
 #[derive(Clone, Copy, Debug)]
 pub enum Status<'a> {
     /// The run has finished.
     Finished,
-    /// The run has been aborted.
-    Aborted,
+    /// The run has been aborted, for the given reason.
+    Aborted(&'a HaltReason),
     /// A custom status message.
     Custom {
         /// The message's subject.
         subject: &'a str,
         /// The message's body.
         message: &'a str,
+        /// An HTML rendition of the body, if any.
+        html: Option<&'a str>,
     },
 }
 
-/// Notify the specified recipients of a status change.
-pub fn notify(to: &[impl ToString], status: Status) -> std::io::Result<()> {
-    let (subject, message) = match status {
+/// Describes a finished job for its completion email.
+#[derive(Clone, Debug)]
+pub struct JobSummary {
+    /// The job ID.
+    pub id: Uuid,
+    /// The program that was run.
+    pub program: Program,
+    /// When the job started.
+    pub started: SystemTime,
+    /// When the job ended.
+    pub ended: SystemTime,
+}
+
+impl JobSummary {
+    /// The buffers the program perfused, in order.
+    fn buffers(&self) -> String {
+        self.program
+            .actions()
+            .iter()
+            .filter_map(|action| match action {
+                Action::Perfuse(buffer) => Some(buffer.to_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    /// How long the job ran, to the second.
+    fn elapsed(&self) -> Duration {
+        let elapsed = self
+            .ended
+            .duration_since(self.started)
+            .unwrap_or_else(|_| Duration::new(0, 0));
+        Duration::from_secs(elapsed.as_secs())
+    }
+    /// Renders the summary as plain text.
+    fn text(&self) -> String {
+        format!(
+            "The decellularization run has completed as scheduled.\n\n\
+             Job: {}\n\
+             Buffers: {}\n\
+             Started: {}\n\
+             Ended: {}\n\
+             Elapsed: {}",
+            self.id,
+            self.buffers(),
+            humantime::format_rfc3339_seconds(self.started),
+            humantime::format_rfc3339_seconds(self.ended),
+            humantime::format_duration(self.elapsed()),
+        )
+    }
+    /// Renders the summary as HTML.
+    fn html(&self) -> String {
+        format!(
+            "<p>The decellularization run has completed as scheduled.</p>\n\
+             <table>\n\
+             <tr><th>Job</th><td>{}</td></tr>\n\
+             <tr><th>Buffers</th><td>{}</td></tr>\n\
+             <tr><th>Started</th><td>{}</td></tr>\n\
+             <tr><th>Ended</th><td>{}</td></tr>\n\
+             <tr><th>Elapsed</th><td>{}</td></tr>\n\
+             </table>",
+            self.id,
+            self.buffers(),
+            humantime::format_rfc3339_seconds(self.started),
+            humantime::format_rfc3339_seconds(self.ended),
+            humantime::format_duration(self.elapsed()),
+        )
+    }
+}
+
+/// An email ready to be sent.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Email {
+    /// The sender's address.
+    pub from: String,
+    /// The recipients' addresses.
+    pub to: Vec<String>,
+    /// The message's subject.
+    pub subject: String,
+    /// The message's body.
+    pub body: String,
+    /// An HTML rendition of the body, sent alongside the plain text if present.
+    pub html: Option<String>,
+}
+
+impl Email {
+    /// The lines of the message (headers, then body) as they should be transmitted.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Subject: {}", self.subject),
+            format!("From: {}", self.from),
+        ];
+        lines.extend(self.to.iter().map(|recipient| format!("To: {}", recipient)));
+        match &self.html {
+            Some(html) => {
+                lines.push("MIME-Version: 1.0".to_owned());
+                lines.push(format!(
+                    "Content-Type: multipart/alternative; boundary=\"{}\"",
+                    BOUNDARY
+                ));
+                lines.push(String::new());
+                for (kind, body) in &[("plain", &self.body), ("html", html)] {
+                    lines.push(format!("--{}", BOUNDARY));
+                    lines.push(format!("Content-Type: text/{}; charset=utf-8", kind));
+                    lines.push(String::new());
+                    lines.extend(body.lines().map(ToOwned::to_owned));
+                }
+                lines.push(format!("--{}--", BOUNDARY));
+            }
+            None => {
+                lines.push(String::new());
+                lines.extend(self.body.lines().map(ToOwned::to_owned));
+            }
+        }
+        lines
+    }
+}
+
+/// Trait representing a means of delivering email.
+pub trait Transport: fmt::Debug {
+    /// Delivers the given email.
+    fn send(&mut self, email: &Email) -> Result<()>;
+}
+
+/// Delivers email through the local `sendmail` binary.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sendmail;
+
+impl Transport for Sendmail {
+    // Thanks to BurntSushi.
+    fn send(&mut self, email: &Email) -> Result<()> {
+        let mut child = Command::new("sendmail")
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()?;
+        {
+            let mut buf = BufWriter::new(child.stdin.as_mut().unwrap());
+            for line in email.lines() {
+                writeln!(&mut buf, "{}", line)?;
+            }
+            writeln!(&mut buf, ".")?;
+        }
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(match status.code() {
+                None => Error::new(ErrorKind::Interrupted, "Email sending interrupted"),
+                Some(_) => Error::new(ErrorKind::Other, status.to_string()),
+            })
+        }
+    }
+}
+
+/// Delivers email through an SMTP relay.
+///
+/// When authenticating, the connection is upgraded with STARTTLS first, so credentials are never
+/// sent in the clear; if the relay can't start TLS, sending fails.
+#[derive(Clone, Debug)]
+pub struct Smtp {
+    /// The relay's host name.
+    pub host: String,
+    /// The relay's port.
+    pub port: u16,
+    /// The credentials to authenticate with, if the relay requires them.
+    pub credentials: Option<SmtpCredentials>,
+}
+
+impl Smtp {
+    /// Reads a (possibly multi-line) reply, failing unless it has the expected code.
+    fn expect(reader: &mut impl BufRead, code: u16) -> Result<()> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "SMTP connection closed",
+                ));
+            }
+            // The last line of a reply has a space after the code, rather than a hyphen.
+            if line.get(3..4) != Some("-") {
+                break;
+            }
+        }
+        match line.get(..3).and_then(|reply| reply.parse::<u16>().ok()) {
+            Some(reply) if reply == code => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::Other,
+                format!("Unexpected SMTP reply: {}", line.trim_end()),
+            )),
+        }
+    }
+    /// Sends a command, failing unless the reply has the expected code.
+    fn command<S: Read + Write>(stream: &mut BufReader<S>, command: &str, code: u16) -> Result<()> {
+        write!(stream.get_mut(), "{}\r\n", command)?;
+        stream.get_mut().flush()?;
+        Self::expect(stream, code)
+    }
+    /// Sends the email over a connection that's been greeted.
+    fn deliver<S: Read + Write>(stream: &mut BufReader<S>, email: &Email) -> Result<()> {
+        let from = format!("MAIL FROM:<{}>", email.from);
+        Self::command(stream, &from, 250)?;
+        for recipient in &email.to {
+            let to = format!("RCPT TO:<{}>", recipient);
+            Self::command(stream, &to, 250)?;
+        }
+        Self::command(stream, "DATA", 354)?;
+        let mut data = String::new();
+        for line in email.lines() {
+            // Lines starting with a dot must have it doubled so as not to end the message.
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(&line);
+            data.push_str("\r\n");
+        }
+        stream.get_mut().write_all(data.as_bytes())?;
+        Self::command(stream, ".", 250)?;
+        Self::command(stream, "QUIT", 221)
+    }
+}
+
+impl Transport for Smtp {
+    fn send(&mut self, email: &Email) -> Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut stream = BufReader::new(stream);
+        Self::expect(&mut stream, 220)?;
+        Self::command(&mut stream, "EHLO deoxy", 250)?;
+        let credentials = match &self.credentials {
+            Some(credentials) => credentials,
+            None => return Self::deliver(&mut stream, email),
+        };
+        Self::command(&mut stream, "STARTTLS", 220)?;
+        let tls = TlsConnector::new()
+            .map_err(|err| Error::new(ErrorKind::Other, err))?
+            .connect(&self.host, stream.into_inner())
+            .map_err(|err| Error::new(ErrorKind::Other, err.to_string()))?;
+        let mut stream = BufReader::new(tls);
+        Self::command(&mut stream, "EHLO deoxy", 250)?;
+        let token = base64::encode(&format!(
+            "\0{}\0{}",
+            credentials.username, credentials.password
+        ));
+        Self::command(&mut stream, &format!("AUTH PLAIN {}", token), 235)?;
+        Self::deliver(&mut stream, email)
+    }
+}
+
+/// The transport the given configuration selects.
+fn transport(config: &MailConfig) -> Box<dyn Transport> {
+    match &config.backend {
+        MailBackend::Sendmail => Box::new(Sendmail),
+        MailBackend::Smtp {
+            host,
+            port,
+            credentials,
+        } => Box::new(Smtp {
+            host: host.clone(),
+            port: *port,
+            credentials: credentials.clone(),
+        }),
+    }
+}
+
+/// Composes the email describing a status change.
+pub(crate) fn status_email(config: &MailConfig, to: &[impl ToString], status: Status) -> Email {
+    let (subject, message, html) = match status {
         Status::Finished => (
             "Completed",
-            "The decellularization run has completed as scheduled.",
+            "The decellularization run has completed as scheduled.".to_owned(),
+            None,
         ),
-        Status::Aborted => (
+        Status::Aborted(reason) => (
             "Aborted",
-            "The decellularization run has been aborted manually.",
+            format!("The decellularization run has been {}.", reason),
+            None,
         ),
-        Status::Custom { subject, message } => (subject, message),
+        Status::Custom {
+            subject,
+            message,
+            html,
+        } => (subject, message.to_owned(), html),
     };
-    mail(to, subject, message)
+    let mut email = compose(config, to, subject, message);
+    email.html = html.map(ToOwned::to_owned);
+    email
+}
+
+/// Composes the standard completion email for the given job.
+pub(crate) fn finished_email(config: &MailConfig, to: &[impl ToString], job: &JobSummary) -> Email {
+    let mut email = compose(config, to, "Completed", job.text());
+    email.html = Some(job.html());
+    email
+}
+
+/// Composes an email from the given parts, prefixing the subject as configured.
+pub(crate) fn compose(
+    config: &MailConfig,
+    to: &[impl ToString],
+    subject: impl ToString,
+    message: impl ToString,
+) -> Email {
+    let subject = match &config.subject_prefix {
+        Some(prefix) => format!("{} {}", prefix, subject.to_string()),
+        None => subject.to_string(),
+    };
+    Email {
+        from: config.from.clone(),
+        to: to.iter().map(ToString::to_string).collect(),
+        subject,
+        body: message.to_string(),
+        html: None,
+    }
+}
+
+/// Notify the specified recipients of a status change.
+pub fn notify(config: &MailConfig, to: &[impl ToString], status: Status) -> Result<()> {
+    transport(config).send(&status_email(config, to, status))
+}
+
+/// Notify the specified recipients that a job has finished, summarizing it.
+pub fn notify_finished(config: &MailConfig, to: &[impl ToString], job: &JobSummary) -> Result<()> {
+    transport(config).send(&finished_email(config, to, job))
 }
 
 /// Send an email to the specified recipients.
-// Thanks to BurntSushi.
+///
+/// If an HTML body is given, it is sent alongside the plain-text message.
 pub fn mail(
+    config: &MailConfig,
     to: &[impl ToString],
     subject: impl ToString,
     message: impl ToString,
-) -> std::io::Result<()> {
-    let mut child = Command::new("sendmail")
-        .arg("-t")
-        .stdin(Stdio::piped())
-        .spawn()?;
-    {
-        let mut buf = BufWriter::new(child.stdin.as_mut().unwrap());
-        writeln!(
-            &mut buf,
-            "\
-Subject: {subject}
-From: deoxy@hmltn.me",
-            subject = subject.to_string()
-        )?;
-        for recipient in to {
-            writeln!(&mut buf, "To: {}", recipient.to_string())?;
+    html: Option<&str>,
+) -> Result<()> {
+    let mut email = compose(config, to, subject, message);
+    email.html = html.map(ToOwned::to_owned);
+    transport(config).send(&email)
+}
+
+/// Asks the mailer to deliver the given email.
+#[derive(Debug)]
+pub struct Deliver(pub Email);
+
+impl Message for Deliver {
+    type Result = ();
+}
+
+/// Delivers emails on its own thread, so that slow deliveries don't hold up the sender.
+///
+/// Delivery failures are logged, since there's nobody to email about them.
+#[derive(Debug)]
+pub struct Mailer {
+    pub(crate) transport: Box<dyn Transport>,
+}
+
+impl Mailer {
+    /// Starts a mailer on its own thread, delivering as configured.
+    pub fn spawn(config: &MailConfig) -> Addr<Self> {
+        let config = config.clone();
+        SyncArbiter::start(1, move || Self {
+            transport: transport(&config),
+        })
+    }
+}
+
+impl Actor for Mailer {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<Deliver> for Mailer {
+    type Result = ();
+    fn handle(&mut self, Deliver(email): Deliver, _context: &mut Self::Context) {
+        if let Err(err) = self.transport.send(&email) {
+            log::warn!("Could not send email (subject: {}): {}", email.subject, err);
         }
-        writeln!(&mut buf)?;
-        writeln!(&mut buf, "{}", message.to_string())?;
-        writeln!(&mut buf, ".")?;
-    }
-    let status = child.wait()?;
-    if status.success() {
-        Ok(())
-    } else {
-        Err(match status.code() {
-            None => {
-                std::io::Error::new(std::io::ErrorKind::Interrupted, "Email sending interrupted")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    /// A transport which keeps the emails it's asked to send.
+    #[derive(Debug, Default)]
+    struct Mock {
+        sent: Vec<Email>,
+    }
+
+    impl Transport for Mock {
+        fn send(&mut self, email: &Email) -> Result<()> {
+            self.sent.push(email.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn status_formatting() {
+        let mut mock = Mock::default();
+        let config = MailConfig {
+            from: "deoxy@example.com".to_owned(),
+            ..MailConfig::default()
+        };
+        let to = ["a@example.com", "b@example.com"];
+        mock.send(&status_email(&config, &to, Status::Finished))
+            .unwrap();
+        let email = &mock.sent[0];
+        assert_eq!(email.subject, "Completed");
+        assert_eq!(
+            email.lines(),
+            vec![
+                "Subject: Completed",
+                "From: deoxy@example.com",
+                "To: a@example.com",
+                "To: b@example.com",
+                "",
+                "The decellularization run has completed as scheduled.",
+            ]
+        );
+    }
+
+    #[test]
+    fn subject_prefix() {
+        let config = MailConfig {
+            from: "deoxy@example.com".to_owned(),
+            subject_prefix: Some("[deoxy]".to_owned()),
+            ..MailConfig::default()
+        };
+        let reason = HaltReason::EmergencyStop;
+        let email = status_email(&config, &["a@example.com"], Status::Aborted(&reason));
+        let lines = email.lines();
+        assert_eq!(lines[0], "Subject: [deoxy] Aborted");
+        assert_eq!(lines[1], "From: deoxy@example.com");
+        assert_eq!(
+            lines.last().unwrap(),
+            "The decellularization run has been aborted by the emergency stop button."
+        );
+    }
+
+    #[test]
+    fn completion_summary() {
+        let id = Uuid::new_v4();
+        let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let job = JobSummary {
+            id,
+            program: crate::Protocol::with_step(crate::Step::Perfuse(2, None))
+                .as_program()
+                .unwrap(),
+            started,
+            ended: started + Duration::from_secs(3725),
+        };
+        let email = finished_email(&MailConfig::default(), &["a@example.com"], &job);
+        let lines = email.lines();
+        let boundary = format!("--{}", BOUNDARY);
+        assert!(lines.contains(&"MIME-Version: 1.0".to_owned()));
+        assert!(lines.contains(&format!(
+            "Content-Type: multipart/alternative; boundary=\"{}\"",
+            BOUNDARY
+        )));
+        let parts = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| **line == boundary)
+            .map(|(index, _)| lines[index + 1].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            vec![
+                "Content-Type: text/plain; charset=utf-8",
+                "Content-Type: text/html; charset=utf-8",
+            ]
+        );
+        assert_eq!(lines.last(), Some(&format!("{}--", boundary)));
+        assert!(email.body.contains(&id.to_string()));
+        assert!(email.body.contains("Buffers: 2"));
+        assert!(email.body.contains("Elapsed: 1h 2m 5s"));
+        assert!(email.html.unwrap().contains(&id.to_string()));
+    }
+
+    /// Starts a relay which accepts one conversation (but can't start TLS), sending along the
+    /// commands and message lines it receives.
+    fn relay() -> (u16, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut received = vec![];
+            let mut line = String::new();
+            stream.write_all(b"220 ready\r\n").unwrap();
+            let mut data = false;
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let command = line.trim_end().to_owned();
+                line.clear();
+                let reply: &[u8] = if data {
+                    if command == "." {
+                        data = false;
+                        b"250 queued\r\n"
+                    } else {
+                        received.push(command);
+                        continue;
+                    }
+                } else if command.starts_with("EHLO") {
+                    b"250-hello\r\n250 AUTH PLAIN\r\n"
+                } else if command == "STARTTLS" {
+                    received.push(command);
+                    b"454 TLS not available\r\n"
+                } else if command.starts_with("AUTH") {
+                    received.push(command);
+                    b"235 ok\r\n"
+                } else if command == "DATA" {
+                    data = true;
+                    b"354 go ahead\r\n"
+                } else if command == "QUIT" {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    received.push(command);
+                    b"250 ok\r\n"
+                };
+                stream.write_all(reply).unwrap();
             }
-            Some(_) => std::io::Error::new(std::io::ErrorKind::Other, status.to_string()),
-        })
+            received
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn smtp_conversation() {
+        let (port, server) = relay();
+        let mut smtp = Smtp {
+            host: "127.0.0.1".to_owned(),
+            port,
+            credentials: None,
+        };
+        let config = MailConfig {
+            from: "deoxy@example.com".to_owned(),
+            ..MailConfig::default()
+        };
+        let email = compose(&config, &["a@example.com"], "Hi", ".hidden");
+        smtp.send(&email).unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "MAIL FROM:<deoxy@example.com>".to_owned(),
+                "RCPT TO:<a@example.com>".to_owned(),
+                "Subject: Hi".to_owned(),
+                "From: deoxy@example.com".to_owned(),
+                "To: a@example.com".to_owned(),
+                String::new(),
+                "..hidden".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn smtp_credentials_need_tls() {
+        let (port, server) = relay();
+        let mut smtp = Smtp {
+            host: "127.0.0.1".to_owned(),
+            port,
+            credentials: Some(SmtpCredentials {
+                username: "user".to_owned(),
+                password: "pass".to_owned(),
+            }),
+        };
+        let email = compose(&MailConfig::default(), &["a@example.com"], "Hi", "Hello");
+        assert!(smtp.send(&email).is_err());
+        // The relay couldn't start TLS, so the credentials were never sent.
+        assert_eq!(server.join().unwrap(), vec!["STARTTLS".to_owned()]);
     }
 }
@@ -1,11 +1,18 @@
 //! Motor management.
 
-use std::{ops::RangeInclusive, time::Duration};
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use crate::{
     actix::*,
-    pin::{Error as PinError, Pin, Pwm},
+    pin::{set_pwm_batch, Error as PinError, Pin, Pwm},
+    Coordinator,
 };
+use actix_web::actix::MessageResult;
 
 /// A message that can be sent to a motor to change its position.
 #[derive(Clone, Copy, Debug)]
@@ -18,12 +25,133 @@ pub enum Message {
     Shut,
     /// Turns off the motor's output signal.
     Stop,
+    /// Requests that the motor be set to the given angle (in degrees, relative to the closed
+    /// position).
+    SetAngle(u16),
+    /// Requests that the motor's pulse width be set directly.
+    ///
+    /// Widths outside of the motor's signal range are clamped to it.
+    SetPulseWidth(Duration),
+    /// Requests the motor's position, without moving it.
+    GetAngle,
 }
 
 impl ActixMessage for Message {
+    type Result = Position;
+}
+
+/// Where a motor is, and where it was last told to be.
+///
+/// There's no encoder, so the angle is derived from the signal being sent to the motor; a
+/// mismatch with the commanded angle means a move was refused or failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// The angle (in degrees, relative to the open position) the motor's signal corresponds to,
+    /// or `None` if the signal is off.
+    pub angle: Option<u16>,
+    /// The angle most recently requested, or `None` if the motor was last stopped or driven by
+    /// pulse width.
+    pub commanded: Option<u16>,
+}
+
+/// Reports to the coordinator that a motor couldn't be moved, even after retrying.
+#[derive(Debug)]
+pub struct Failure {
+    /// The pin of the motor that failed.
+    pub pin: u16,
+    /// The message the motor couldn't carry out.
+    pub message: Message,
+    /// How many times the move was attempted.
+    pub attempts: u32,
+    /// The error from the last attempt.
+    pub error: Error,
+}
+
+impl ActixMessage for Failure {
     type Result = ();
 }
 
+/// Motor operation error type.
+#[derive(Debug)]
+pub enum Error {
+    /// The requested angle is outside the motor's range of motion.
+    Angle(u16),
+    /// The output pin could not be driven.
+    Pin(PinError),
+}
+
+impl From<PinError> for Error {
+    fn from(err: PinError) -> Self {
+        Self::Pin(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Angle(angle) => write!(f, "Angle {}º is out of range", angle),
+            Self::Pin(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A motor's output pin, along with the pulse width it was last driven with.
+#[derive(Debug)]
+pub(crate) struct Output {
+    /// The pin itself.
+    pub(crate) pin: Pin,
+    /// The pulse width most recently written (zero if the signal is off).
+    width: Duration,
+}
+
+impl Pwm for Output {
+    fn set_pwm(&mut self, period: Duration, pulse_width: Duration) -> Result<(), PinError> {
+        self.pin.set_pwm(period, pulse_width)?;
+        self.width = pulse_width;
+        Ok(())
+    }
+}
+
+/// A direct handle to a motor's output, for closing its valve alongside others in one batch.
+///
+/// The motor picks up moves made through the handle the next time it handles a message or steps
+/// toward its target.
+#[derive(Clone, Debug)]
+pub(crate) struct Valve {
+    /// The output shared with the motor.
+    pub(crate) output: Arc<Mutex<Output>>,
+    /// The characteristic period of the motor.
+    period: Duration,
+    /// The pulse width of the closed position.
+    pub(crate) closed: Duration,
+    /// The pulse width of the shut position.
+    pub(crate) shut: Duration,
+}
+
+/// Closes the given valves, shutting those flagged, in one synchronous pass.
+///
+/// Every output is locked before any is written, so no motor can move partway through the batch.
+pub(crate) fn close_valves<'a, I>(valves: I) -> Result<(), PinError>
+where
+    I: IntoIterator<Item = (&'a Valve, bool)>,
+{
+    let mut writes = valves
+        .into_iter()
+        .map(|(valve, shut)| {
+            let width = if shut { valve.shut } else { valve.closed };
+            let output = valve.output.lock().map_err(|_| PinError::Panic)?;
+            Ok((output, valve.period, width))
+        })
+        .collect::<Result<Vec<_>, PinError>>()?;
+    set_pwm_batch(
+        writes
+            .iter_mut()
+            .map(|(output, period, width)| (&mut **output, *period, *width)),
+    )
+}
+
 /// A motor connected to the syringe manifold.
 ///
 /// Moving a motor (physically) will cause the control knob to rotate.
@@ -31,26 +159,48 @@ impl ActixMessage for Message {
 pub struct Motor {
     /// The characteristic period of the motor.
     period: Duration,
-    /// The output pin controlling the physical motor.
-    pin: Pin,
+    /// The number of the output pin controlling the physical motor.
+    number: u16,
+    /// The output controlling the physical motor, shared with any [`Valve`](struct.Valve.html)
+    /// handles.
+    output: Arc<Mutex<Output>>,
     /// The range of acceptable signal lengths.
     ///
-    /// The motor is assumed to have 180º of motion, meaning the minimum and signals should
-    /// correspond to antiparallel positions.
+    /// The minimum and maximum signals should correspond to the ends of the motor's range of
+    /// motion.
     ///
-    /// The closed position is assumed to be 0º; the open position is at 90º.
+    /// The open position is assumed to be 0º; the closed position is halfway through the range.
     signal_range: RangeInclusive<Duration>,
+    /// The range of motion of the motor (in degrees) over its signal range.
+    pub range_degrees: u16,
     /// The duration for which the signal should be high in each period.
     ///
     /// Changing this property will change the position of the motor.
     pulse_width: Duration,
+    /// How long the motor takes to move across its whole signal range, if its moves are ramped
+    /// rather than made all at once.
+    ///
+    /// Ramping only happens while the motor is running as an actor; the signal is stepped toward
+    /// its target once each period.
+    pub slew: Option<Duration>,
+    /// The pulse width the motor is moving toward.
+    target: Duration,
+    /// The last (nonzero) pulse width the motor was driven with, where it's assumed to have
+    /// stayed even once its signal is turned off.
+    last_width: Option<Duration>,
     /// The handle to the main loop for this motor (for cancellation).
     main_handle: Option<SpawnHandle>,
+    /// The angle most recently requested, if the motor was last moved by angle.
+    last_commanded: Option<u16>,
+    /// How many more times a move is attempted if the pin can't be driven.
+    pub retries: u32,
+    /// The coordinator to report to if a move fails after every retry.
+    pub(crate) supervisor: Option<Addr<Coordinator>>,
 }
 
 impl PartialEq for Motor {
     fn eq(&self, other: &Self) -> bool {
-        self.pin.number == other.pin.number
+        self.number == other.number
     }
 }
 
@@ -60,74 +210,248 @@ impl Motor {
     fn set_pulse_width(&mut self, width: Duration) -> Result<(), PinError> {
         log::debug!(
             "Setting pulse width of motor on pin {} to {:?}",
-            self.pin.number,
+            self.number,
             width
         );
         self.pulse_width = width;
-        self.pin.set_pwm(self.period, width)
+        if width != Duration::new(0, 0) {
+            self.last_width = Some(width);
+        }
+        self.output
+            .lock()
+            .map_err(|_| PinError::Panic)?
+            .set_pwm(self.period, width)
     }
 
+    /// Catches up with any move made directly through a [`Valve`](struct.Valve.html), so that
+    /// ramping resumes from where the motor actually is.
+    fn sync(&mut self) {
+        let width = match self.output.lock() {
+            Ok(output) => output.width,
+            Err(_) => return,
+        };
+        if width != self.pulse_width {
+            self.pulse_width = width;
+            if width != Duration::new(0, 0) {
+                self.last_width = Some(width);
+                self.target = width;
+            }
+        }
+    }
+
+    /// Moves the motor to the given pulse width, all at once or (if it slews) gradually.
+    ///
+    /// A motor that hasn't been driven yet is moved all at once, since there's no knowing where
+    /// to ramp from.
+    fn move_to(&mut self, width: Duration) -> Result<(), PinError> {
+        self.target = width;
+        let ramped = self.slew.map_or(false, |slew| slew > Duration::new(0, 0))
+            && self.main_handle.is_some();
+        match self.last_width {
+            Some(from) if ramped && from != width => Ok(()),
+            _ => self.set_pulse_width(width),
+        }
+    }
+
+    /// Steps the pulse width toward its target, by as much as the motor may move in one period.
+    fn step(&mut self, _context: &mut Context<Self>) {
+        self.sync();
+        let (slew, from) = match (self.slew, self.last_width) {
+            (Some(slew), Some(from)) if from != self.target => (slew, from),
+            _ => return,
+        };
+        let (start, end) = (*self.signal_range.start(), *self.signal_range.end());
+        let span = (end - start).as_nanos();
+        let max = span * self.period.as_nanos() / slew.as_nanos().max(1);
+        let max = Duration::from_nanos(max.max(1) as u64);
+        let next = if self.target > from {
+            (from + max).min(self.target)
+        } else {
+            from.checked_sub(max)
+                .map_or(self.target, |next| next.max(self.target))
+        };
+        if let Err(err) = self.set_pulse_width(next) {
+            log::warn!(
+                "Could not step motor on pin {} toward {:?}: {}",
+                self.number,
+                self.target,
+                err
+            );
+        }
+    }
+
+    /// Sets the pulse width, clamped to the motor's signal range.
+    fn set_clamped_pulse_width(&mut self, width: Duration) -> Result<(), PinError> {
+        let (start, end) = (*self.signal_range.start(), *self.signal_range.end());
+        let clamped = width.max(start).min(end);
+        if clamped != width {
+            log::warn!(
+                "Pulse width {:?} is out of range for motor on pin {}; using {:?}",
+                width,
+                self.number,
+                clamped
+            );
+        }
+        self.target = clamped;
+        self.set_pulse_width(clamped)
+    }
+
+    /// The angle (in degrees) corresponding to the current pulse width, rounded to the nearest
+    /// degree, or `None` if the signal is off.
+    pub fn angle(&self) -> Option<u16> {
+        if self.pulse_width == Duration::new(0, 0) {
+            return None;
+        }
+        let (start, end) = (*self.signal_range.start(), *self.signal_range.end());
+        let delta = (end - start).as_nanos();
+        if delta == 0 {
+            return Some(0);
+        }
+        let offset = (self.pulse_width.max(start) - start).as_nanos();
+        let range = u128::from(self.range_degrees);
+        Some(((offset * range + delta / 2) / delta) as u16)
+    }
+    /// The motor's current and most recently requested positions.
+    pub fn position(&self) -> Position {
+        Position {
+            angle: self.angle(),
+            commanded: self.last_commanded,
+        }
+    }
     /// Sets the motor's angle in degrees (relative to the closed position).
     ///
-    /// ## Panics
-    /// This method will panic if `angle` is greater than 180.
-    pub fn set_angle(&mut self, angle: u16) -> Result<(), PinError> {
-        assert!(angle <= 180);
+    /// If `angle` is beyond the motor's range of motion, an error is returned and the motor is
+    /// left where it is (though the angle is still recorded as the one commanded).
+    pub fn set_angle(&mut self, angle: u16) -> Result<(), Error> {
+        self.last_commanded = Some(angle);
+        if angle > self.range_degrees {
+            return Err(Error::Angle(angle));
+        }
+        let width = self.width_for(angle);
+        log::trace!(
+            "Setting motor angle to {} (pulse width: {:?})",
+            angle,
+            width
+        );
+        Ok(self.move_to(width)?)
+    }
+    /// The pulse width corresponding to the given angle (in degrees, relative to the open
+    /// position).
+    fn width_for(&self, angle: u16) -> Duration {
         let (start, end) = (self.signal_range.start(), self.signal_range.end());
         // Dereference, since auto-deref doesn't seem to work for std::ops::Sub?
         let (start, end) = (*start, *end);
         let delta = end - start;
-        // Assume a range of motion of 180º.
-        let range = 180;
+        let range = self.range_degrees.into();
         // Calculate the change in signal per unit angle (dT/dθ).
         let step = delta / range;
         // Multiply the step by the desired angle to get the offset from the baseline (∆T).
-        let offset = step * angle.into();
-        log::trace!(
-            "Setting motor angle to {} (pulse width: {:?})",
-            angle,
-            start + offset
-        );
-        self.set_pulse_width(start + offset)
+        start + step * angle.into()
     }
-    /// Sets the motor to the closed position (angle of 90º).
+    /// A handle for closing (or shutting) the motor's valve directly, without going through the
+    /// motor's actor.
+    pub(crate) fn valve(&self) -> Valve {
+        Valve {
+            output: Arc::clone(&self.output),
+            period: self.period,
+            closed: self.width_for(self.range_degrees / 2),
+            shut: self.width_for(self.range_degrees),
+        }
+    }
+    /// Sets the motor to the closed position (halfway through its range of motion).
     ///
     /// Fluid will flow through the valve, but not from the associated buffer.
-    pub fn close(&mut self) -> Result<(), PinError> {
-        log::trace!("Closing motor on pin {}.", self.pin.number);
-        self.set_angle(90)
+    pub fn close(&mut self) -> Result<(), Error> {
+        log::trace!("Closing motor on pin {}.", self.number);
+        self.set_angle(self.range_degrees / 2)
     }
-    /// Sets the motor to the shut position, where no fluid will flow through it.
-    pub fn shut(&mut self) -> Result<(), PinError> {
-        log::trace!("Shutting motor on pin {}.", self.pin.number);
-        self.set_angle(180)
+    /// Sets the motor to the shut position (the end of its range of motion), where no fluid will
+    /// flow through it.
+    pub fn shut(&mut self) -> Result<(), Error> {
+        log::trace!("Shutting motor on pin {}.", self.number);
+        self.set_angle(self.range_degrees)
     }
     /// Sets the motor to the open position (angle of 0º).
     ///
     /// Fluid from the associated buffer will flow through the valve.
-    pub fn open(&mut self) -> Result<(), PinError> {
-        log::trace!("Opening motor on pin {}.", self.pin.number);
+    pub fn open(&mut self) -> Result<(), Error> {
+        log::trace!("Opening motor on pin {}.", self.number);
         self.set_angle(0)
     }
     ///
     /// Constructs a new motor with the given period and signal range on the given pin number, if
     /// possible.
     ///
+    /// The motor is assumed to have 180º of motion; this can be changed with
+    /// [`range_degrees`](#structfield.range_degrees).
+    ///
     /// The motor will be set to the closed position initially.
     pub fn try_new<R>(period: Duration, range: R, pin: u16) -> Result<Self, PinError>
     where
         R: Into<RangeInclusive<Duration>>,
     {
-        let pin = Pin::try_new(pin)?;
+        let output = Output {
+            pin: Pin::try_new(pin)?,
+            width: Duration::new(0, 0),
+        };
         let signal_range = range.into();
         Ok(Self {
             period,
-            pin,
+            number: pin,
+            output: Arc::new(Mutex::new(output)),
             pulse_width: *signal_range.start(),
+            target: *signal_range.start(),
             signal_range,
+            range_degrees: 180,
+            slew: None,
+            last_width: None,
             main_handle: None,
+            last_commanded: None,
+            retries: 3,
+            supervisor: None,
         })
     }
+    /// Makes writes to the motor's (stub) pin fail, as a faulty motor would.
+    #[cfg(feature = "stub")]
+    pub fn simulate_failure(&mut self, failing: bool) {
+        if let Ok(mut output) = self.output.lock() {
+            output.pin.simulate_failure(failing);
+        }
+    }
+    /// Carries out the given message once.
+    fn apply(&mut self, message: Message) -> Result<(), Error> {
+        match message {
+            Message::Open => self.open(),
+            Message::Close => self.close(),
+            Message::Shut => self.shut(),
+            Message::Stop => {
+                log::trace!("Stopping motor motion.");
+                self.last_commanded = None;
+                // The motor stays wherever it got to.
+                self.target = self.last_width.unwrap_or_default();
+                self.set_pulse_width(Duration::new(0, 0))
+                    .map_err(Error::from)
+            }
+            Message::SetAngle(angle) => self.set_angle(angle),
+            Message::SetPulseWidth(width) => {
+                self.last_commanded = None;
+                self.set_clamped_pulse_width(width).map_err(Error::from)
+            }
+            Message::GetAngle => Ok(()),
+        }
+    }
+    /// Begins calibrating the motor, stepping its pulse width across its signal range by the given
+    /// step (see [`Calibration`](struct.Calibration.html)).
+    ///
+    /// To calibrate a motor whose signal range is unknown, construct it with a generous range
+    /// (such as 500–2500 µs), and drive it with
+    /// [`SetPulseWidth`](enum.Message.html#variant.SetPulseWidth).
+    ///
+    /// ## Panics
+    /// This method will panic if the step is zero.
+    pub fn calibrate(&self, step: Duration) -> Calibration {
+        Calibration::new(self.signal_range.clone(), step)
+    }
     /// Constructs a new motor with the given period and signal range on the given pin number.
     ///
     /// The motor will be set to the closed position initially.
@@ -145,27 +469,188 @@ impl Motor {
 
 impl Actor for Motor {
     type Context = Context<Self>;
+    fn started(&mut self, context: &mut Self::Context) {
+        if self.slew.is_some() {
+            self.main_handle = Some(context.run_interval(self.period, Self::step));
+        }
+    }
 }
 
 impl Handle<Message> for Motor {
-    type Result = ();
+    type Result = MessageResult<Message>;
     fn handle(&mut self, message: Message, _context: &mut Self::Context) -> Self::Result {
-        match message {
-            Message::Open => self.open().unwrap(),
-            Message::Close => self.close().unwrap(),
-            Message::Shut => self.shut().unwrap(),
-            Message::Stop => {
-                log::trace!("Stopping motor motion.");
-                self.set_pulse_width(Duration::new(0, 0)).unwrap()
+        self.sync();
+        let mut attempts = 1;
+        let mut result = self.apply(message);
+        // Only failures to drive the pin are worth retrying; a bad angle will stay bad.
+        while let Err(Error::Pin(ref err)) = result {
+            if attempts > self.retries {
+                break;
             }
+            log::warn!(
+                "Could not move motor on pin {} ({:?}): {}; retrying",
+                self.number,
+                message,
+                err
+            );
+            attempts += 1;
+            result = self.apply(message);
+        }
+        match result {
+            Err(Error::Pin(err)) => {
+                log::error!(
+                    "Giving up on moving motor on pin {} ({:?}) after {} attempts: {}",
+                    self.number,
+                    message,
+                    attempts,
+                    err
+                );
+                if let Some(supervisor) = &self.supervisor {
+                    supervisor.do_send(Failure {
+                        pin: self.number,
+                        message,
+                        attempts,
+                        error: Error::Pin(err),
+                    });
+                }
+            }
+            Err(err) => log::warn!(
+                "Could not move motor on pin {} ({:?}): {}",
+                self.number,
+                message,
+                err
+            ),
+            Ok(()) => {}
+        }
+        MessageResult(self.position())
+    }
+}
+
+/// Motor calibration error type.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CalibrationError {
+    /// The open or closed position hasn't been confirmed.
+    Unconfirmed,
+    /// The closed position's signal isn't longer than the open position's, so the signal range
+    /// wouldn't increase.
+    NotIncreasing {
+        /// The signal confirmed for the open position.
+        open: Duration,
+        /// The signal confirmed for the closed position.
+        closed: Duration,
+    },
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unconfirmed => write!(f, "The open and closed positions must both be confirmed"),
+            Self::NotIncreasing { open, closed } => write!(
+                f,
+                "The closed position ({} µs) must have a longer signal than the open position \
+                 ({} µs)",
+                closed.as_micros(),
+                open.as_micros()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CalibrationError {}
+
+/// Finds a motor's signal range by stepping its pulse width across a span of signals, as the
+/// operator confirms where the valve is open and where it's closed.
+///
+/// Iterating yields each pulse width to try in turn. Once both positions are confirmed, the
+/// [`signal_range`](#method.signal_range) is ready to be written into the motor's configuration.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    /// The signals to step across.
+    span: RangeInclusive<Duration>,
+    /// How far apart successive signals are.
+    step: Duration,
+    /// The signal most recently tried, if any.
+    width: Option<Duration>,
+    /// The signal confirmed for the open position, if any.
+    open: Option<Duration>,
+    /// The signal confirmed for the closed position, if any.
+    closed: Option<Duration>,
+}
+
+impl Calibration {
+    /// Begins a calibration stepping across the given span of signals by the given step.
+    ///
+    /// ## Panics
+    /// This method will panic if the step is zero.
+    pub fn new<R>(span: R, step: Duration) -> Self
+    where
+        R: Into<RangeInclusive<Duration>>,
+    {
+        assert!(
+            step > Duration::new(0, 0),
+            "Calibration step must be nonzero."
+        );
+        Self {
+            span: span.into(),
+            step,
+            width: None,
+            open: None,
+            closed: None,
+        }
+    }
+    /// The signal most recently tried, if any.
+    pub fn width(&self) -> Option<Duration> {
+        self.width
+    }
+    /// Confirms that the signal most recently tried puts the valve in the open position.
+    pub fn confirm_open(&mut self) {
+        self.open = self.width;
+    }
+    /// Confirms that the signal most recently tried puts the valve in the closed position.
+    pub fn confirm_closed(&mut self) {
+        self.closed = self.width;
+    }
+    /// The signal range corresponding to the confirmed positions.
+    pub fn signal_range(&self) -> Result<RangeInclusive<Duration>, CalibrationError> {
+        match (self.open, self.closed) {
+            (Some(open), Some(closed)) => Self::range_from(open, closed),
+            _ => Err(CalibrationError::Unconfirmed),
         }
     }
+    /// The signal range in which the given signals are the open and closed positions.
+    ///
+    /// The open position is the start of the signal range and the closed position is halfway
+    /// through it (see [`Motor::close`](struct.Motor.html#method.close)), so the range ends as far
+    /// past the closed position as the open position is before it.
+    pub fn range_from(
+        open: Duration,
+        closed: Duration,
+    ) -> Result<RangeInclusive<Duration>, CalibrationError> {
+        if closed <= open {
+            return Err(CalibrationError::NotIncreasing { open, closed });
+        }
+        Ok(open..=closed + (closed - open))
+    }
+}
+
+impl Iterator for Calibration {
+    type Item = Duration;
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = match self.width {
+            Some(width) => width + self.step,
+            None => *self.span.start(),
+        };
+        if next > *self.span.end() {
+            return None;
+        }
+        self.width = Some(next);
+        Some(next)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    // This test makes sure the panic in validate_motor_angle isn't from constructing the motor and unwrapping it.
     #[test]
     fn make_fake_motor() {
         let _motor = Motor::try_new(
@@ -176,7 +661,6 @@ mod tests {
         .unwrap();
     }
     #[test]
-    #[should_panic]
     fn validate_motor_angle() {
         let mut motor = Motor::try_new(
             Duration::new(2, 0),
@@ -184,6 +668,166 @@ mod tests {
             1,
         )
         .unwrap();
-        let _ = motor.set_angle(181);
+        motor.set_angle(90).unwrap();
+        let width = motor.pulse_width;
+        assert!(matches!(motor.set_angle(181), Err(Error::Angle(181))));
+        assert_eq!(motor.pulse_width, width);
+    }
+    #[test]
+    fn angle_pulse_widths() {
+        let mut motor = Motor::try_new(
+            Duration::from_millis(20),
+            Duration::from_micros(600)..=Duration::from_micros(2400),
+            1,
+        )
+        .unwrap();
+        for &(angle, width) in &[(0, 600), (45, 1050), (90, 1500)] {
+            motor.set_angle(angle).unwrap();
+            assert_eq!(motor.pulse_width, Duration::from_micros(width));
+        }
+    }
+    #[test]
+    fn clamped_pulse_width() {
+        let mut motor = Motor::try_new(
+            Duration::from_millis(20),
+            Duration::from_micros(600)..=Duration::from_micros(2400),
+            1,
+        )
+        .unwrap();
+        motor
+            .set_clamped_pulse_width(Duration::from_micros(100))
+            .unwrap();
+        assert_eq!(motor.pulse_width, Duration::from_micros(600));
+        motor
+            .set_clamped_pulse_width(Duration::from_micros(3000))
+            .unwrap();
+        assert_eq!(motor.pulse_width, Duration::from_micros(2400));
+        motor
+            .set_clamped_pulse_width(Duration::from_micros(1200))
+            .unwrap();
+        assert_eq!(motor.pulse_width, Duration::from_micros(1200));
+    }
+    #[test]
+    fn report_angle() {
+        use futures::Future;
+        let mut system = System::new("motor");
+        let motor = Motor::try_new(
+            Duration::from_millis(20),
+            Duration::from_micros(600)..=Duration::from_micros(2400),
+            1,
+        )
+        .unwrap();
+        let addr = motor.start();
+        let query = addr
+            .send(Message::Close)
+            .and_then(move |_| addr.send(Message::GetAngle));
+        let position = system.block_on(query).unwrap();
+        assert_eq!(
+            position,
+            Position {
+                angle: Some(90),
+                commanded: Some(90)
+            }
+        );
+    }
+    #[test]
+    fn narrow_range_of_motion() {
+        let motor = || {
+            Motor::try_new(
+                Duration::from_millis(20),
+                Duration::from_micros(600)..=Duration::from_micros(2400),
+                1,
+            )
+            .unwrap()
+        };
+        let mut wide = motor();
+        let mut narrow = motor();
+        narrow.range_degrees = 120;
+        wide.set_angle(90).unwrap();
+        narrow.set_angle(90).unwrap();
+        assert_eq!(wide.pulse_width, Duration::from_micros(1500));
+        assert_eq!(narrow.pulse_width, Duration::from_micros(1950));
+        assert!(matches!(narrow.set_angle(150), Err(Error::Angle(150))));
+        // Both motors close halfway through their signal range.
+        wide.close().unwrap();
+        narrow.close().unwrap();
+        assert_eq!(narrow.pulse_width, wide.pulse_width);
+        narrow.shut().unwrap();
+        assert_eq!(narrow.pulse_width, Duration::from_micros(2400));
+    }
+    #[test]
+    fn calibrated_range() {
+        let micros = Duration::from_micros;
+        let mut motor = Motor::try_new(micros(20_000), micros(500)..=micros(2500), 1).unwrap();
+        let mut calibration = motor.calibrate(micros(100));
+        assert_eq!(
+            calibration.signal_range(),
+            Err(CalibrationError::Unconfirmed)
+        );
+        // The operator sees the valve open at 600 µs and closed at 1500 µs.
+        while let Some(width) = calibration.next() {
+            if width == micros(600) {
+                calibration.confirm_open();
+            } else if width == micros(1500) {
+                calibration.confirm_closed();
+                break;
+            }
+        }
+        let range = calibration.signal_range().unwrap();
+        assert!(range.start() < range.end());
+        assert_eq!(range, micros(600)..=micros(2400));
+        // A motor with the calibrated range returns to the confirmed positions.
+        motor.signal_range = range;
+        motor.open().unwrap();
+        assert_eq!(motor.pulse_width, micros(600));
+        motor.close().unwrap();
+        assert_eq!(motor.pulse_width, micros(1500));
+        assert_eq!(
+            Calibration::range_from(micros(1500), micros(600)),
+            Err(CalibrationError::NotIncreasing {
+                open: micros(1500),
+                closed: micros(600)
+            })
+        );
+        assert!(Calibration::range_from(micros(600), micros(600)).is_err());
+    }
+    #[test]
+    fn slewed_move() {
+        use std::sync::mpsc;
+        let (ms, micros) = (Duration::from_millis, Duration::from_micros);
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Motor::create(move |context| {
+                let mut motor = Motor::try_new(ms(20), micros(600)..=micros(2400), 1).unwrap();
+                // Moving across the whole range takes five periods.
+                motor.slew = Some(ms(100));
+                // The motor hasn't been driven yet, so this move is immediate.
+                motor.close().unwrap();
+                let widths = tx.clone();
+                context.run_later(ms(10), move |motor, _| {
+                    motor.open().unwrap();
+                    widths.send(motor.pulse_width).unwrap();
+                });
+                let widths = tx.clone();
+                context.run_later(ms(50), move |motor, _| {
+                    widths.send(motor.pulse_width).unwrap();
+                });
+                context.run_later(ms(150), move |motor, _| {
+                    tx.send(motor.pulse_width).unwrap();
+                    System::current().stop();
+                });
+                motor
+            });
+        });
+        let widths = rx.try_iter().collect::<Vec<_>>();
+        assert_eq!(widths[0], micros(1500));
+        assert!(widths[1] > micros(600) && widths[1] < micros(1500));
+        assert_eq!(widths[2], micros(600));
+    }
+    #[test]
+    fn calibration_steps() {
+        let micros = Duration::from_micros;
+        let widths = Calibration::new(micros(500)..=micros(750), micros(100)).collect::<Vec<_>>();
+        assert_eq!(widths, vec![micros(500), micros(600), micros(700)]);
     }
 }
@@ -27,11 +27,51 @@ pub trait Out {
     }
 }
 
+/// Trait representing a general input device.
+pub trait In {
+    /// Whether the input is currently high.
+    fn is_high(&self) -> Result<bool, Error>;
+}
+
+/// Sets several outputs to the given levels in a single synchronous pass.
+///
+/// Nothing else can write to the outputs partway through the batch, so this should be used
+/// wherever an intermediate combination of levels would be unsafe.
+pub fn set_batch<'a, O, I>(writes: I)
+where
+    O: Out + 'a,
+    I: IntoIterator<Item = (&'a mut O, bool)>,
+{
+    for (output, level) in writes {
+        output.set(level);
+    }
+}
+
+/// Sets several PWM outputs to the given (period, pulse width) signals in a single synchronous
+/// pass.
+///
+/// Every output is written even if an earlier one fails; the first error is returned.
+pub fn set_pwm_batch<'a, P, I>(writes: I) -> Result<(), Error>
+where
+    P: Pwm + 'a,
+    I: IntoIterator<Item = (&'a mut P, Duration, Duration)>,
+{
+    let mut result = Ok(());
+    for (output, period, pulse_width) in writes {
+        if let Err(err) = output.set_pwm(period, pulse_width) {
+            if result.is_ok() {
+                result = Err(err);
+            }
+        }
+    }
+    result
+}
+
 #[cfg(not(feature = "stub"))]
 mod gpio {
-    use super::{Error, Out, Pwm};
+    use super::{Error, In, Out, Pwm};
     use lazy_static::lazy_static;
-    pub(crate) use rppal::gpio::{Gpio, OutputPin};
+    pub(crate) use rppal::gpio::{Gpio, InputPin, OutputPin};
     use std::time::Duration;
     lazy_static! {
         pub static ref GPIO: Gpio = Gpio::new().unwrap();
@@ -39,6 +79,14 @@ mod gpio {
     pub(crate) fn pin(number: u8) -> Result<OutputPin, Error> {
         Ok(GPIO.get(number).map(|pin| pin.into_output())?)
     }
+    pub(crate) fn input(number: u8) -> Result<InputPin, Error> {
+        Ok(GPIO.get(number).map(|pin| pin.into_input())?)
+    }
+    impl In for InputPin {
+        fn is_high(&self) -> Result<bool, Error> {
+            Ok(Self::is_high(self))
+        }
+    }
     impl Pwm for OutputPin {
         fn set_pwm(&mut self, period: Duration, pulse_width: Duration) -> Result<(), Error> {
             if pulse_width == Duration::new(0, 0) {
@@ -60,20 +108,70 @@ mod gpio {
     }
 }
 
+/// A write to a stub output, as recorded for tests to inspect.
+#[cfg(feature = "stub")]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Write {
+    /// The output was set high.
+    High,
+    /// The output was set low.
+    Low,
+    /// The output was given a PWM signal with the given period and pulse width.
+    Pwm(Duration, Duration),
+}
+
 #[cfg(feature = "stub")]
 mod stub {
-    use super::{Error, Out, Pwm};
-    use std::time::Duration;
-    #[derive(Debug)]
-    pub(crate) struct Stub;
-    impl Pwm for Stub {
-        fn set_pwm(&mut self, _: Duration, _: Duration) -> Result<(), Error> {
-            Ok(())
+    use super::{Error, In, Out, Pwm, Write};
+    use std::{collections::VecDeque, time::Duration};
+    /// How many of the most recent writes a stub output keeps.
+    const RECORD_LIMIT: usize = 256;
+    /// A stub output, which records the writes made to it and can be made to fail to simulate a
+    /// faulty device.
+    ///
+    /// Only the most recent writes are kept, so a long-running stubbed process doesn't grow
+    /// without bound.
+    #[derive(Debug, Default)]
+    pub(crate) struct RecordingStub {
+        pub(crate) failing: bool,
+        pub(crate) writes: VecDeque<Write>,
+    }
+    impl RecordingStub {
+        fn record(&mut self, write: Write) {
+            if self.writes.len() == RECORD_LIMIT {
+                self.writes.pop_front();
+            }
+            self.writes.push_back(write);
         }
     }
-    impl Out for Stub {
-        fn set_high(&mut self) {}
-        fn set_low(&mut self) {}
+    /// A stub input, whose level can be set to simulate external changes.
+    #[derive(Debug, Default)]
+    pub(crate) struct StubInput {
+        pub(crate) high: bool,
+    }
+    impl In for StubInput {
+        fn is_high(&self) -> Result<bool, Error> {
+            Ok(self.high)
+        }
+    }
+    impl Pwm for RecordingStub {
+        fn set_pwm(&mut self, period: Duration, pulse_width: Duration) -> Result<(), Error> {
+            if self.failing {
+                let err = std::io::Error::new(std::io::ErrorKind::Other, "simulated failure");
+                Err(Error::Io(err))
+            } else {
+                self.record(Write::Pwm(period, pulse_width));
+                Ok(())
+            }
+        }
+    }
+    impl Out for RecordingStub {
+        fn set_high(&mut self) {
+            self.record(Write::High);
+        }
+        fn set_low(&mut self) {
+            self.record(Write::Low);
+        }
     }
 }
 
@@ -134,7 +232,7 @@ pub struct Pin {
     #[cfg(not(feature = "stub"))]
     output: self::gpio::OutputPin,
     #[cfg(feature = "stub")]
-    output: self::stub::Stub,
+    output: self::stub::RecordingStub,
 }
 
 impl Pin {
@@ -149,12 +247,41 @@ impl Pin {
     /// Creates a stub Pin output struct on the given pin number.
     #[cfg(feature = "stub")]
     pub fn try_new(number: u16) -> Result<Self, Error> {
-        log::info!("Using a stub for GPIO; writes will be ignored");
+        log::info!("Using a stub for GPIO; writes will be recorded but not performed");
         Ok(Self {
-            output: self::stub::Stub,
+            output: self::stub::RecordingStub::default(),
             number,
         })
     }
+    /// Sets whether PWM writes to the stub output will fail.
+    #[cfg(feature = "stub")]
+    pub fn simulate_failure(&mut self, failing: bool) {
+        self.output.failing = failing;
+    }
+    /// The most recent writes to the stub output, oldest first.
+    #[cfg(feature = "stub")]
+    pub fn writes(&self) -> Vec<Write> {
+        self.output.writes.iter().copied().collect()
+    }
+    /// Whether the stub output was last set high.
+    #[cfg(feature = "stub")]
+    pub fn is_set_high(&self) -> bool {
+        self.output
+            .writes
+            .iter()
+            .rev()
+            .find_map(|write| match write {
+                Write::High => Some(true),
+                Write::Low => Some(false),
+                Write::Pwm(_, _) => None,
+            })
+            .unwrap_or(false)
+    }
+    /// Forgets the writes recorded by the stub output.
+    #[cfg(feature = "stub")]
+    pub fn clear_writes(&mut self) {
+        self.output.writes.clear();
+    }
     /// Sets the pin to the desired state.
     pub fn set(&mut self, high: bool) {
         self.output.set(high);
@@ -169,6 +296,49 @@ impl Pin {
     }
 }
 
+/// Represents a GPIO input pin.
+#[derive(Debug)]
+pub struct Input {
+    pub(crate) number: u16,
+    #[cfg(not(feature = "stub"))]
+    input: self::gpio::InputPin,
+    #[cfg(feature = "stub")]
+    input: self::stub::StubInput,
+}
+
+impl Input {
+    /// Attempts to create an Input struct on the given pin number.
+    #[cfg(not(feature = "stub"))]
+    pub fn try_new(number: u16) -> Result<Self, Error> {
+        Ok(Self {
+            input: gpio::input(number as u8)?,
+            number,
+        })
+    }
+    /// Creates a stub Input struct on the given pin number.
+    ///
+    /// The input will read low until [`simulate`](#method.simulate) is called.
+    #[cfg(feature = "stub")]
+    pub fn try_new(number: u16) -> Result<Self, Error> {
+        log::info!("Using a stub for GPIO; inputs will read low");
+        Ok(Self {
+            input: self::stub::StubInput::default(),
+            number,
+        })
+    }
+    /// Sets the level the stub input will read.
+    #[cfg(feature = "stub")]
+    pub fn simulate(&mut self, high: bool) {
+        self.input.high = high;
+    }
+}
+
+impl In for Input {
+    fn is_high(&self) -> Result<bool, Error> {
+        In::is_high(&self.input)
+    }
+}
+
 impl Out for Pin {
     fn set_high(&mut self) {
         self.output.set_high()
@@ -184,3 +354,63 @@ impl Pwm for Pin {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[derive(Debug, Default)]
+    struct Recorder {
+        levels: Vec<bool>,
+    }
+    impl Out for Recorder {
+        fn set_high(&mut self) {
+            self.levels.push(true);
+        }
+        fn set_low(&mut self) {
+            self.levels.push(false);
+        }
+    }
+    #[derive(Debug, Default)]
+    struct PwmRecorder {
+        failing: bool,
+        widths: Vec<Duration>,
+    }
+    impl Pwm for PwmRecorder {
+        fn set_pwm(&mut self, _period: Duration, pulse_width: Duration) -> Result<(), Error> {
+            if self.failing {
+                return Err(Error::Panic);
+            }
+            self.widths.push(pulse_width);
+            Ok(())
+        }
+    }
+    #[test]
+    fn batch_sets_every_output() {
+        let mut outputs = [
+            Recorder::default(),
+            Recorder::default(),
+            Recorder::default(),
+        ];
+        set_batch(outputs.iter_mut().map(|output| (output, true)));
+        set_batch(outputs.iter_mut().map(|output| (output, false)));
+        for output in outputs.iter() {
+            assert_eq!(output.levels, vec![true, false]);
+        }
+    }
+    #[test]
+    fn pwm_batch_writes_past_failures() {
+        let (period, width) = (Duration::from_millis(20), Duration::from_micros(1500));
+        let mut outputs = [
+            PwmRecorder::default(),
+            PwmRecorder {
+                failing: true,
+                ..PwmRecorder::default()
+            },
+            PwmRecorder::default(),
+        ];
+        let result = set_pwm_batch(outputs.iter_mut().map(|output| (output, period, width)));
+        assert!(matches!(result, Err(Error::Panic)));
+        assert_eq!(outputs[0].widths, vec![width]);
+        assert_eq!(outputs[2].widths, vec![width]);
+    }
+}
@@ -1,9 +1,11 @@
 //! Pump management.
+use std::io;
 use std::ops::Not;
 use std::thread;
+use std::time::Duration;
 
 use crate::actix::*;
-use crate::pin::{Error as PinError, Pin};
+use crate::pin::{set_batch, Error as PinError, Out, Pin, Pwm};
 
 /// Messages that can be sent to the pump to change its direction or turn it off.
 #[derive(Clone, Copy, Debug)]
@@ -14,6 +16,12 @@ pub enum Message {
     Drain,
     /// Asks the pump to stop.
     Stop,
+    /// Asks the pump to run at the given speed (duty cycle, from 0 to 1).
+    ///
+    /// Speeds outside of this range are clamped to it; speeds that aren't finite are refused.
+    SetSpeed(f32),
+    /// Asks the pump which direction it's running in, without changing anything.
+    GetDirection,
 }
 
 impl ActixMessage for Message {
@@ -21,7 +29,7 @@ impl ActixMessage for Message {
 }
 
 /// The direction of a pump.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Direction {
     /// The pump should run in the forward direction (toward the sample), perfusing any sample.
     Forward,
@@ -42,6 +50,20 @@ impl Not for Direction {
 /// Pump movement result type.
 pub type Result<T> = std::result::Result<T, PinError>;
 
+/// Drives a pin with the given duty cycle.
+///
+/// Full and zero speeds are written as plain digital levels rather than PWM.
+fn drive<P: Out + Pwm>(pin: &mut P, period: Duration, speed: f32) -> Result<()> {
+    if speed > 0.0 && speed < 1.0 {
+        pin.set_pwm(period, period.mul_f32(speed))
+    } else {
+        // A pulse width of zero clears any PWM.
+        pin.set_pwm(period, Duration::new(0, 0))?;
+        pin.set(speed > 0.0);
+        Ok(())
+    }
+}
+
 /// Represents a pump.
 ///
 /// ## Notes
@@ -53,7 +75,8 @@ pub type Result<T> = std::result::Result<T, PinError>;
 ///
 /// ### Diagram
 /// Here is a circuit diagram showing the meaning of each pin number.
-/// Each pin controls a relay/transistor in the H-bridge.
+/// Each pin controls a relay/transistor in the H-bridge. The speed of the pump is controlled by
+/// modulating the low-side pin (3 when running forward, 2 when running backward).
 /// ```plaintext
 ///  +-----+-----+
 ///  |     0     1
@@ -69,6 +92,15 @@ pub struct Pump {
     direction: Option<Direction>,
     /// Whether directions should be reversed.
     pub invert: bool,
+    /// The period of the PWM signal used to control the pump's speed.
+    pub period: Duration,
+    /// The speed (duty cycle) at which the pump should run.
+    speed: f32,
+    /// How long the pump is left stopped before changing direction.
+    pub dead_time: Duration,
+    /// Counts the commands given to the pump, so that a change of direction waiting out the dead
+    /// time can tell whether it has been superseded.
+    commands: u64,
 }
 
 impl PartialEq for Pump {
@@ -95,6 +127,10 @@ impl Pump {
             direction: None,
             pins,
             invert: false,
+            period: Duration::from_millis(10),
+            speed: 1.0,
+            dead_time: Duration::from_millis(20),
+            commands: 0,
         })
     }
     /// Creates a new pump using the given GPIO pin numbers.
@@ -107,8 +143,9 @@ impl Pump {
     }
     /// Changes the pump direction to the specified direction.
     ///
-    /// If the pump is not already stopped, it will be stopped and a wait of 20 ms will be added to
-    /// prevent sparks, short-circuits, etc.
+    /// If the pump is not already stopped, it will be stopped and the thread will sleep for the
+    /// [dead time](#structfield.dead_time) to prevent sparks, short-circuits, etc. The pump actor
+    /// schedules the change instead; see [`switch_to`](#method.switch_to).
     ///
     /// ## Notes
     /// If [`invert`](#structfield.invert) is `true`, `direction` will be inverted.
@@ -121,24 +158,59 @@ impl Pump {
             if !self.is_stopped() {
                 self.stop()?;
                 // Sleep to make sure we avoid Bad Things™️
-                thread::sleep(std::time::Duration::from_millis(20));
+                thread::sleep(self.dead_time);
             }
-            let direction = if self.invert { !direction } else { direction };
-            let pins = match direction {
-                Direction::Forward => (0, 3),
-                Direction::Backward => (1, 2),
-            };
-            let (top, bottom) = (pins.0, pins.1);
-            self.pins[top].set_high();
-            self.pins[bottom].set_high();
+            let (top, bottom) = self.pins_for(direction);
+            set_batch(
+                self.pins
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|&(i, _)| i == top || i == bottom)
+                    .map(|(_, pin)| (pin, true)),
+            );
+            self.direction = Some(direction);
+            self.apply_speed()?;
         } else {
-            for i in 0..4 {
-                self.pins[i].set_low();
+            if let Some(current) = self.direction {
+                // Make sure the low-side pin isn't left modulating.
+                let (_, bottom) = self.pins_for(current);
+                self.pins[bottom].set_pwm(self.period, Duration::new(0, 0))?;
             }
+            set_batch(self.pins.iter_mut().map(|pin| (pin, false)));
+            self.direction = None;
         }
-        self.direction = direction;
         Ok(direction)
     }
+    /// The (high-side, low-side) pins to drive for the given direction.
+    fn pins_for(&self, direction: Direction) -> (usize, usize) {
+        let direction = if self.invert { !direction } else { direction };
+        match direction {
+            Direction::Forward => (0, 3),
+            Direction::Backward => (1, 2),
+        }
+    }
+    /// Applies the current speed to the pump, if it's running.
+    fn apply_speed(&mut self) -> Result<()> {
+        if let Some(direction) = self.direction {
+            let (_, bottom) = self.pins_for(direction);
+            drive(&mut self.pins[bottom], self.period, self.speed)?;
+        }
+        Ok(())
+    }
+    /// Sets the speed (duty cycle) at which the pump runs, clamped to the range 0–1.
+    ///
+    /// The speed is kept when the direction changes. A speed that isn't finite (such as NaN) is
+    /// refused, leaving the pump as it was.
+    pub fn set_speed(&mut self, speed: f32) -> Result<Option<Direction>> {
+        log::trace!("Setting pump speed to {}", speed);
+        if !speed.is_finite() {
+            let message = format!("Invalid pump speed: {}", speed);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+        }
+        self.speed = speed.max(0.0).min(1.0);
+        self.apply_speed()?;
+        Ok(self.direction)
+    }
     /// Switches the pump to the forward direction.
     pub fn perfuse(&mut self) -> Result<Option<Direction>> {
         log::trace!("Setting pump to perfuse");
@@ -154,6 +226,30 @@ impl Pump {
         log::trace!("Stopping pump");
         self.set_direction(None)
     }
+    /// Changes the pump direction from within the actor, without blocking it.
+    ///
+    /// If the pump is running, it's stopped immediately and started in the new direction once the
+    /// dead time has passed. The direction being switched to is returned either way.
+    fn switch_to(
+        &mut self,
+        direction: Direction,
+        context: &mut <Self as Actor>::Context,
+    ) -> Result<Option<Direction>> {
+        if self.is_stopped() {
+            return self.set_direction(direction);
+        }
+        self.stop()?;
+        let command = self.commands;
+        context.run_later(self.dead_time, move |pump, _| {
+            if pump.commands != command {
+                return;
+            }
+            if let Err(err) = pump.set_direction(direction) {
+                log::error!("Could not change pump direction: {}", err);
+            }
+        });
+        Ok(Some(direction))
+    }
     /// Whether the pump is currently stopped.
     pub fn is_stopped(&self) -> bool {
         self.direction.is_none()
@@ -166,11 +262,187 @@ impl Actor for Pump {
 
 impl Handle<Message> for Pump {
     type Result = Result<Option<Direction>>;
-    fn handle(&mut self, message: Message, _context: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, message: Message, context: &mut Self::Context) -> Self::Result {
+        if let Message::Perfuse | Message::Drain | Message::Stop = message {
+            // A newer command supersedes any change of direction still waiting on the dead time.
+            self.commands = self.commands.wrapping_add(1);
+        }
         match message {
-            Message::Perfuse => self.perfuse(),
-            Message::Drain => self.drain(),
+            Message::Perfuse => {
+                log::trace!("Setting pump to perfuse");
+                self.switch_to(Direction::Forward, context)
+            }
+            Message::Drain => {
+                log::trace!("Setting pump to drain");
+                self.switch_to(Direction::Backward, context)
+            }
             Message::Stop => self.stop(),
+            Message::SetSpeed(speed) => self.set_speed(speed),
+            Message::GetDirection => Ok(self.direction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[derive(Debug, Default)]
+    struct Recorder {
+        pulses: Vec<(Duration, Duration)>,
+        levels: Vec<bool>,
+    }
+    impl Out for Recorder {
+        fn set_high(&mut self) {
+            self.levels.push(true);
+        }
+        fn set_low(&mut self) {
+            self.levels.push(false);
         }
     }
+    impl Pwm for Recorder {
+        fn set_pwm(&mut self, period: Duration, pulse_width: Duration) -> Result<()> {
+            self.pulses.push((period, pulse_width));
+            Ok(())
+        }
+    }
+    #[test]
+    fn half_speed() {
+        let mut pin = Recorder::default();
+        let period = Duration::from_millis(10);
+        drive(&mut pin, period, 0.5).unwrap();
+        assert_eq!(pin.pulses, vec![(period, Duration::from_millis(5))]);
+        assert!(pin.levels.is_empty());
+        drive(&mut pin, period, 1.0).unwrap();
+        assert_eq!(pin.pulses[1], (period, Duration::new(0, 0)));
+        assert_eq!(pin.levels, vec![true]);
+    }
+    #[test]
+    fn reports_direction() {
+        use futures::Future;
+        let mut system = System::new("pump");
+        let addr = Pump::try_new([1, 2, 3, 4]).unwrap().start();
+        let query = addr
+            .send(Message::Perfuse)
+            .and_then(move |_| addr.send(Message::GetDirection));
+        let direction = system.block_on(query).unwrap().unwrap();
+        assert_eq!(direction, Some(Direction::Forward));
+    }
+    #[test]
+    fn reverse_after_dead_time() {
+        use std::sync::mpsc;
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Pump::create(move |context| {
+                let mut pump = Pump::try_new([1, 2, 3, 4]).unwrap();
+                pump.dead_time = Duration::from_millis(50);
+                pump.perfuse().unwrap();
+                pump.switch_to(Direction::Backward, context).unwrap();
+                tx.send(pump.direction).unwrap();
+                let early = tx.clone();
+                context.run_later(Duration::from_millis(25), move |pump, _| {
+                    early.send(pump.direction).unwrap();
+                });
+                context.run_later(Duration::from_millis(100), move |pump, _| {
+                    tx.send(pump.direction).unwrap();
+                    System::current().stop();
+                });
+                pump
+            });
+        });
+        let directions = rx.iter().collect::<Vec<_>>();
+        assert_eq!(directions, vec![None, None, Some(Direction::Backward)]);
+    }
+    #[test]
+    fn stop_during_dead_time() {
+        use std::sync::mpsc;
+        let (tx, rx) = mpsc::channel();
+        System::run(move || {
+            Pump::create(move |context| {
+                let mut pump = Pump::try_new([1, 2, 3, 4]).unwrap();
+                pump.dead_time = Duration::from_millis(50);
+                pump.perfuse().unwrap();
+                // The stop comes in while the pump is waiting to drain.
+                context.address().do_send(Message::Drain);
+                context.address().do_send(Message::Stop);
+                context.run_later(Duration::from_millis(100), move |pump, _| {
+                    tx.send(pump.direction).unwrap();
+                    System::current().stop();
+                });
+                pump
+            });
+        });
+        assert_eq!(rx.recv().unwrap(), None);
+    }
+    #[test]
+    fn h_bridge_pattern() {
+        use crate::pin::Write::{self, High, Low};
+        let writes = |pump: &mut Pump| {
+            let writes = pump.pins.iter().map(Pin::writes).collect::<Vec<_>>();
+            pump.pins.iter_mut().for_each(Pin::clear_writes);
+            writes
+        };
+        let period = Duration::from_millis(10);
+        let clear = Write::Pwm(period, Duration::new(0, 0));
+        let half = Write::Pwm(period, Duration::from_millis(5));
+        let mut pump = Pump::try_new([1, 2, 3, 4]).unwrap();
+        pump.dead_time = Duration::new(0, 0);
+        // Forward drives the first high side and the last low side, which carries the speed.
+        pump.perfuse().unwrap();
+        assert_eq!(
+            writes(&mut pump),
+            vec![vec![High], vec![], vec![], vec![High, clear, High]]
+        );
+        pump.set_speed(0.5).unwrap();
+        // Everything is turned off before the other pair is driven.
+        pump.drain().unwrap();
+        assert_eq!(
+            writes(&mut pump),
+            vec![
+                vec![Low],
+                vec![Low, High],
+                vec![Low, High, half],
+                vec![half, clear, Low]
+            ]
+        );
+        pump.stop().unwrap();
+        assert_eq!(
+            writes(&mut pump),
+            vec![vec![Low], vec![Low], vec![clear, Low], vec![Low]]
+        );
+    }
+    #[test]
+    fn inverted_direction() {
+        let levels = |pump: &Pump| {
+            pump.pins
+                .iter()
+                .map(|pin| pin.is_set_high())
+                .collect::<Vec<_>>()
+        };
+        let mut normal = Pump::try_new([1, 2, 3, 4]).unwrap();
+        let mut inverted = Pump::try_new([1, 2, 3, 4]).unwrap();
+        inverted.invert = true;
+        normal.drain().unwrap();
+        inverted.perfuse().unwrap();
+        assert_eq!(levels(&inverted), vec![false, true, true, false]);
+        assert_eq!(levels(&inverted), levels(&normal));
+        normal.dead_time = Duration::new(0, 0);
+        inverted.dead_time = Duration::new(0, 0);
+        normal.perfuse().unwrap();
+        inverted.drain().unwrap();
+        assert_eq!(levels(&inverted), vec![true, false, false, true]);
+        assert_eq!(levels(&inverted), levels(&normal));
+    }
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn clamp_speed() {
+        let mut pump = Pump::try_new([1, 2, 3, 4]).unwrap();
+        pump.set_speed(1.5).unwrap();
+        assert_eq!(pump.speed, 1.0);
+        pump.set_speed(-0.5).unwrap();
+        assert_eq!(pump.speed, 0.0);
+        pump.set_speed(0.5).unwrap();
+        assert!(pump.set_speed(f32::NAN).is_err());
+        assert!(pump.set_speed(f32::INFINITY).is_err());
+        assert_eq!(pump.speed, 0.5);
+    }
 }
@@ -0,0 +1,31 @@
+//! Ambient condition sensing.
+use std::{fmt::Debug, io::Result, time::SystemTime};
+
+/// A timestamped reading of the ambient temperature (ºC) and relative humidity (%).
+pub type Reading = (SystemTime, f64, f64);
+
+/// Trait representing a sensor capable of measuring ambient conditions.
+pub trait Sensor: Debug + Send {
+    /// Measures the current temperature (ºC) and relative humidity (%).
+    fn sample(&mut self) -> Result<(f64, f64)>;
+    /// Takes a timestamped reading.
+    fn read(&mut self) -> Result<Reading> {
+        let (temperature, humidity) = self.sample()?;
+        Ok((SystemTime::now(), temperature, humidity))
+    }
+}
+
+/// A stub sensor which always reports the same conditions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fixed {
+    /// The temperature to report (ºC).
+    pub temperature: f64,
+    /// The relative humidity to report (%).
+    pub humidity: f64,
+}
+
+impl Sensor for Fixed {
+    fn sample(&mut self) -> Result<(f64, f64)> {
+        Ok((self.temperature, self.humidity))
+    }
+}
@@ -0,0 +1,166 @@
+//! Bearer-token authentication.
+use super::{
+    job::{chamber, Error},
+    state::State as AppState,
+};
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        Method,
+    },
+    middleware::{Middleware, Started},
+    HttpRequest, ResponseError,
+};
+
+/// Middleware which rejects requests to a chamber without one of its configured tokens.
+///
+/// Tokens are given in an `Authorization: Bearer ...` header. If the chamber is configured with
+/// a public status, requests which only read (`GET` and `HEAD`) are let through without one,
+/// unless they open a WebSocket (which can control the job).
+///
+/// Requests which aren't for a particular chamber (such as those for saved protocols) are
+/// accepted with any chamber's token, and reads are only let through without one if every
+/// chamber's status is public.
+#[derive(Clone, Copy, Debug)]
+pub struct Authenticate;
+
+impl Authenticate {
+    /// The bearer token given with the request, if any.
+    fn token(req: &HttpRequest<AppState>) -> Option<&str> {
+        req.headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+    }
+}
+
+impl Middleware<AppState> for Authenticate {
+    fn start(&self, req: &HttpRequest<AppState>) -> actix_web::Result<Started> {
+        let auths = if req.match_info().get("chamber").is_some() {
+            // Requests for unknown chambers are left to the handlers to reject.
+            chamber(req)
+                .ok()
+                .and_then(|chamber| chamber.auth.as_ref())
+                .into_iter()
+                .collect::<Vec<_>>()
+        } else {
+            let chambers = req.state().chambers.iter();
+            chambers
+                .filter_map(|chamber| chamber.auth.as_ref())
+                .collect()
+        };
+        if auths.is_empty() {
+            return Ok(Started::Done);
+        }
+        let reads = *req.method() == Method::GET || *req.method() == Method::HEAD;
+        let upgrade = req.headers().contains_key(header::UPGRADE);
+        let public = auths.iter().all(|auth| auth.public_status);
+        if public && reads && !upgrade {
+            return Ok(Started::Done);
+        }
+        let valid = |token: &str| {
+            auths
+                .iter()
+                .any(|auth| auth.tokens.iter().any(|valid| valid == token))
+        };
+        match Self::token(req) {
+            Some(token) if valid(token) => Ok(Started::Done),
+            _ => {
+                let mut response = Error::Unauthorized.error_response();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                Ok(Started::Response(response))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actix::{Actor, System},
+        server::state::{app_state, Chamber},
+        AuthConfig, Config, Coordinator,
+    };
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    fn state(public_status: bool) -> AppState {
+        let addr = Coordinator::try_new(Config::stub()).unwrap().start();
+        let auth = Some(AuthConfig {
+            tokens: vec!["secret".to_owned()],
+            public_status,
+        });
+        app_state(vec![Chamber { addr, auth }])
+    }
+
+    /// The status with which the middleware rejects the request, if it does.
+    fn rejection(req: &HttpRequest<AppState>) -> Option<StatusCode> {
+        match Authenticate.start(req).unwrap() {
+            Started::Response(response) => Some(response.status()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn post_requires_token() {
+        let _system = System::new("auth");
+        let state = state(true);
+        let request = |token: Option<&'static str>| {
+            let mut request = TestRequest::with_state(state.clone())
+                .method(Method::POST)
+                .param("chamber", "0");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, token);
+            }
+            request.finish()
+        };
+        assert_eq!(rejection(&request(None)), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(
+            rejection(&request(Some("Bearer wrong"))),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(rejection(&request(Some("Bearer secret"))), None);
+    }
+
+    #[test]
+    fn public_status() {
+        let _system = System::new("auth");
+        let get = |state: AppState| {
+            TestRequest::with_state(state)
+                .param("chamber", "0")
+                .finish()
+        };
+        assert_eq!(rejection(&get(state(true))), None);
+        assert_eq!(
+            rejection(&get(state(false))),
+            Some(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn protocols_require_token() {
+        let _system = System::new("auth");
+        let state = state(true);
+        // Protocol requests aren't for any particular chamber.
+        let request = |method: Method, token: Option<&'static str>| {
+            let mut request = TestRequest::with_state(state.clone())
+                .method(method)
+                .uri("/protocols/whatif");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, token);
+            }
+            request.finish()
+        };
+        for method in vec![Method::POST, Method::PUT, Method::DELETE] {
+            assert_eq!(
+                rejection(&request(method.clone(), None)),
+                Some(StatusCode::UNAUTHORIZED)
+            );
+            assert_eq!(rejection(&request(method, Some("Bearer secret"))), None);
+        }
+        assert_eq!(rejection(&request(Method::GET, None)), None);
+    }
+}
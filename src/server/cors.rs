@@ -0,0 +1,154 @@
+//! Cross-origin requests, so that a web frontend served from elsewhere can use the API.
+use super::state::State as AppState;
+use actix_web::{
+    http::{
+        header::{self, HeaderValue},
+        Method,
+    },
+    middleware::{Middleware, Response, Started},
+    HttpRequest, HttpResponse,
+};
+
+/// The origins allowed if none are configured, which are where a frontend is usually served
+/// while developing it.
+pub const DEFAULT_ORIGINS: &[&str] = &[
+    "http://localhost:8000",
+    "http://127.0.0.1:8000",
+    "http://localhost:8080",
+    "http://127.0.0.1:8080",
+];
+
+/// The methods used by the API.
+const ALLOWED_METHODS: &str = "GET, HEAD, POST, DELETE, PUT";
+
+/// The request headers read by the API (besides those browsers always allow).
+const ALLOWED_HEADERS: &str = "Authorization, Content-Type, Idempotency-Key";
+
+/// The response headers a frontend may need to read.
+const EXPOSED_HEADERS: &str = "ETag, Location";
+
+/// Middleware which lets browsers make requests from the configured origins.
+///
+/// Responses to requests from an allowed origin name it in `Access-Control-Allow-Origin`;
+/// requests from anywhere else are handled as usual, but without the header, so the browser
+/// withholds the response. Preflight (`OPTIONS`) requests are answered here, since browsers don't
+/// send tokens with them.
+#[derive(Clone, Copy, Debug)]
+pub struct Cors;
+
+impl Cors {
+    /// The origin of the request, if it's one of those allowed.
+    fn allowed_origin(req: &HttpRequest<AppState>) -> Option<HeaderValue> {
+        let origin = req.headers().get(header::ORIGIN)?;
+        let allowed = origin.to_str().ok().map_or(false, |origin| {
+            req.state().origins.iter().any(|allowed| allowed == origin)
+        });
+        if allowed {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware<AppState> for Cors {
+    fn start(&self, req: &HttpRequest<AppState>) -> actix_web::Result<Started> {
+        let preflight = *req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if !preflight {
+            return Ok(Started::Done);
+        }
+        let mut response = HttpResponse::NoContent();
+        response.header(header::VARY, "Origin");
+        if let Some(origin) = Self::allowed_origin(req) {
+            response
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+                .header(header::ACCESS_CONTROL_ALLOW_HEADERS, ALLOWED_HEADERS)
+                .header(header::ACCESS_CONTROL_MAX_AGE, "3600");
+        }
+        Ok(Started::Response(response.finish()))
+    }
+
+    fn response(
+        &self,
+        req: &HttpRequest<AppState>,
+        mut response: HttpResponse,
+    ) -> actix_web::Result<Response> {
+        let headers = response.headers_mut();
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+        if let Some(origin) = Self::allowed_origin(req) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSED_HEADERS),
+            );
+        }
+        Ok(Response::Done(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actix::System, server::state::app_state};
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    fn state() -> AppState {
+        let mut state = app_state(vec![]);
+        state.origins = vec!["https://lab.example.com".to_owned()];
+        state
+    }
+
+    /// The `Access-Control-Allow-Origin` header of the response to a request from the given
+    /// origin.
+    fn allowed(origin: &'static str) -> Option<HeaderValue> {
+        let req = TestRequest::with_state(state())
+            .header(header::ORIGIN, origin)
+            .finish();
+        assert!(matches!(Cors.start(&req), Ok(Started::Done)));
+        match Cors.response(&req, HttpResponse::Ok().finish()).unwrap() {
+            Response::Done(response) => response
+                .headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .cloned(),
+            _ => panic!("response was deferred"),
+        }
+    }
+
+    #[test]
+    fn configured_origins() {
+        let _system = System::new("cors");
+        assert_eq!(
+            allowed("https://lab.example.com"),
+            Some(HeaderValue::from_static("https://lab.example.com"))
+        );
+        assert_eq!(allowed("https://elsewhere.example.com"), None);
+    }
+
+    #[test]
+    fn preflight() {
+        let _system = System::new("cors");
+        let preflight = |origin: &'static str| {
+            let req = TestRequest::with_state(state())
+                .method(Method::OPTIONS)
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "DELETE")
+                .finish();
+            match Cors.start(&req).unwrap() {
+                Started::Response(response) => response,
+                _ => panic!("preflight request was passed on"),
+            }
+        };
+        let response = preflight("https://lab.example.com");
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let methods = &response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS];
+        assert!(methods.to_str().unwrap().contains("DELETE"));
+        let response = preflight("https://elsewhere.example.com");
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
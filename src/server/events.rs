@@ -0,0 +1,137 @@
+//! Server-sent events describing coordinator status changes.
+use super::{
+    job::{chamber, Error},
+    state::State as AppState,
+};
+use crate::comm::{Message, Status, StatusMessage, Subscribers, Update};
+use actix_web::{HttpRequest, HttpResponse};
+use bytes::Bytes;
+use futures::{sync::mpsc, Stream};
+
+/// A coordinator subscriber which forwards status updates to a connected event stream client.
+///
+/// Once the client disconnects, the receiving end of the channel is dropped and the subscriber
+/// reports itself closed, so the coordinator stops forwarding to it.
+#[derive(Debug)]
+pub struct EventStream {
+    sender: mpsc::UnboundedSender<Bytes>,
+}
+
+impl EventStream {
+    /// Creates a subscriber along with the stream of encoded events it will produce.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Bytes>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (Self { sender }, receiver)
+    }
+}
+
+impl Update for EventStream {
+    fn handle(&self, status: &Status, _coord: &Subscribers) {
+        // If the client has gone away, we'll be dropped before the next update.
+        let _ = self.sender.unbounded_send(event(&status.message));
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+/// Encodes a status update as a named server-sent event.
+fn event(message: &StatusMessage) -> Bytes {
+    let (name, data) = encode(message);
+    Bytes::from(format!("event: {}\ndata: {}\n\n", name, data))
+}
+
+/// Names a status update and encodes its details as JSON.
+pub(super) fn encode(message: &StatusMessage) -> (&'static str, serde_json::Value) {
+    match message {
+        StatusMessage::Continued => ("continued", serde_json::Value::Null),
+        StatusMessage::Started(protocol) => (
+            "started",
+            serde_json::to_value(protocol).unwrap_or(serde_json::Value::Null),
+        ),
+        StatusMessage::Paused => ("paused", serde_json::Value::Null),
+        StatusMessage::StopQueued { early } => {
+            ("stop_queued", serde_json::json!({ "early": early }))
+        }
+        StatusMessage::Halted { reason } => ("halted", serde_json::json!({ "reason": reason })),
+        StatusMessage::QueueCleared => ("queue_cleared", serde_json::Value::Null),
+        StatusMessage::Scheduled { id, at } => (
+            "scheduled",
+            serde_json::json!({
+                "id": id,
+                "at": humantime::format_rfc3339_seconds(*at).to_string(),
+            }),
+        ),
+        StatusMessage::Skipped => ("skipped", serde_json::Value::Null),
+        StatusMessage::Errored(description) => ("errored", serde_json::json!(description)),
+        StatusMessage::Notified(notification) => (
+            "notified",
+            serde_json::to_value(notification).unwrap_or(serde_json::Value::Null),
+        ),
+        StatusMessage::Advanced {
+            completed,
+            total,
+            action,
+        } => (
+            "advanced",
+            serde_json::json!({ "completed": completed, "total": total, "action": action }),
+        ),
+    }
+}
+
+/// Streams the chamber's status updates to the client as server-sent events.
+#[allow(clippy::needless_pass_by_value)]
+pub fn events(req: HttpRequest<AppState>) -> Result<HttpResponse, Error> {
+    let chamber = chamber(&req)?;
+    let (subscriber, receiver) = EventStream::new();
+    chamber
+        .addr
+        .do_send(Message::Subscribe(Box::new(subscriber)));
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .streaming(receiver.map_err(|()| actix_web::error::ErrorInternalServerError("closed"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actix::*, Action, Config, Coordinator, Protocol, Step};
+    use futures::Future;
+    use std::{collections::HashMap, time::Duration};
+
+    #[test]
+    fn started_then_paused() {
+        let (subscriber, receiver) = EventStream::new();
+        System::run(move || {
+            let addr = Coordinator::create(|context| {
+                context.run_later(Duration::from_millis(50), |coord, context| {
+                    coord.state.remaining = vec![Action::Hail, Action::Finish];
+                    coord.advance(context).unwrap();
+                });
+                context.run_later(Duration::from_millis(150), |_, _| System::current().stop());
+                Coordinator::try_new(Config::stub()).unwrap()
+            });
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Subscribe(Box::new(subscriber)));
+            addr.do_send(Message::Start(protocol, None, HashMap::new()));
+        });
+        let events = receiver
+            .filter(|event| !event.starts_with(b"event: advanced"))
+            .take(2)
+            .collect()
+            .wait()
+            .unwrap();
+        let names = events
+            .iter()
+            .map(|event| {
+                String::from_utf8_lossy(event)
+                    .lines()
+                    .next()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["event: started", "event: paused"]);
+    }
+}
@@ -0,0 +1,52 @@
+//! Health checks, for whatever is supervising the server.
+use super::{job::Error, state::State as AppState};
+use crate::{comm::GetHealth, Health};
+use actix_web::{http::StatusCode, AsyncResponder, HttpRequest, HttpResponse};
+use futures::{future::join_all, prelude::*};
+
+/// Reports the health of each chamber's devices, in order.
+///
+/// The response is `503 Service Unavailable` if any chamber's devices couldn't be initialized, in
+/// which case the pins which couldn't be opened are listed along with it.
+#[allow(clippy::needless_pass_by_value)]
+pub fn health(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let requests = req
+        .state()
+        .chambers
+        .iter()
+        .map(|chamber| chamber.addr.send(GetHealth))
+        .collect::<Vec<_>>();
+    join_all(requests)
+        .from_err()
+        .map(|chambers: Vec<Health>| {
+            let status = if chambers.iter().all(Health::is_healthy) {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            HttpResponse::build(status).json(chambers)
+        })
+        .responder()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actix::{Actor, System},
+        server::state::{app_state, Chamber},
+        Config, Coordinator,
+    };
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn healthy() {
+        let mut system = System::new("health");
+        let addr = Coordinator::try_new(Config::stub()).unwrap().start();
+        let state = app_state(vec![Chamber { addr, auth: None }]);
+        let response = system
+            .block_on(health(TestRequest::with_state(state).finish()))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,16 +1,30 @@
-use super::state::State as AppState;
+use super::{
+    keys::{Claim, Release},
+    state::{Chamber, State as AppState},
+    store::Get,
+};
 use crate::{
-    comm::{Message, State},
-    Action, MotorId, Program, Protocol,
+    buffer_label,
+    comm::{
+        GetHistory, GetMetrics, GetSnapshot, HaltReason, JobRecord, Message, Metrics, Snapshot,
+        State, TimedAction,
+    },
+    sensor::Reading,
+    Action, ChamberId, MotorId, Program, Protocol,
 };
 use actix_web::{
-    http::header, AsyncResponder, FromRequest, HttpMessage, HttpRequest, HttpResponse, Json, Path,
-    Responder, ResponseError,
+    http::{header, StatusCode},
+    AsyncResponder, FromRequest, HttpMessage, HttpRequest, HttpResponse, Json, ResponseError,
 };
-use futures::prelude::*;
+use futures::{future::Either, prelude::*};
 use uuid::Uuid;
 
-use std::{fmt, ops::Deref};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    ops::Deref,
+    time::{Duration, SystemTime},
+};
 
 /// Represents a (buffer-exchange) job to be run.
 #[derive(Deserialize, Serialize)]
@@ -21,6 +35,14 @@ pub struct Job {
     program: Option<Program>,
     remaining: Vec<Action>,
     buffer: Option<MotorId>,
+    labels: BTreeMap<MotorId, String>,
+    ambient: Vec<Reading>,
+    estimated_completion: Option<SystemTime>,
+    completion_uncertain: bool,
+    eta_seconds: Option<u64>,
+    timeline: Vec<TimedAction>,
+    metadata: HashMap<String, String>,
+    halt_reason: Option<HaltReason>,
 }
 
 /// Job request error type.
@@ -28,12 +50,47 @@ pub struct Job {
 pub enum Error {
     Coordinator(crate::comm::Error),
     Json(actix_web::error::JsonPayloadError),
+    Command(serde_json::Error),
     Mailbox(actix_web::actix::MailboxError),
     InvalidUuid,
     IncorrectUuid,
+    NoSuchChamber,
+    NoSuchJob,
+    NoSuchProtocol,
+    Unauthorized,
     ActixWeb(actix_web::Error),
 }
 
+impl Job {
+    /// Describes the job in the given snapshot, if there is one.
+    fn from_snapshot(snapshot: Snapshot) -> Option<Self> {
+        let id = snapshot.uuid?;
+        let mut labels = snapshot
+            .program
+            .as_ref()
+            .map(|program| program.resolve_labels(&snapshot.labels))
+            .unwrap_or_default();
+        if let Some(buffer) = snapshot.buffer {
+            labels.insert(buffer, buffer_label(&snapshot.labels, buffer));
+        }
+        Some(Self {
+            id,
+            state: snapshot.state,
+            program: snapshot.program,
+            remaining: snapshot.remaining,
+            buffer: snapshot.buffer,
+            labels,
+            ambient: snapshot.ambient,
+            estimated_completion: snapshot.estimated_completion,
+            completion_uncertain: snapshot.completion_uncertain,
+            eta_seconds: snapshot.remaining_duration.map(|eta| eta.as_secs()),
+            timeline: snapshot.timeline,
+            metadata: snapshot.metadata,
+            halt_reason: snapshot.halt_reason,
+        })
+    }
+}
+
 impl From<crate::comm::Error> for Error {
     fn from(err: crate::comm::Error) -> Self {
         Self::Coordinator(err)
@@ -61,11 +118,16 @@ impl From<actix_web::Error> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Coordinator(_e) => unimplemented!(),
+            Self::Coordinator(e) => e.fmt(f),
             Self::Json(e) => e.fmt(f),
+            Self::Command(e) => write!(f, "Invalid command: {}", e),
             Self::Mailbox(e) => e.fmt(f),
             Self::InvalidUuid => write!(f, "Invalid UUID"),
             Self::IncorrectUuid => write!(f, "Specified job is no longer active."),
+            Self::NoSuchChamber => write!(f, "No such chamber"),
+            Self::NoSuchJob => write!(f, "No such job"),
+            Self::NoSuchProtocol => write!(f, "No such protocol"),
+            Self::Unauthorized => write!(f, "A valid bearer token is required"),
             Self::ActixWeb(e) => e.fmt(f),
         }
     }
@@ -73,72 +135,341 @@ impl fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+/// The body of an error response.
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl Error {
+    /// The HTTP status corresponding to the error.
+    fn status(&self) -> StatusCode {
+        use crate::comm::Error as CoordError;
+        match self {
+            Self::Coordinator(err) => match err {
+                CoordError::ProtocolConversion(_)
+                | CoordError::UnknownBuffers(_)
+                | CoordError::UnknownPumps(_) => StatusCode::BAD_REQUEST,
+                CoordError::Busy | CoordError::Interlock(_, _) => StatusCode::CONFLICT,
+                CoordError::NotReady | CoordError::EmergencyStop => StatusCode::SERVICE_UNAVAILABLE,
+                CoordError::NothingQueued => StatusCode::NOT_FOUND,
+                CoordError::Pin(_) | CoordError::Config(_) | CoordError::Unreachable => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            },
+            Self::Json(_) | Self::Command(_) | Self::InvalidUuid => StatusCode::BAD_REQUEST,
+            Self::IncorrectUuid | Self::NoSuchChamber | Self::NoSuchJob | Self::NoSuchProtocol => {
+                StatusCode::NOT_FOUND
+            }
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Mailbox(_) | Self::ActixWeb(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
 impl ResponseError for Error {
     fn error_response(&self) -> HttpResponse {
-        unimplemented!()
+        HttpResponse::build(self.status()).json(ErrorBody {
+            error: self.to_string(),
+        })
     }
 }
 
-/// The current status of the device.
-// TODO: HEAD support
-#[allow(clippy::needless_pass_by_value)]
-pub fn status(req: HttpRequest<AppState>) -> Json<Option<Job>> {
-    let coord = &req.state().coord;
-    if let Some(uuid) = coord.state.uuid {
-        let state = coord.status();
-        let program = coord.state.program.clone();
-        let remaining = coord.state.remaining.clone();
-        let buffer = coord.state.buffer;
-        let job = Job {
-            id: uuid,
-            state,
-            program,
-            remaining,
-            buffer,
-        };
-        Json(Some(job))
-    } else {
-        Json(None)
+/// The ID of the chamber specified in the request path.
+fn chamber_id(req: &HttpRequest<AppState>) -> Result<ChamberId, Error> {
+    req.match_info()
+        .get("chamber")
+        .and_then(|id| id.parse::<ChamberId>().ok())
+        .ok_or(Error::NoSuchChamber)
+}
+
+/// Looks up the chamber specified in the request path.
+pub(super) fn chamber(req: &HttpRequest<AppState>) -> Result<&Chamber, Error> {
+    chamber_id(req).and_then(|id| req.state().chamber(id).ok_or(Error::NoSuchChamber))
+}
+
+/// Takes a snapshot of the chamber's current (or most recent) job.
+fn snapshot(req: &HttpRequest<AppState>) -> impl Future<Item = Snapshot, Error = Error> {
+    chamber(req)
+        .map(|chamber| chamber.addr.send(GetSnapshot))
+        .into_future()
+        .and_then(|request| request.from_err())
+}
+
+/// The name of the given execution state, as reported in the `Deoxy-State` header.
+fn state_name(state: State) -> &'static str {
+    match state {
+        State::Waiting => "waiting",
+        State::Running => "running",
+        State::Stopped { .. } => "stopped",
     }
 }
 
-/// Creates and starts a new job if the system is ready.
+/// The current status of the chamber.
 #[allow(clippy::needless_pass_by_value)]
-pub fn start(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+pub fn status(
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = Json<Option<Job>>, Error = Error>> {
+    snapshot(&req)
+        .map(|snapshot| Json(Job::from_snapshot(snapshot)))
+        .responder()
+}
+
+/// The current state of the chamber, in headers only, for cheap polling.
+///
+/// The `Deoxy-State` header holds the execution state (`waiting`, `running`, or `stopped`), and
+/// the `ETag` header holds the job ID, if a job has been run.
+#[allow(clippy::needless_pass_by_value)]
+pub fn head(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    snapshot(&req)
+        .map(|snapshot| {
+            let mut response = HttpResponse::Ok();
+            response.header("Deoxy-State", state_name(snapshot.state));
+            if let Some(uuid) = snapshot.uuid {
+                response.header(header::ETAG, format!("\"{}\"", uuid));
+            }
+            response.finish()
+        })
+        .responder()
+}
+
+/// The records of the chamber's recently-ended jobs, newest first.
+#[allow(clippy::needless_pass_by_value)]
+pub fn history(
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = Json<Vec<JobRecord>>, Error = Error>> {
+    chamber(&req)
+        .map(|chamber| chamber.addr.send(GetHistory))
+        .into_future()
+        .and_then(|request| request.from_err())
+        .map(Json)
+        .responder()
+}
+
+/// The record of a single ended job.
+#[allow(clippy::needless_pass_by_value)]
+pub fn record(
+    uuid: UUID,
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = Json<JobRecord>, Error = Error>> {
+    chamber(&req)
+        .map(|chamber| chamber.addr.send(GetHistory))
+        .into_future()
+        .and_then(|request| request.from_err())
+        .and_then(move |history| {
+            history
+                .into_iter()
+                .find(|record| record.id == *uuid)
+                .map(Json)
+                .ok_or(Error::NoSuchJob)
+        })
+        .responder()
+}
+
+/// The metrics of a running or recently-ended job.
+#[allow(clippy::needless_pass_by_value)]
+pub fn metrics(
+    uuid: UUID,
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = Json<Metrics>, Error = Error>> {
+    chamber(&req)
+        .map(|chamber| chamber.addr.send(GetMetrics(*uuid)))
+        .into_future()
+        .and_then(|request| request.from_err())
+        .and_then(|metrics| metrics.map(Json).ok_or(Error::NoSuchJob))
+        .responder()
+}
+
+/// The protocol a job should run, as given in the body of a request to start one.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JobRequest {
+    /// A saved protocol, by ID, along with any metadata describing the job.
+    Stored {
+        protocol: Uuid,
+        #[serde(default)]
+        metadata: HashMap<String, String>,
+    },
+    /// A protocol given in full, along with any metadata describing the job.
+    Described {
+        protocol: Protocol,
+        #[serde(default)]
+        metadata: HashMap<String, String>,
+    },
+    /// A protocol given in full.
+    Inline(Protocol),
+}
+
+/// Reads the protocol a job should run (and the metadata describing it) from the request body,
+/// looking the protocol up in the protocol store if it's given by ID.
+fn requested_job(
+    req: &HttpRequest<AppState>,
+) -> impl Future<Item = (Protocol, HashMap<String, String>), Error = Error> {
+    let store = req.state().store.clone();
     req.json()
-        .from_err()
-        .and_then(move |proto: Protocol| {
-            let coord = &req.state().coord;
-            if !coord.is_stopped() {
-                Err(Error::from(crate::comm::Error::Busy))
-            } else {
-                let addr = &req.state().addr;
-                let id = Uuid::new_v4();
-                let result = addr
-                    .send(Message::Start(proto, Some(id)))
-                    .map(move |_| {
-                        HttpResponse::Created()
-                            .header(self::header::LOCATION, format!("{}", id))
-                            .finish()
-                    })
-                    .from_err();
-                Ok(result)
+        .from_err::<Error>()
+        .and_then(move |request: JobRequest| match request {
+            JobRequest::Inline(protocol) => Either::A(Ok((protocol, HashMap::new())).into_future()),
+            JobRequest::Described { protocol, metadata } => {
+                Either::A(Ok((protocol, metadata)).into_future())
             }
+            JobRequest::Stored { protocol, metadata } => Either::B(
+                store
+                    .send(Get(protocol))
+                    .from_err()
+                    .and_then(|protocol| protocol.ok_or(Error::NoSuchProtocol))
+                    .map(move |protocol| (protocol, metadata)),
+            ),
+        })
+}
+
+/// The response to a request creating the given job.
+fn created(id: Uuid) -> HttpResponse {
+    HttpResponse::Created()
+        .header(self::header::LOCATION, format!("{}", id))
+        .finish()
+}
+
+/// Creates and starts a new job if the system is ready.
+///
+/// The body is either a protocol or `{"protocol": ...}`, giving a protocol or naming a saved
+/// protocol by ID. The latter form may also give `"metadata"` describing the job (as an object of
+/// strings, such as `{"sample": "Heart 3"}`), which is reported along with it.
+///
+/// If an `Idempotency-Key` header is given and a job was created with the same key recently, the
+/// original response is repeated rather than starting another job. A key whose job couldn't be
+/// started may be used again.
+#[allow(clippy::needless_pass_by_value)]
+pub fn start(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let key = req
+        .headers()
+        .get("Idempotency-Key")
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_owned);
+    requested_job(&req)
+        .and_then(move |(proto, metadata)| {
+            let addr = chamber(&req)?.addr.clone();
+            let id = Uuid::new_v4();
+            let claim = match key {
+                Some(key) => Some(Claim {
+                    chamber: chamber_id(&req)?,
+                    key,
+                    id,
+                }),
+                None => None,
+            };
+            let keys = req.state().keys.clone();
+            let claimed = match claim.clone() {
+                Some(claim) => Either::A(keys.send(claim).from_err()),
+                None => Either::B(Ok(None).into_future()),
+            };
+            let result = claimed.and_then(move |existing| match existing {
+                // This is a retry, so the job has already been created.
+                Some(existing) => Either::A(Ok(created(existing)).into_future()),
+                None => Either::B(
+                    addr.send(Message::Start(proto, Some(id), metadata))
+                        .from_err()
+                        .and_then(|result| result.map_err(Error::from))
+                        .then(move |result| {
+                            if let (Err(_), Some(claim)) = (&result, claim) {
+                                keys.do_send(Release(claim));
+                            }
+                            result.map(move |_| created(id))
+                        }),
+                ),
+            });
+            Ok(result)
         })
         .flatten()
         .responder()
 }
 
+/// Queues a new job to be started once the current one finishes.
+///
+/// Any previously-queued job is replaced. As when starting a job, the protocol may be given by
+/// ID, and metadata may be given along with it.
+#[allow(clippy::needless_pass_by_value)]
+pub fn queue(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    requested_job(&req)
+        .and_then(move |(proto, metadata)| {
+            let chamber = chamber(&req)?;
+            let id = Uuid::new_v4();
+            let result = chamber
+                .addr
+                .send(Message::Queue(proto, Some(id), metadata))
+                .from_err()
+                .and_then(|result| result.map_err(Error::from))
+                .map(move |_| created(id));
+            Ok(result)
+        })
+        .flatten()
+        .responder()
+}
+
+/// Cancels the queued job, leaving the running job untouched.
+///
+/// If no job is queued, this responds with `404 Not Found`.
+#[allow(clippy::needless_pass_by_value)]
+pub fn cancel_queued(
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    chamber(&req)
+        .map(|chamber| chamber.addr.send(Message::CancelQueued))
+        .into_future()
+        .and_then(|request| request.map_err(Error::from))
+        .and_then(|result| result.map_err(Error::from))
+        .map(|_| HttpResponse::NoContent().finish())
+        .responder()
+}
+
+/// A request to move a valve for servicing, as given in the body of a request to jog one.
+#[derive(Deserialize)]
+pub struct Jog {
+    /// The buffer valve to move.
+    motor: MotorId,
+    /// Whether to open the valve (rather than close it).
+    open: bool,
+    /// How long to leave the valve before closing it.
+    #[serde(with = "deoxy_core::duration")]
+    duration: Duration,
+}
+
+/// Moves a valve for servicing, as with the coordinator's `Jog` message.
+///
+/// If a job is active, this responds with `409 Conflict`.
+#[allow(clippy::needless_pass_by_value)]
+pub fn jog(
+    jog: Json<Jog>,
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let Jog {
+        motor,
+        open,
+        duration,
+    } = jog.into_inner();
+    let message = Message::Jog {
+        motor,
+        open,
+        duration,
+    };
+    chamber(&req)
+        .map(|chamber| chamber.addr.send(message))
+        .into_future()
+        .and_then(|request| request.map_err(Error::from))
+        .and_then(|result| result.map_err(Error::from))
+        .map(|_| HttpResponse::NoContent().finish())
+        .responder()
+}
+
 /// Wrapper type around `Uuid`.
 ///
 /// This struct implements some convenience methods and helps us avoid the orphan rules.
 pub struct UUID(Uuid);
 
 impl UUID {
-    /// Whether this is the UUID of the currently-running job.
-    pub fn is_current(&self, state: &AppState) -> bool {
-        let current = state.coord.state.uuid;
-        current == Some(**self)
+    /// Whether this is the UUID of the job in the given snapshot.
+    pub fn is_current(&self, snapshot: &Snapshot) -> bool {
+        snapshot.uuid == Some(**self)
     }
 }
 
@@ -159,8 +490,9 @@ impl<S> FromRequest<S> for UUID {
     type Config = ();
     type Result = Result<Self, Error>;
     fn from_request(req: &HttpRequest<S>, _: &Self::Config) -> Self::Result {
-        let path = Path::<String>::extract(req).map_err(Error::from);
-        path.and_then(|path| Uuid::parse_str(&path.into_inner()).map_err(|_| Error::InvalidUuid))
+        let job = req.match_info().get("job").ok_or(Error::InvalidUuid)?;
+        Uuid::parse_str(job)
+            .map_err(|_| Error::InvalidUuid)
             .map(Self)
     }
 }
@@ -171,16 +503,23 @@ fn message_uuid(
     uuid: UUID,
     req: HttpRequest<AppState>,
 ) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
-    let state = &req.state();
-    (if uuid.is_current(&state) {
-        let addr = &state.addr;
-        Ok(addr.send(message))
-    } else {
-        Err(Error::IncorrectUuid)
-    })
-    .into_future()
-    .map(|_| HttpResponse::NoContent().finish())
-    .responder()
+    let addr = match chamber(&req) {
+        Ok(chamber) => chamber.addr.clone(),
+        Err(err) => return Box::new(futures::future::err(err)),
+    };
+    addr.send(GetSnapshot)
+        .from_err()
+        .and_then(move |snapshot| {
+            if uuid.is_current(&snapshot) {
+                Ok(addr.send(message).from_err())
+            } else {
+                Err(Error::IncorrectUuid)
+            }
+        })
+        .flatten()
+        .and_then(|result| result.map_err(Error::from))
+        .map(|_| HttpResponse::NoContent().finish())
+        .responder()
 }
 
 /// Resumes a job that is waiting for user confirmation.
@@ -194,6 +533,15 @@ pub fn resume(
     message_uuid(Message::Continue, uuid, req)
 }
 
+/// Pauses the running job partway through its current step, until it's resumed.
+#[allow(clippy::needless_pass_by_value)]
+pub fn pause(
+    uuid: UUID,
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    message_uuid(Message::Pause, uuid, req)
+}
+
 /// Immediately stops the running job.
 #[allow(clippy::needless_pass_by_value)]
 pub fn halt(
@@ -214,3 +562,185 @@ pub fn stop(
     let message = Message::Stop;
     message_uuid(message, uuid, req)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comm::{Status, StatusMessage, Subscribers, Update};
+    use crate::{actix::*, server::state::app_state, Config, Coordinator, Step};
+    use actix_web::test::TestRequest;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    fn stub_chamber(uuid: Option<Uuid>) -> Chamber {
+        let addr = Coordinator::create(move |_| {
+            let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+            coord.state.uuid = uuid;
+            coord
+        });
+        Chamber { addr, auth: None }
+    }
+
+    #[test]
+    fn chambers_are_independent() {
+        let mut system = System::new("chambers");
+        let id = Uuid::new_v4();
+        let state = app_state(vec![stub_chamber(Some(id)), stub_chamber(None)]);
+        let request = |chamber| {
+            TestRequest::with_state(state.clone())
+                .param("chamber", chamber)
+                .finish()
+        };
+        let job = system.block_on(status(request("0"))).unwrap();
+        assert_eq!(job.into_inner().unwrap().id, id);
+        let job = system.block_on(status(request("1"))).unwrap();
+        assert!(job.into_inner().is_none());
+        let missing = system.block_on(status(request("2")));
+        assert!(matches!(missing, Err(Error::NoSuchChamber)));
+    }
+
+    fn body(response: &HttpResponse) -> String {
+        match response.body() {
+            actix_web::Body::Binary(binary) => String::from_utf8_lossy(binary.as_ref()).into(),
+            _ => panic!("Unexpected response body"),
+        }
+    }
+
+    #[test]
+    fn head_has_no_body() {
+        let mut system = System::new("head");
+        let id = Uuid::new_v4();
+        let state = app_state(vec![stub_chamber(Some(id))]);
+        let request = || {
+            TestRequest::with_state(state.clone())
+                .param("chamber", "0")
+                .finish()
+        };
+        let response = system.block_on(head(request())).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(matches!(response.body(), actix_web::Body::Empty));
+        assert_eq!(response.headers()["Deoxy-State"], "stopped");
+        assert_eq!(
+            response.headers()[header::ETAG],
+            format!("\"{}\"", id).as_str()
+        );
+        let job = system.block_on(status(request())).unwrap();
+        assert_eq!(job.into_inner().unwrap().id, id);
+    }
+
+    #[test]
+    fn invalid_protocol() {
+        let mut system = System::new("invalid");
+        let state = app_state(vec![stub_chamber(None)]);
+        let request = |payload: &'static str| {
+            TestRequest::with_state(state.clone())
+                .param("chamber", "0")
+                .header(header::CONTENT_TYPE, "application/json")
+                .set_payload(payload)
+                .finish()
+        };
+        let empty = system.block_on(start(request("[]"))).unwrap_err();
+        let response = empty.error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(body(&response).starts_with(r#"{"error":"#));
+        assert!(body(&response).contains("The protocol has no steps"));
+        let malformed = system.block_on(start(request("[{"))).unwrap_err();
+        assert!(matches!(malformed, Error::Json(_)));
+        assert_eq!(malformed.error_response().status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// A subscriber which counts the jobs started.
+    #[derive(Debug, Default)]
+    struct CountStarts(Arc<AtomicUsize>);
+
+    impl Update for CountStarts {
+        fn handle(&self, status: &Status, _coord: &Subscribers) {
+            if let StatusMessage::Started(_) = status.message {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn idempotent_start() {
+        let mut system = System::new("idempotent");
+        let chamber = stub_chamber(None);
+        let starts = CountStarts::default();
+        let count = starts.0.clone();
+        chamber.addr.do_send(Message::Subscribe(Box::new(starts)));
+        let state = app_state(vec![chamber.clone()]);
+        let request = |key: &'static str| {
+            TestRequest::with_state(state.clone())
+                .param("chamber", "0")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Idempotency-Key", key)
+                .set_payload(r#"[{"perfuse": [0, null]}]"#)
+                .finish()
+        };
+        let location = |response: &HttpResponse| response.headers()[header::LOCATION].clone();
+        let first = system.block_on(start(request("tablet-1"))).unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+        let retried = system.block_on(start(request("tablet-1"))).unwrap();
+        assert_eq!(retried.status(), StatusCode::CREATED);
+        assert_eq!(location(&retried), location(&first));
+        // Let the subscribers catch up.
+        system.block_on(chamber.addr.send(GetSnapshot)).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        // Another key is another job, which can't start while the first is running.
+        let other = system.block_on(start(request("tablet-2"))).unwrap_err();
+        assert_eq!(other.error_response().status(), StatusCode::CONFLICT);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn finished_job_history() {
+        let mut system = System::new("history");
+        let id = Uuid::new_v4();
+        let addr = Coordinator::create(move |context| {
+            let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+            coord.state.uuid = Some(id);
+            coord.state.remaining = Protocol::with_step(Step::Perfuse(0, None))
+                .as_program()
+                .unwrap()
+                .into();
+            // Skip the perfusion and go straight to the end.
+            coord.state.remaining.remove(0);
+            coord.advance(context).unwrap();
+            coord
+        });
+        let state = app_state(vec![Chamber { addr, auth: None }]);
+        let req = TestRequest::with_state(state.clone())
+            .param("chamber", "0")
+            .finish();
+        let history = system.block_on(history(req)).unwrap().into_inner();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, id);
+        assert_eq!(history[0].state, State::Stopped { early: false });
+        assert_eq!(history[0].completed, vec![Action::Finish]);
+        let req = TestRequest::with_state(state.clone())
+            .param("chamber", "0")
+            .finish();
+        let found = system.block_on(record(UUID(id), req)).unwrap().into_inner();
+        assert_eq!(found, history[0]);
+        let req = TestRequest::with_state(state.clone())
+            .param("chamber", "0")
+            .finish();
+        let missing = system.block_on(record(UUID(Uuid::new_v4()), req));
+        assert!(matches!(missing, Err(Error::NoSuchJob)));
+        let req = TestRequest::with_state(state.clone())
+            .param("chamber", "0")
+            .finish();
+        let found = system
+            .block_on(metrics(UUID(id), req))
+            .unwrap()
+            .into_inner();
+        assert_eq!(found, history[0].metrics);
+        let req = TestRequest::with_state(state)
+            .param("chamber", "0")
+            .finish();
+        let missing = system.block_on(metrics(UUID(Uuid::new_v4()), req));
+        assert!(matches!(missing, Err(Error::NoSuchJob)));
+    }
+}
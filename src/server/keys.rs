@@ -0,0 +1,81 @@
+//! Idempotency keys, so that a retried request doesn't start a job twice.
+use crate::{
+    actix::{ActixMessage, Actor, Context, Handle},
+    ChamberId,
+};
+use actix_web::actix::MessageResult;
+use uuid::Uuid;
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    time::{Duration, Instant},
+};
+
+/// How long a key is remembered after it's first used.
+pub const KEY_LIFETIME: Duration = Duration::from_secs(600);
+
+/// Remembers the job each recently-used idempotency key (of each chamber) created.
+///
+/// Keys are only kept in memory, so they're forgotten when the server restarts.
+#[derive(Debug, Default)]
+pub struct Keys {
+    jobs: HashMap<(ChamberId, String), (Uuid, Instant)>,
+}
+
+impl Actor for Keys {
+    type Context = Context<Self>;
+}
+
+/// Claims the given key of the given chamber for the given job, unless it was used recently.
+///
+/// The result is the job the key was already used for, if any.
+#[derive(Clone, Debug)]
+pub struct Claim {
+    /// The chamber the job is to run in.
+    pub chamber: ChamberId,
+    /// The key given with the request.
+    pub key: String,
+    /// The job to be created.
+    pub id: Uuid,
+}
+
+impl ActixMessage for Claim {
+    type Result = Option<Uuid>;
+}
+
+/// Forgets the given claim, as when its job couldn't be started after all.
+///
+/// The key is left alone if it has since been claimed for another job.
+#[derive(Clone, Debug)]
+pub struct Release(pub Claim);
+
+impl ActixMessage for Release {
+    type Result = ();
+}
+
+impl Handle<Claim> for Keys {
+    type Result = MessageResult<Claim>;
+    fn handle(&mut self, message: Claim, _context: &mut Self::Context) -> Self::Result {
+        let now = Instant::now();
+        self.jobs
+            .retain(|_, &mut (_, used)| now.duration_since(used) < KEY_LIFETIME);
+        MessageResult(match self.jobs.entry((message.chamber, message.key)) {
+            Entry::Occupied(entry) => Some(entry.get().0),
+            Entry::Vacant(entry) => {
+                entry.insert((message.id, now));
+                None
+            }
+        })
+    }
+}
+
+impl Handle<Release> for Keys {
+    type Result = ();
+    fn handle(&mut self, message: Release, _context: &mut Self::Context) -> Self::Result {
+        let Release(claim) = message;
+        let key = (claim.chamber, claim.key);
+        if self.jobs.get(&key).map(|&(id, _)| id) == Some(claim.id) {
+            self.jobs.remove(&key);
+        }
+    }
+}
@@ -1,32 +1,182 @@
 //! Web server utilities.
+mod auth;
+mod cors;
+mod events;
+mod health;
 mod job;
+mod keys;
+mod protocol;
+mod socket;
 mod state;
-use actix_web::{http::Method, App};
+mod store;
+use crate::{actix::Actor, Config, CoordError, Coordinator};
+use actix_web::{http::Method, server::HttpServer, App};
+
+use std::{fmt, io, net::SocketAddr};
+
+pub use self::state::State;
 
 /// Returns an actix-web app for handling jobs.
-fn job_app(state: state::State) -> App<state::State> {
+///
+/// Each chamber's jobs are served under `/chambers/{chamber}/`, and require a token if the
+/// chamber is configured to.
+fn job_app(state: State) -> App<State> {
     App::with_state(state)
-        .route("/", Method::GET, job::status)
-        .route("/", Method::HEAD, job::status)
-        .route("/", Method::POST, job::start)
-        .resource("/{job}", |r| r.method(Method::DELETE).with(job::stop))
-        .resource("/{job}/halt", |r| r.method(Method::POST).with(job::stop))
-        .resource("/{job}/resume", |r| {
-            r.method(Method::POST).with(job::resume)
+        .middleware(cors::Cors)
+        .prefix("/chambers")
+        .scope("/{chamber}", |scope| {
+            scope
+                .middleware(auth::Authenticate)
+                .route("/", Method::GET, job::status)
+                .route("/", Method::HEAD, job::head)
+                .route("/", Method::POST, job::start)
+                .resource("/queue", |r| {
+                    r.method(Method::POST).with(job::queue);
+                    r.method(Method::DELETE).with(job::cancel_queued);
+                })
+                .resource("/jog", |r| r.method(Method::POST).with(job::jog))
+                .resource("/jobs", |r| r.method(Method::GET).with(job::history))
+                .resource("/jobs/{job}", |r| r.method(Method::GET).with(job::record))
+                .resource("/jobs/{job}/metrics", |r| {
+                    r.method(Method::GET).with(job::metrics)
+                })
+                .resource("/events", |r| r.method(Method::GET).with(events::events))
+                .resource("/ws", |r| r.method(Method::GET).with(socket::socket))
+                .resource("/{job}", |r| r.method(Method::DELETE).with(job::stop))
+                .resource("/{job}/halt", |r| r.method(Method::POST).with(job::stop))
+                .resource("/{job}/pause", |r| r.method(Method::POST).with(job::pause))
+                .resource("/{job}/resume", |r| {
+                    r.method(Method::POST).with(job::resume)
+                })
         })
 }
 
 /// Returns an actix-web app for handling protocols.
-fn protocol_app(state: state::State) -> App<state::State> {
+///
+/// Protocols are shared by every chamber, so any chamber's token will do.
+fn protocol_app(state: State) -> App<State> {
     App::with_state(state)
+        .middleware(cors::Cors)
+        .middleware(auth::Authenticate)
+        .prefix("/protocols")
+        .resource("", |r| {
+            r.method(Method::GET).with(protocol::list);
+            r.method(Method::POST).with(protocol::create);
+        })
+        .resource("/whatif", |r| r.method(Method::POST).with(protocol::whatif))
+        .resource("/{protocol}", |r| {
+            r.method(Method::GET).with(protocol::fetch);
+            r.method(Method::PUT).with(protocol::replace);
+            r.method(Method::DELETE).with(protocol::remove);
+        })
 }
 
-fn state() -> state::State {
-    unimplemented!()
+/// Returns an actix-web app for health checks.
+///
+/// This isn't authenticated, so that whatever supervises the server can use it.
+fn health_app(state: State) -> App<State> {
+    App::with_state(state)
+        .prefix("/healthz")
+        .resource("", |r| r.method(Method::GET).with(health::health))
+}
+
+/// Starts a coordinator for each of the given chamber configurations and collects their
+/// addresses into the app state, along with an empty protocol store (and record of idempotency
+/// keys).
+///
+/// Chambers are numbered in the order they're given, and the first chamber's configuration
+/// decides which origins may make requests from a browser. This must be called from within a
+/// running actix system, since the coordinators (and store) are started on the current arbiter.
+pub fn state<I>(configs: I) -> Result<State, CoordError>
+where
+    I: IntoIterator<Item = Config>,
+{
+    let configs = configs.into_iter().collect::<Vec<_>>();
+    let origins = configs
+        .first()
+        .and_then(|config| config.server.as_ref())
+        .and_then(|server| server.allowed_origins.clone())
+        .unwrap_or_else(|| {
+            cors::DEFAULT_ORIGINS
+                .iter()
+                .map(|&origin| origin.to_owned())
+                .collect()
+        });
+    let chambers = configs
+        .into_iter()
+        .map(|config| {
+            let auth = config.auth.clone();
+            let addr = Coordinator::try_new(config)?.start();
+            Ok(state::Chamber { addr, auth })
+        })
+        .collect::<Result<Vec<_>, CoordError>>()?;
+    Ok(State {
+        chambers,
+        store: store::Store::default().start(),
+        keys: keys::Keys::default().start(),
+        origins,
+    })
 }
 
 /// Returns the list of actix-web apps to be used with the server.
-pub fn apps() -> Vec<App<state::State>> {
-    let state = state();
-    vec![job_app(state.clone()), protocol_app(state.clone())]
+///
+/// Jobs and protocols may also be used by web frontends served from the allowed origins (see
+/// [`ServerConfig`](../struct.ServerConfig.html)).
+pub fn apps(state: State) -> Vec<App<State>> {
+    vec![
+        job_app(state.clone()),
+        protocol_app(state.clone()),
+        health_app(state),
+    ]
+}
+
+/// Server start-up error type.
+#[derive(Debug)]
+pub enum Error {
+    /// A chamber's coordinator couldn't be initialized.
+    Coordinator(CoordError),
+    /// The server couldn't listen on the given address.
+    Bind(String, io::Error),
+}
+
+impl From<CoordError> for Error {
+    fn from(err: CoordError) -> Self {
+        Self::Coordinator(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Coordinator(err) => err.fmt(f),
+            Self::Bind(address, err) => write!(f, "Could not listen on {}: {}", address, err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Starts a coordinator for each of the given chamber configurations and serves them, returning
+/// the addresses the server is listening on.
+///
+/// The server listens where the first chamber's configuration says to (see
+/// [`Config::server`](../struct.Config.html#structfield.server)). As with [`state`](fn.state.html),
+/// this must be called from within a running actix system.
+pub fn start<I>(configs: I) -> Result<Vec<SocketAddr>, Error>
+where
+    I: IntoIterator<Item = Config>,
+{
+    let configs = configs.into_iter().collect::<Vec<_>>();
+    let bind = configs
+        .first()
+        .and_then(|config| config.server.clone())
+        .unwrap_or_default()
+        .bind;
+    let state = state(configs)?;
+    let server = HttpServer::new(move || apps(state.clone()))
+        .bind(&bind)
+        .map_err(|err| Error::Bind(bind, err))?;
+    let addrs = server.addrs();
+    server.start();
+    Ok(addrs)
 }
@@ -0,0 +1,205 @@
+//! Protocol planning and storage utilities.
+use super::{
+    job::Error,
+    state::State as AppState,
+    store::{Get, List, Put, Remove},
+};
+use crate::{comm::Project, ChamberId, CoordError, Plan, Protocol};
+use actix_web::{http::header, AsyncResponder, HttpMessage, HttpRequest, HttpResponse, Json};
+use futures::prelude::*;
+use uuid::Uuid;
+
+use std::{collections::BTreeMap, time::SystemTime};
+
+/// A request to project the timeline of a protocol.
+#[derive(Deserialize)]
+pub struct WhatIf {
+    /// The protocol to project.
+    protocol: Protocol,
+    /// When the protocol would start (now, if unspecified).
+    #[serde(default)]
+    start: Option<SystemTime>,
+    /// The chamber whose timings should be used.
+    #[serde(default)]
+    chamber: ChamberId,
+}
+
+/// Projects when each step of the given protocol would start and end, and how much of each buffer
+/// it would use.
+///
+/// Nothing is run; this is intended for planning.
+#[allow(clippy::needless_pass_by_value)]
+pub fn whatif(
+    whatif: Json<WhatIf>,
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = Json<Plan>, Error = Error>> {
+    let WhatIf {
+        protocol,
+        start,
+        chamber,
+    } = whatif.into_inner();
+    let start = start.unwrap_or_else(SystemTime::now);
+    req.state()
+        .chamber(chamber)
+        .ok_or(Error::NoSuchChamber)
+        .map(|chamber| chamber.addr.send(Project(protocol, start)))
+        .into_future()
+        .and_then(|request| request.from_err())
+        .and_then(|result| result.map_err(Error::from))
+        .map(Json)
+        .responder()
+}
+
+/// Looks up the protocol ID specified in the request path.
+fn protocol_id(req: &HttpRequest<AppState>) -> Result<Uuid, Error> {
+    let id = req.match_info().get("protocol").ok_or(Error::InvalidUuid)?;
+    Uuid::parse_str(id).map_err(|_| Error::InvalidUuid)
+}
+
+/// Reads a protocol from the request body, accepting it only if it can be run.
+fn valid_protocol(req: &HttpRequest<AppState>) -> impl Future<Item = Protocol, Error = Error> {
+    req.json()
+        .from_err::<Error>()
+        .and_then(|protocol: Protocol| {
+            protocol
+                .as_program()
+                .map_err(|err| Error::from(CoordError::from(err)))?;
+            Ok(protocol)
+        })
+}
+
+/// All of the saved protocols, by ID.
+#[allow(clippy::needless_pass_by_value)]
+pub fn list(
+    req: HttpRequest<AppState>,
+) -> Box<dyn Future<Item = Json<BTreeMap<Uuid, Protocol>>, Error = Error>> {
+    req.state()
+        .store
+        .send(List)
+        .from_err()
+        .map(Json)
+        .responder()
+}
+
+/// A single saved protocol.
+#[allow(clippy::needless_pass_by_value)]
+pub fn fetch(req: HttpRequest<AppState>) -> Box<dyn Future<Item = Json<Protocol>, Error = Error>> {
+    let store = req.state().store.clone();
+    protocol_id(&req)
+        .into_future()
+        .and_then(move |id| store.send(Get(id)).from_err())
+        .and_then(|protocol| protocol.map(Json).ok_or(Error::NoSuchProtocol))
+        .responder()
+}
+
+/// Saves a new protocol, responding with its ID.
+#[allow(clippy::needless_pass_by_value)]
+pub fn create(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let store = req.state().store.clone();
+    valid_protocol(&req)
+        .and_then(move |protocol| {
+            let id = Uuid::new_v4();
+            store.send(Put(id, protocol)).from_err().map(move |_| {
+                HttpResponse::Created()
+                    .header(header::LOCATION, format!("{}", id))
+                    .finish()
+            })
+        })
+        .responder()
+}
+
+/// Replaces a saved protocol.
+#[allow(clippy::needless_pass_by_value)]
+pub fn replace(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let store = req.state().store.clone();
+    protocol_id(&req)
+        .into_future()
+        .join(valid_protocol(&req))
+        .and_then(move |(id, protocol)| {
+            let exists = store.send(Get(id)).from_err();
+            exists.and_then(move |existing| {
+                if existing.is_some() {
+                    Ok(store.send(Put(id, protocol)).from_err())
+                } else {
+                    Err(Error::NoSuchProtocol)
+                }
+            })
+        })
+        .flatten()
+        .map(|_| HttpResponse::NoContent().finish())
+        .responder()
+}
+
+/// Deletes a saved protocol.
+#[allow(clippy::needless_pass_by_value)]
+pub fn remove(req: HttpRequest<AppState>) -> Box<dyn Future<Item = HttpResponse, Error = Error>> {
+    let store = req.state().store.clone();
+    protocol_id(&req)
+        .into_future()
+        .and_then(move |id| store.send(Remove(id)).from_err())
+        .and_then(|removed| removed.ok_or(Error::NoSuchProtocol))
+        .map(|_| HttpResponse::NoContent().finish())
+        .responder()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{actix::System, server::state::app_state, Step};
+    use actix_web::{http::StatusCode, test::TestRequest, ResponseError};
+
+    fn state() -> AppState {
+        app_state(vec![])
+    }
+
+    fn request(
+        state: &AppState,
+        id: Option<&'static str>,
+        payload: &'static str,
+    ) -> HttpRequest<AppState> {
+        let mut request = TestRequest::with_state(state.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload(payload);
+        if let Some(id) = id {
+            request = request.param("protocol", id);
+        }
+        request.finish()
+    }
+
+    #[test]
+    fn create_read_delete() {
+        let mut system = System::new("store");
+        let state = state();
+        let created = system
+            .block_on(create(request(&state, None, r#"[{"perfuse": [0, null]}]"#)))
+            .unwrap();
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let location = created.headers()[header::LOCATION].to_str().unwrap();
+        // Test request parameters must be static.
+        let id: &'static str = Box::leak(location.to_owned().into_boxed_str());
+        let fetched = system
+            .block_on(fetch(request(&state, Some(id), "")))
+            .unwrap();
+        assert_eq!(fetched.steps(), [Step::Perfuse(0, None)]);
+        let listed = system.block_on(list(request(&state, None, ""))).unwrap();
+        assert_eq!(listed.len(), 1);
+        let removed = system
+            .block_on(remove(request(&state, Some(id), "")))
+            .unwrap();
+        assert_eq!(removed.status(), StatusCode::NO_CONTENT);
+        let missing = system.block_on(fetch(request(&state, Some(id), "")));
+        assert!(matches!(missing, Err(Error::NoSuchProtocol)));
+    }
+
+    #[test]
+    fn invalid_protocol() {
+        let mut system = System::new("store");
+        let state = state();
+        let err = system
+            .block_on(create(request(&state, None, r#"[{"perfuse": [0, "5s"]}]"#)))
+            .unwrap_err();
+        assert_eq!(err.error_response().status(), StatusCode::BAD_REQUEST);
+        let listed = system.block_on(list(request(&state, None, ""))).unwrap();
+        assert!(listed.is_empty());
+    }
+}
@@ -0,0 +1,216 @@
+//! WebSocket connections for monitoring and controlling jobs.
+use super::{
+    events::encode,
+    job::{chamber, Error, UUID},
+    state::State as AppState,
+};
+use crate::{
+    actix::{Actor, Addr, AsyncContext},
+    comm::{GetSnapshot, Message, Status, Subscribers, Update},
+    Coordinator,
+};
+use actix_web::{
+    actix::{ActorContext, ActorFuture, StreamHandler, WrapFuture},
+    ws, HttpRequest, HttpResponse,
+};
+use futures::{prelude::*, sync::mpsc};
+use uuid::Uuid;
+
+/// A coordinator subscriber which forwards status updates to a connected socket as JSON frames.
+///
+/// As with [`EventStream`](../events/struct.EventStream.html), the subscriber reports itself
+/// closed once the socket goes away.
+#[derive(Debug)]
+pub struct Frames {
+    sender: mpsc::UnboundedSender<String>,
+}
+
+impl Frames {
+    /// Creates a subscriber along with the stream of encoded frames it will produce.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<String>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (Self { sender }, receiver)
+    }
+}
+
+impl Update for Frames {
+    fn handle(&self, status: &Status, _coord: &Subscribers) {
+        let (name, data) = encode(&status.message);
+        let frame = serde_json::json!({ "event": name, "data": data });
+        // If the client has gone away, we'll be dropped before the next update.
+        let _ = self.sender.unbounded_send(frame.to_string());
+    }
+
+    fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+}
+
+/// The ways a client can control the active job.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Control {
+    Continue,
+    Pause,
+    Stop,
+    Halt,
+    Skip,
+}
+
+impl From<Control> for Message {
+    fn from(control: Control) -> Self {
+        match control {
+            Control::Continue => Self::Continue,
+            Control::Pause => Self::Pause,
+            Control::Stop => Self::Stop,
+            Control::Halt => Self::Halt,
+            Control::Skip => Self::Skip,
+        }
+    }
+}
+
+/// A control frame sent by the client.
+#[derive(Debug, Deserialize)]
+struct Command {
+    /// The job to control, which must be the active one.
+    job: Uuid,
+    /// What to do with it.
+    action: Control,
+}
+
+/// Carries out the command in the given control frame, if it names the active job.
+fn control(addr: Addr<Coordinator>, frame: &str) -> Box<dyn Future<Item = (), Error = Error>> {
+    let command: Command = match serde_json::from_str(frame) {
+        Ok(command) => command,
+        Err(err) => return Box::new(futures::future::err(Error::Command(err))),
+    };
+    let uuid = UUID::from(command.job);
+    Box::new(
+        addr.send(GetSnapshot)
+            .from_err()
+            .and_then(move |snapshot| {
+                if uuid.is_current(&snapshot) {
+                    Ok(addr.send(Message::from(command.action)).from_err())
+                } else {
+                    Err(Error::IncorrectUuid)
+                }
+            })
+            .flatten()
+            .and_then(|result| result.map_err(Error::from)),
+    )
+}
+
+/// A WebSocket connection to a chamber's coordinator.
+#[derive(Debug)]
+struct Socket {
+    addr: Addr<Coordinator>,
+}
+
+impl Actor for Socket {
+    type Context = ws::WebsocketContext<Self, AppState>;
+
+    fn started(&mut self, context: &mut Self::Context) {
+        let (subscriber, receiver) = Frames::new();
+        self.addr.do_send(Message::Subscribe(Box::new(subscriber)));
+        context.add_stream(receiver);
+    }
+}
+
+impl StreamHandler<String, ()> for Socket {
+    fn handle(&mut self, frame: String, context: &mut Self::Context) {
+        context.text(frame);
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for Socket {
+    fn handle(&mut self, message: ws::Message, context: &mut Self::Context) {
+        match message {
+            ws::Message::Text(frame) => {
+                let request = control(self.addr.clone(), &frame).into_actor(self).map_err(
+                    |err, _, context| {
+                        let frame = serde_json::json!({ "error": err.to_string() });
+                        context.text(frame.to_string());
+                    },
+                );
+                context.spawn(request);
+            }
+            ws::Message::Ping(ping) => context.pong(&ping),
+            ws::Message::Close(_) => context.stop(),
+            ws::Message::Binary(_) | ws::Message::Pong(_) => {}
+        }
+    }
+}
+
+/// Opens a WebSocket which streams the chamber's status updates and accepts control commands.
+///
+/// Updates are sent as `{"event": ..., "data": ...}` frames, named as for the
+/// [event stream](../events/fn.events.html). Commands take the form
+/// `{"job": ..., "action": "continue" | "pause" | "stop" | "halt" | "skip"}`, and are only carried
+/// out if they name the active job; otherwise, an `{"error": ...}` frame is sent back.
+#[allow(clippy::needless_pass_by_value)]
+pub fn socket(req: HttpRequest<AppState>) -> Result<HttpResponse, actix_web::Error> {
+    let addr = chamber(&req)?.addr.clone();
+    ws::start(&req, Socket { addr })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        actix::{Arbiter, System},
+        Action, Config, Protocol, Step,
+    };
+    use std::{collections::HashMap, time::Duration};
+
+    #[test]
+    fn resume_from_pause() {
+        let id = Uuid::new_v4();
+        let (subscriber, receiver) = Frames::new();
+        System::run(move || {
+            let addr = Coordinator::create(move |context| {
+                context.run_later(Duration::from_millis(50), move |coord, context| {
+                    // The job is still waiting for the valves to settle, so stand in for it.
+                    coord.state.uuid = Some(id);
+                    coord.state.remaining = vec![Action::Hail, Action::Finish];
+                    coord.advance(context).unwrap();
+                });
+                context.run_later(Duration::from_millis(100), move |_, context| {
+                    let frame = format!(r#"{{"job": "{}", "action": "continue"}}"#, id);
+                    Arbiter::spawn(control(context.address(), &frame).map_err(|_| ()));
+                });
+                context.run_later(Duration::from_millis(200), |_, _| System::current().stop());
+                Coordinator::try_new(Config::stub()).unwrap()
+            });
+            let protocol = Protocol::with_step(Step::Perfuse(0, None));
+            addr.do_send(Message::Subscribe(Box::new(subscriber)));
+            addr.do_send(Message::Start(protocol, Some(id), HashMap::new()));
+        });
+        let frames = receiver
+            .map(|frame| serde_json::from_str::<serde_json::Value>(&frame).unwrap())
+            .filter(|frame| frame["event"] != "advanced")
+            .take(3)
+            .collect()
+            .wait()
+            .unwrap();
+        let names = frames
+            .iter()
+            .map(|frame| frame["event"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["started", "paused", "continued"]);
+    }
+
+    #[test]
+    fn rejects_other_jobs() {
+        let mut system = System::new("socket");
+        let addr = Coordinator::create(|_| {
+            let mut coord = Coordinator::try_new(Config::stub()).unwrap();
+            coord.state.uuid = Some(Uuid::new_v4());
+            coord
+        });
+        let frame = format!(r#"{{"job": "{}", "action": "halt"}}"#, Uuid::new_v4());
+        let result = system.block_on(control(addr.clone(), &frame));
+        assert!(matches!(result, Err(Error::IncorrectUuid)));
+        let result = system.block_on(control(addr, r#"{"action": "halt"}"#));
+        assert!(matches!(result, Err(Error::Command(_))));
+    }
+}
@@ -1,14 +1,51 @@
 //! App state management.
-use crate::{actix::Addr, Coordinator};
+use super::{keys::Keys, store::Store};
+use crate::{actix::Addr, AuthConfig, ChamberId, Coordinator};
 
-use std::sync::Arc;
-
-/// Contains the coordinator and other required state components.
+/// Contains the coordinator of a single chamber.
 #[derive(Clone, Debug)]
-pub struct State {
-    /// The coordinator.
-    // We don't need an RwLock because we'll just be sending messages.
-    pub coord: Arc<Coordinator>,
+pub struct Chamber {
     /// The address of the coordinator.
+    ///
+    /// Everything (including status queries) goes through messages, so no locking is needed.
     pub addr: Addr<Coordinator>,
+    /// Who may control the chamber (anyone, if unspecified).
+    pub auth: Option<AuthConfig>,
+}
+
+/// Contains the chambers and other required state components.
+///
+/// Each chamber has its own coordinator (and so its own devices), and saved protocols and
+/// idempotency keys are kept by their own actors, so nothing mutable is shared.
+#[derive(Clone, Debug)]
+pub struct State {
+    /// The chambers served by this process, indexed by their IDs.
+    pub chambers: Vec<Chamber>,
+    /// The address of the store of saved protocols.
+    pub store: Addr<Store>,
+    /// The address of the record of idempotency keys used to start jobs.
+    pub keys: Addr<Keys>,
+    /// The origins of the web frontends which may make requests from a browser.
+    pub origins: Vec<String>,
+}
+
+impl State {
+    /// Returns the chamber with the given ID, if there is one.
+    pub fn chamber(&self, id: ChamberId) -> Option<&Chamber> {
+        self.chambers.get(id)
+    }
+}
+
+/// Returns the state for serving the given chambers in tests, with nothing saved and no
+/// origins allowed.
+#[cfg(test)]
+pub(crate) fn app_state(chambers: Vec<Chamber>) -> State {
+    use crate::actix::Actor;
+
+    State {
+        chambers,
+        store: Store::default().start(),
+        keys: Keys::default().start(),
+        origins: vec![],
+    }
 }
@@ -0,0 +1,83 @@
+//! Storage of protocols for later use.
+use crate::{
+    actix::{ActixMessage, Actor, Context, Handle},
+    Protocol,
+};
+use actix_web::actix::MessageResult;
+use uuid::Uuid;
+
+use std::collections::BTreeMap;
+
+/// Keeps saved protocols, by ID.
+///
+/// Protocols are only kept in memory, so they're lost when the server restarts.
+#[derive(Debug, Default)]
+pub struct Store {
+    protocols: BTreeMap<Uuid, Protocol>,
+}
+
+impl Actor for Store {
+    type Context = Context<Self>;
+}
+
+/// Requests all of the stored protocols.
+#[derive(Clone, Copy, Debug)]
+pub struct List;
+
+impl ActixMessage for List {
+    type Result = BTreeMap<Uuid, Protocol>;
+}
+
+/// Requests the protocol with the given ID.
+#[derive(Clone, Copy, Debug)]
+pub struct Get(pub Uuid);
+
+impl ActixMessage for Get {
+    type Result = Option<Protocol>;
+}
+
+/// Stores the given protocol under the given ID, replacing any protocol already stored there.
+///
+/// The result is whether a protocol was replaced.
+#[derive(Clone, Debug)]
+pub struct Put(pub Uuid, pub Protocol);
+
+impl ActixMessage for Put {
+    type Result = bool;
+}
+
+/// Removes the protocol with the given ID, returning it.
+#[derive(Clone, Copy, Debug)]
+pub struct Remove(pub Uuid);
+
+impl ActixMessage for Remove {
+    type Result = Option<Protocol>;
+}
+
+impl Handle<List> for Store {
+    type Result = MessageResult<List>;
+    fn handle(&mut self, _: List, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.protocols.clone())
+    }
+}
+
+impl Handle<Get> for Store {
+    type Result = MessageResult<Get>;
+    fn handle(&mut self, message: Get, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.protocols.get(&message.0).cloned())
+    }
+}
+
+impl Handle<Put> for Store {
+    type Result = MessageResult<Put>;
+    fn handle(&mut self, message: Put, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.protocols.insert(message.0, message.1).is_some())
+    }
+}
+
+impl Handle<Remove> for Store {
+    type Result = MessageResult<Remove>;
+    fn handle(&mut self, message: Remove, _context: &mut Self::Context) -> Self::Result {
+        MessageResult(self.protocols.remove(&message.0))
+    }
+}
@@ -0,0 +1,338 @@
+//! Contains utilities for posting status changes to a webhook.
+
+#[cfg(feature = "use_serde")]
+use crate::{
+    comm::{Status, StatusMessage, Subscribers, Update},
+    Action,
+};
+
+use actix_web::actix::{Actor, Addr, Handler, Message, SyncArbiter, SyncContext};
+
+use std::{
+    io::{BufRead, BufReader, Error, ErrorKind, Result, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+/// How many times a status update is posted before giving up on it.
+const ATTEMPTS: u32 = 3;
+
+/// How long to wait between attempts to post a status update.
+const BACKOFF: Duration = Duration::from_secs(2);
+
+/// How long the webhook is given to accept a status update and respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where status updates are posted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Endpoint {
+    /// The server's host name.
+    pub host: String,
+    /// The server's port.
+    pub port: u16,
+    /// The path posted to.
+    pub path: String,
+}
+
+impl Endpoint {
+    /// Parses an `http://` URL, returning `None` if it isn't one.
+    ///
+    /// HTTPS isn't supported; such webhooks can be reached through a local relay.
+    pub fn parse(url: &str) -> Option<Self> {
+        if !url.starts_with("http://") {
+            return None;
+        }
+        let rest = &url["http://".len()..];
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(index) => (&authority[..index], authority[index + 1..].parse().ok()?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+    /// Posts the given JSON, failing unless the server responds with success (2xx).
+    pub fn post(&self, body: &str) -> Result<()> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\n\
+             Host: {}:{}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n\
+             {}",
+            self.path,
+            self.host,
+            self.port,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        // The status line looks like `HTTP/1.1 204 No Content`.
+        let code = status
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok());
+        match code {
+            Some(code) if (200..300).contains(&code) => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::Other,
+                format!("Unexpected webhook response: {}", status.trim_end()),
+            )),
+        }
+    }
+}
+
+/// Posts the given JSON to the endpoint, trying up to `attempts` times (waiting `backoff`
+/// between attempts) before returning the last error.
+fn deliver(endpoint: &Endpoint, body: &str, attempts: u32, backoff: Duration) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match endpoint.post(body) {
+            Err(err) if attempt < attempts => {
+                log::debug!("Could not post to webhook (attempt {}): {}", attempt, err);
+                thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Asks the poster to deliver the given JSON.
+#[derive(Debug)]
+pub struct Post(pub String);
+
+impl Message for Post {
+    type Result = ();
+}
+
+/// Posts to a webhook on its own thread, so that slow or failing deliveries don't hold up the
+/// coordinator.
+///
+/// Deliveries are retried a couple of times, then given up on and logged.
+#[derive(Debug)]
+pub struct Poster {
+    endpoint: Endpoint,
+}
+
+impl Poster {
+    /// Starts a poster on its own thread, delivering to the given endpoint.
+    pub fn spawn(endpoint: Endpoint) -> Addr<Self> {
+        SyncArbiter::start(1, move || Self {
+            endpoint: endpoint.clone(),
+        })
+    }
+}
+
+impl Actor for Poster {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<Post> for Poster {
+    type Result = ();
+    fn handle(&mut self, Post(body): Post, _context: &mut Self::Context) {
+        if let Err(err) = deliver(&self.endpoint, &body, ATTEMPTS, BACKOFF) {
+            log::warn!(
+                "Could not post to webhook after {} attempts: {}",
+                ATTEMPTS,
+                err
+            );
+        }
+    }
+}
+
+/// A coordinator subscriber which posts each status update to a webhook.
+#[cfg(feature = "use_serde")]
+#[derive(Debug)]
+pub struct Webhook {
+    poster: Addr<Poster>,
+}
+
+#[cfg(feature = "use_serde")]
+impl Webhook {
+    /// Creates a subscriber posting to the given endpoint (from its own thread).
+    pub fn spawn(endpoint: Endpoint) -> Self {
+        Self {
+            poster: Poster::spawn(endpoint),
+        }
+    }
+}
+
+#[cfg(feature = "use_serde")]
+impl Update for Webhook {
+    fn handle(&self, status: &Status, _coord: &Subscribers) {
+        let (state, message) = describe(&status.message);
+        let payload = serde_json::json!({
+            "uuid": status.job,
+            "state": state,
+            "message": message,
+        });
+        self.poster.do_send(Post(payload.to_string()));
+    }
+}
+
+/// Names a status update and describes it for people.
+#[cfg(feature = "use_serde")]
+fn describe(message: &StatusMessage) -> (&'static str, String) {
+    match message {
+        StatusMessage::Continued => ("continued", "The job has been continued.".to_owned()),
+        StatusMessage::Started(_) => ("started", "The job has started.".to_owned()),
+        StatusMessage::Paused => (
+            "paused",
+            "The job is paused, awaiting confirmation to continue.".to_owned(),
+        ),
+        StatusMessage::StopQueued { .. } => (
+            "stop_queued",
+            "The job will stop after the current step.".to_owned(),
+        ),
+        StatusMessage::Halted { reason } => ("halted", format!("The job has been {}.", reason)),
+        StatusMessage::QueueCleared => (
+            "queue_cleared",
+            "The queued job has been cancelled.".to_owned(),
+        ),
+        StatusMessage::Scheduled { id, at } => (
+            "scheduled",
+            format!(
+                "Job {} is scheduled to start at {}.",
+                id,
+                humantime::format_rfc3339_seconds(*at)
+            ),
+        ),
+        StatusMessage::Skipped => ("skipped", "The current step has been skipped.".to_owned()),
+        StatusMessage::Errored(description) => (
+            "errored",
+            format!("The job has been aborted due to an error: {}", description),
+        ),
+        StatusMessage::Notified(notification) => (
+            "notified",
+            format!("{}: {}", notification.subject, notification.message),
+        ),
+        StatusMessage::Advanced {
+            action: Action::Finish,
+            ..
+        } => ("finished", "The job has finished.".to_owned()),
+        StatusMessage::Advanced {
+            completed,
+            total,
+            action,
+        } => (
+            "advanced",
+            format!("Step {} of {}: {}", completed + 1, total, action),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Read,
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+    };
+
+    /// Starts a server which responds to each request with the next of the given status codes,
+    /// sending along the bodies of the requests it receives.
+    fn mock(codes: Vec<u16>) -> (Endpoint, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = Endpoint {
+            host: "127.0.0.1".to_owned(),
+            port: listener.local_addr().unwrap().port(),
+            path: "/hooks/deoxy".to_owned(),
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for code in codes {
+                let (stream, _) = listener.accept().unwrap();
+                tx.send(read_request(&stream)).unwrap();
+                write!(&stream, "HTTP/1.1 {} Whatever\r\n\r\n", code).unwrap();
+            }
+        });
+        (endpoint, rx)
+    }
+
+    /// Reads a request from the stream, returning its body.
+    fn read_request(stream: &TcpStream) -> String {
+        let mut reader = BufReader::new(stream);
+        let mut length = 0;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            let header = line.to_lowercase();
+            if header.starts_with("content-length:") {
+                length = header["content-length:".len()..].trim().parse().unwrap();
+            }
+            line.clear();
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn endpoint_parsing() {
+        assert_eq!(
+            Endpoint::parse("http://relay.local:8080/hooks/slack"),
+            Some(Endpoint {
+                host: "relay.local".to_owned(),
+                port: 8080,
+                path: "/hooks/slack".to_owned(),
+            })
+        );
+        let endpoint = Endpoint::parse("http://relay.local").unwrap();
+        assert_eq!((endpoint.port, endpoint.path.as_str()), (80, "/"));
+        assert_eq!(Endpoint::parse("https://hooks.slack.com/services/x"), None);
+        assert_eq!(Endpoint::parse("http://:80/"), None);
+    }
+
+    #[test]
+    fn retried_delivery() {
+        let (endpoint, rx) = mock(vec![503, 204]);
+        deliver(&endpoint, "{}", 2, Duration::from_millis(10)).unwrap();
+        assert_eq!(rx.try_iter().count(), 2);
+        let (endpoint, _rx) = mock(vec![500, 500]);
+        assert!(deliver(&endpoint, "{}", 2, Duration::from_millis(10)).is_err());
+    }
+
+    #[cfg(all(feature = "use_serde", feature = "stub"))]
+    #[test]
+    fn halt_delivered() {
+        use crate::{Config, CoordMessage, Coordinator};
+        use futures::Future;
+
+        let (endpoint, rx) = mock(vec![204]);
+        let mut config = Config::stub();
+        config.webhook_url = Some(format!("http://127.0.0.1:{}/hooks/deoxy", endpoint.port));
+        let (tx, addr) = mpsc::channel();
+        thread::spawn(move || {
+            crate::actix::System::run(move || {
+                tx.send(Coordinator::try_new(config).unwrap().start())
+                    .unwrap();
+            });
+        });
+        let addr = addr.recv().unwrap();
+        addr.send(CoordMessage::Halt).wait().unwrap().unwrap();
+        let payload: serde_json::Value =
+            serde_json::from_str(&rx.recv_timeout(Duration::from_secs(5)).unwrap()).unwrap();
+        assert_eq!(payload["state"], "halted");
+        assert!(payload["uuid"].is_null());
+        assert!(payload["message"].is_string());
+        addr.do_send(CoordMessage::Shutdown);
+    }
+}
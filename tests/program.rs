@@ -0,0 +1,20 @@
+use deoxy::{Action, Program, ProtocolBuilder};
+
+use std::time::Duration;
+
+#[test]
+fn root_protocol_is_core_protocol() {
+    let protocol = ProtocolBuilder::new()
+        .perfuse(0, Duration::from_secs(60))
+        .bath(1)
+        .build()
+        .unwrap();
+    let program: deoxy_core::Program = protocol.as_program().unwrap();
+    let expected: Program = deoxy_core::Protocol::from(protocol.steps().to_vec())
+        .as_program()
+        .unwrap();
+    assert_eq!(program, expected);
+    let actions: Vec<Action> = program.into();
+    assert_eq!(actions.first(), Some(&Action::Perfuse(0)));
+    assert_eq!(actions.last(), Some(&Action::Finish));
+}
@@ -0,0 +1,79 @@
+#![cfg(all(feature = "use_serde", feature = "stub"))]
+use deoxy::{
+    actix::*, Config, CoordError, CoordMessage, Coordinator, Program, Protocol, ProtocolBuilder,
+    Step,
+};
+use futures::Future;
+
+use std::{collections::HashMap, sync::mpsc, thread, time::Duration};
+
+#[test]
+fn config_and_protocol_round_trip() {
+    let mut config = Config::example();
+    config.settle_delay = Some(Duration::from_millis(10));
+    config.admins = vec!["admin@example.com".to_owned()];
+    let protocol = ProtocolBuilder::new()
+        .prime(0, Duration::from_secs(5))
+        .perfuse(1, Duration::from_secs(60))
+        .bath(2)
+        .build()
+        .unwrap();
+    let config: Config = serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+    assert_eq!(config.settle_delay, Some(Duration::from_millis(10)));
+    let json = serde_json::to_string(&protocol).unwrap();
+    let protocol: Protocol = serde_json::from_str(&json).unwrap();
+    let program = protocol.as_program().unwrap();
+    let json = serde_json::to_string(&program).unwrap();
+    assert_eq!(serde_json::from_str::<Program>(&json).unwrap(), program);
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        System::run(move || {
+            let addr = Coordinator::try_new(config).unwrap().start();
+            tx.send(addr).unwrap();
+        });
+    });
+    let addr = rx.recv().unwrap();
+    let started = addr.send(CoordMessage::Start(protocol.clone(), None, HashMap::new()));
+    assert!(started.wait().unwrap().is_ok());
+    // Once the valves have settled, the job is running.
+    thread::sleep(Duration::from_millis(100));
+    let again = addr.send(CoordMessage::Start(protocol, None, HashMap::new()));
+    assert!(matches!(again.wait().unwrap(), Err(CoordError::Busy)));
+    addr.do_send(CoordMessage::Shutdown);
+}
+
+#[test]
+fn minimal_config_parses() {
+    // Only the devices are required; everything added since has a default.
+    let config: Config = serde_json::from_str(
+        r#"{
+            "pump": { "pins": [1, 2, 3, 4] },
+            "motors": [{ "pin": 5, "period": "20ms", "range": ["600us", "2400us"] }]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.pumps.len(), 1);
+    assert!(config.admins.is_empty());
+    assert!(!config.simulate);
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn protocol_serialization_unchanged() {
+    // Protocols have always been serialized as a bare list of steps.
+    let json = r#"[{"perfuse":[0,"1m"]},{"perfuse":[1,null]}]"#;
+    let mut protocol: Protocol = serde_json::from_str(json).unwrap();
+    assert_eq!(protocol.steps().len(), 2);
+    assert_eq!(serde_json::to_string(&protocol).unwrap(), json);
+    protocol.insert_step(1, Step::Perfuse(2, Some(Duration::from_secs(30))));
+    assert_eq!(
+        serde_json::to_string(&protocol).unwrap(),
+        r#"[{"perfuse":[0,"1m"]},{"perfuse":[2,"30s"]},{"perfuse":[1,null]}]"#
+    );
+    let built = Protocol::from(protocol.steps().to_vec());
+    assert_eq!(
+        serde_json::to_vec(&built).unwrap(),
+        serde_json::to_vec(&protocol).unwrap()
+    );
+}
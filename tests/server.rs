@@ -0,0 +1,148 @@
+#![cfg(all(feature = "server", feature = "stub"))]
+use deoxy::{
+    actix::System,
+    actix_web::server::HttpServer,
+    server::{apps, start, state, Error},
+    Config, MotorConfig, PumpConfig, ServerConfig,
+};
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+fn config() -> Config {
+    let motor = |pin| MotorConfig {
+        pin,
+        label: None,
+        period: Duration::from_millis(20),
+        range: [Duration::from_micros(600), Duration::from_micros(2400)],
+        range_degrees: None,
+        slew: None,
+    };
+    Config {
+        pumps: vec![PumpConfig {
+            pins: [1, 2, 3, 4],
+            invert: false,
+            period: None,
+            dead_time: None,
+        }],
+        motors: vec![motor(5), motor(6), motor(7), motor(8)],
+        waste_motor: 0,
+        admins: vec![],
+        rescue_buffer: None,
+        ambient: None,
+        interlocks: vec![],
+        soak: Duration::new(0, 0),
+        volume: None,
+        rate: None,
+        drain_rate: None,
+        estop_pin: None,
+        history: None,
+        state_file: None,
+        settle_delay: None,
+        line_clear_delay: None,
+        mail: None,
+        webhook_url: None,
+        auth: None,
+        server: None,
+        motor_retries: None,
+        halt_retries: None,
+        halt_backoff: None,
+        max_step_duration: None,
+        simulate: false,
+    }
+}
+
+#[test]
+fn status_of_idle_chamber() {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        System::run(move || {
+            let state = state(vec![config()]).unwrap();
+            let server = HttpServer::new(move || apps(state.clone()))
+                .bind("127.0.0.1:0")
+                .unwrap();
+            tx.send(server.addrs()[0]).unwrap();
+            server.start();
+        });
+    });
+    let addr = rx.recv().unwrap();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /chambers/0/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("null"), "{}", response);
+}
+
+#[test]
+fn metadata_round_trip() {
+    let mut config = config();
+    config.settle_delay = Some(Duration::from_millis(10));
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        System::run(move || {
+            let state = state(vec![config]).unwrap();
+            let server = HttpServer::new(move || apps(state.clone()))
+                .bind("127.0.0.1:0")
+                .unwrap();
+            tx.send(server.addrs()[0]).unwrap();
+            server.start();
+        });
+    });
+    let addr = rx.recv().unwrap();
+    let request = |request: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let body = r#"{
+        "protocol": [{ "perfuse": [0, null] }],
+        "metadata": { "sample": "Heart 3", "operator": "AH" }
+    }"#;
+    let created = request(&format!(
+        "POST /chambers/0/ HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    ));
+    assert!(created.starts_with("HTTP/1.1 201"), "{}", created);
+    // Once the valves have settled, the job is running.
+    thread::sleep(Duration::from_millis(200));
+    let status =
+        request("GET /chambers/0/ HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+    let body = &status[status.find("\r\n\r\n").unwrap() + 4..];
+    let job: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(job["metadata"]["sample"], "Heart 3");
+    assert_eq!(job["metadata"]["operator"], "AH");
+}
+
+#[test]
+fn configured_bind_address() {
+    let _system = System::new("bind");
+    let mut config = config();
+    config.server = Some(ServerConfig {
+        bind: "127.0.0.1:0".to_owned(),
+        allowed_origins: None,
+    });
+    let addrs = start(vec![config.clone()]).unwrap();
+    assert!(addrs[0].ip().is_loopback());
+    assert_ne!(addrs[0].port(), 0);
+    config.server = Some(ServerConfig {
+        bind: "localhost:noport".to_owned(),
+        allowed_origins: None,
+    });
+    let err = start(vec![config]).unwrap_err();
+    assert!(matches!(err, Error::Bind(ref address, _) if address == "localhost:noport"));
+    assert!(err
+        .to_string()
+        .starts_with("Could not listen on localhost:noport"));
+}
@@ -1,10 +1,7 @@
 use yew::html;
 use yew::prelude::*;
 
-use uom::{
-    fmt::DisplayStyle,
-    si::{f32::*, volume::milliliter},
-};
+use uom::si::{f32::*, volume::liter};
 
 use std::{cell::RefCell, rc::Rc};
 
@@ -66,7 +63,7 @@ impl Renderable<Self> for Buffers {
             <div id={"buffers"},>
             <h1>{"Buffers"}</h1>
             <table>
-                <tr><th>{"Index"}</th><th>{"Label"}</th><th>{"Volume"}</th></tr>
+                <tr><th>{"Index"}</th><th>{"Label"}</th><th>{"Volume (L)"}</th></tr>
                 { for buffers.iter().map(Renderable::view) }
             </table>
             </div>
@@ -129,8 +126,7 @@ impl Renderable<Buffers> for Buffer {
             }
         };
         let volume = if let Some(volume) = self.volume {
-            let fmt = Volume::format_args(milliliter, DisplayStyle::Abbreviation);
-            format!("{}", fmt.with(volume))
+            volume.get::<liter>().to_string()
         } else {
             "".to_string()
         };
@@ -145,7 +141,14 @@ impl Renderable<Buffers> for Buffer {
                         oninput=|e| BufferMessage::Input(index, e.value),
                         onchange=|e| onchange(e), />
                 </td>
-                <td>{volume}</td>
+                <td class={"volume"},>
+                    <input type={"number"},
+                        name={"volume[]"},
+                        min=0,
+                        step={"any"},
+                        value=volume,
+                        oninput=|e| BufferMessage::Volume(index, e.value), />
+                </td>
             </tr>
         }
     }
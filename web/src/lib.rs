@@ -1,7 +1,9 @@
+use yew::format::Json;
 use yew::html;
 use yew::prelude::*;
+use yew::services::fetch::{FetchService, FetchTask, Request, Response};
 
-use deoxy_core::Step as CStep;
+use deoxy_core::{Step as CStep, ValidateProtocolError as ValidateError};
 
 use uom::si::{f32::*, volume::liter};
 
@@ -18,6 +20,8 @@ const SLOTS: usize = 10;
 const WASTE: usize = 1;
 pub const BUFFERS: usize = SLOTS - WASTE;
 pub const VOLUME_LITERS: f32 = 0.5;
+/// The chamber to which jobs are submitted.
+const CHAMBER: usize = 0;
 
 fn reaction_volume() -> Volume {
     Volume::new::<liter>(VOLUME_LITERS)
@@ -30,14 +34,118 @@ pub struct ProtocolProps {
     pub onchange: Option<Callback<ProtocolMessage>>,
 }
 
+/// The kinds of step the user can add.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepKind {
+    /// Perfusion for a given number of minutes.
+    Perfuse,
+    /// Indefinite perfusion, which ends the protocol.
+    Bath,
+}
+
+impl Default for StepKind {
+    fn default() -> Self {
+        StepKind::Perfuse
+    }
+}
+
 #[derive(Default, PartialEq)]
-pub struct Step(usize, Option<CStep>, Vec<Buffer>);
+pub struct Step(usize, Option<CStep>, Vec<Buffer>, StepKind);
+
+impl Step {
+    /// The core step described by this row, if it has been filled in.
+    fn complete(&self) -> Option<CStep> {
+        match (self.3, &self.1) {
+            (StepKind::Perfuse, Some(CStep::Perfuse(_, None))) => None,
+            (_, step) => step.clone(),
+        }
+    }
+}
+
+/// Renumbers the rows after they've been added, removed, or moved.
+///
+/// If the last row is a complete perfusion, a blank row is appended so that another step can be
+/// entered.
+fn renumber(steps: &mut Vec<Step>) {
+    let open = match steps.last() {
+        Some(last) => last.3 == StepKind::Perfuse && last.complete().is_some(),
+        None => true,
+    };
+    if open {
+        let mut next = Step::default();
+        next.2 = steps.last().map(|s| s.2.clone()).unwrap_or_default();
+        steps.push(next);
+    }
+    for (i, s) in steps.iter_mut().enumerate() {
+        s.0 = i;
+    }
+}
+
+/// Builds a protocol from the rows that have been filled in.
+pub fn protocol(steps: &[Step]) -> deoxy_core::Protocol {
+    deoxy_core::Protocol::from(steps.iter().filter_map(Step::complete).collect::<Vec<_>>())
+}
 #[derive(Default)]
 struct Protocol {
     steps: Rc<RefCell<Vec<Step>>>,
     onchange: Option<Callback<ProtocolMessage>>,
 }
 
+/// Estimates how long the rows that have been filled in will take to run.
+///
+/// Once the protocol is valid, this includes the time spent filling and draining the chamber.
+/// Until then, only the perfusion times entered so far are counted.
+fn estimate(steps: &[Step]) -> String {
+    let protocol = protocol(steps);
+    let total = protocol.duration().unwrap_or_else(|| {
+        protocol
+            .steps()
+            .iter()
+            .filter_map(|step| match step {
+                CStep::Perfuse(_, time) => *time,
+                _ => None,
+            })
+            .sum()
+    });
+    let mins = (total.as_secs() + 59) / 60;
+    let total = if mins >= 60 {
+        format!("{} h {} min", mins / 60, mins % 60)
+    } else {
+        format!("{} min", mins)
+    };
+    if steps.iter().any(|step| step.3 == StepKind::Bath) {
+        format!("≥ {} (plus open-ended steps)", total)
+    } else {
+        total
+    }
+}
+
+/// Explains why the protocol can't be run, in terms of the form.
+fn explain(err: &ValidateError) -> String {
+    match err {
+        ValidateError::Empty => "Add a step to get started.".to_string(),
+        ValidateError::Last { .. } => {
+            "End with a bath, so the sample isn't left dry when the protocol finishes.".to_string()
+        }
+        ValidateError::ZeroDuration(index) => {
+            format!("Step {} needs to run for at least a minute.", index + 1)
+        }
+        ValidateError::ZeroCycles(index) => {
+            format!("Step {} needs to recirculate at least once.", index + 1)
+        }
+        ValidateError::ZeroRepeat => "Every repetition needs to run at least once.".to_string(),
+        ValidateError::TooLong { index, .. } => {
+            format!("Step {} runs for longer than is allowed.", index + 1)
+        }
+        ValidateError::IntermediateBath(index) => {
+            format!(
+                "Step {} is a bath, so nothing can come after it.",
+                index + 1
+            )
+        }
+    }
+}
+
 impl Component for Protocol {
     type Message = ProtocolMessage;
     type Properties = ProtocolProps;
@@ -67,13 +175,22 @@ impl Renderable<Self> for Protocol {
                 break steps;
             }
         };
+        let problem = protocol(&steps).validate().err().map(|err| explain(&err));
+        let feedback = if let Some(problem) = &problem {
+            html! { <p class="invalid",>{problem}</p> }
+        } else {
+            html! { <></> }
+        };
         html! {
             <div id={"protocol"},>
             <h1>{"Protocol"}</h1>
+            <span class="runtime",>{estimate(&steps)}</span>
             <ol>
             { for steps.iter().map(Renderable::view) }
             </ol>
-            <input type={"button"}, id={"start"}, value={"Start"}, />
+            { feedback }
+            <input type={"button"}, id={"start"}, value={"Start"}, disabled=problem.is_some(),
+                onclick=|_| ProtocolMessage::Start, />
             </div>
         }
     }
@@ -106,6 +223,7 @@ impl Renderable<Protocol> for Step {
                 <li>{"Add a buffer!"}</li>
             }
         } else {
+            let kind = self.3;
             let (id, time) = if let Some(step) = &self.1 {
                 if let CStep::Perfuse(id, time) = step {
                     (Some(*id), *time)
@@ -152,6 +270,29 @@ impl Renderable<Protocol> for Step {
                     _ => ProtocolMessage::Ignore,
                 }
             };
+            let kinds = move |event: yew::html::ChangeData| match event {
+                yew::html::ChangeData::Select(sel) => {
+                    match sel.value().as_ref().map(String::as_str) {
+                        Some("perfuse") => ProtocolMessage::Perfuse(index),
+                        Some("bath") => ProtocolMessage::Bath(index),
+                        _ => ProtocolMessage::Ignore,
+                    }
+                }
+                _ => ProtocolMessage::Ignore,
+            };
+            let option = |value: &str, label: &str, this: StepKind| {
+                if this == kind {
+                    html! { <option value=value, selected=true,>{label}</option> }
+                } else {
+                    html! { <option value=value,>{label}</option> }
+                }
+            };
+            let verb = html! {
+                <select class="verb", onchange=|e| kinds(e), >
+                { option("perfuse", "Perfuse", StepKind::Perfuse) }
+                { option("bath", "Bathe", StepKind::Bath) }
+                </select>
+            };
             let input = move |event: yew::html::ChangeData| match event {
                 yew::html::ChangeData::Value(val) => ProtocolMessage::Input(index, 0, val),
                 _ => ProtocolMessage::Ignore,
@@ -174,67 +315,193 @@ impl Renderable<Protocol> for Step {
                     </select>
                 }
             };
-            html! {
-                <li>
-                    <span class=("verb", "perfuse"),>{"Perfuse"}</span>
-                    {" with "}
-                    { sel }
-                    {" for "}
-                    <input type="number", class="time", min=1, value=time, onchange=|e| input(e), />
-                    {" "}
-                    <span class="time",>{"minutes"}</span>
-                    {"."}
-                </li>
+            let controls = html! {
+                <span class="controls",>
+                    <input type="button", class="up", value="↑", onclick=|_| ProtocolMessage::MoveUp(index), />
+                    <input type="button", class="down", value="↓", onclick=|_| ProtocolMessage::MoveDown(index), />
+                    <input type="button", class="delete", value="✕", onclick=|_| ProtocolMessage::Delete(index), />
+                </span>
+            };
+            match kind {
+                StepKind::Perfuse => html! {
+                    <li>
+                        { controls }
+                        { verb }
+                        {" with "}
+                        { sel }
+                        {" for "}
+                        <input type="number", class="time", min=1, value=time, onchange=|e| input(e), />
+                        {" "}
+                        <span class="time",>{"minutes"}</span>
+                        {"."}
+                    </li>
+                },
+                StepKind::Bath => html! {
+                    <li>
+                        { controls }
+                        { verb }
+                        {" in "}
+                        { sel }
+                        {" until stopped."}
+                    </li>
+                },
             }
         }
     }
 }
 
+/// What's become of the most recently submitted job.
+enum Submission {
+    /// The server hasn't responded yet.
+    Pending,
+    /// The job is running under the given ID.
+    Started(String),
+    /// Another job was already running.
+    Busy,
+    /// The job couldn't be started for the given reason.
+    Failed(String),
+}
+
 struct Root {
     buffers: Rc<RefCell<[Buffer; BUFFERS]>>,
     steps: Rc<RefCell<Vec<Step>>>,
+    link: ComponentLink<Self>,
+    fetch: FetchService,
+    task: Option<FetchTask>,
+    submission: Option<Submission>,
 }
 
-impl Default for Root {
-    fn default() -> Self {
-        let mut buffers: [Buffer; BUFFERS] = Default::default();
-        for (i, buf) in buffers.iter_mut().enumerate() {
-            buf.index = i;
+impl Root {
+    /// Sends the protocol to the server to be started.
+    fn submit(&mut self) -> ShouldRender {
+        let protocol = {
+            let steps = loop {
+                let steps = self.steps.try_borrow();
+                if let Ok(steps) = steps {
+                    break steps;
+                }
+            };
+            protocol(&steps)
+        };
+        if protocol.validate().is_err() {
+            return false;
+        }
+        let url = format!("/chambers/{}/", CHAMBER);
+        let request = Request::post(url.as_str())
+            .header("Content-Type", "application/json")
+            .body(Json(&protocol))
+            .expect("Failed to build request");
+        let callback = self
+            .link
+            .send_back(|response: Response<yew::format::Text>| {
+                let (meta, body) = response.into_parts();
+                match meta.status.as_u16() {
+                    201 => {
+                        let location = meta
+                            .headers
+                            .get("Location")
+                            .and_then(|location| location.to_str().ok())
+                            .unwrap_or_default();
+                        JobMessage::Started(location.to_string())
+                    }
+                    409 => JobMessage::Busy,
+                    _ => JobMessage::Failed(body.unwrap_or_else(|_| meta.status.to_string())),
+                }
+                .into()
+            });
+        self.task = Some(self.fetch.fetch(request, callback));
+        self.submission = Some(Submission::Pending);
+        true
+    }
+
+    /// Changes the buffer at the given index, updating the copies held by each step.
+    fn update_buffer<F: FnOnce(&mut Buffer)>(&mut self, index: usize, f: F) {
+        let mut buffers = loop {
+            let buffers = self.buffers.try_borrow_mut();
+            if let Ok(buffers) = buffers {
+                break buffers;
+            }
+        };
+        f(&mut buffers[index]);
+        let buffers = buffers.clone().to_vec();
+        let mut steps = loop {
+            let steps = self.steps.try_borrow_mut();
+            if let Ok(steps) = steps {
+                break steps;
+            }
+        };
+        for step in steps.iter_mut() {
+            step.2 = buffers.clone();
         }
-        let buffers = Rc::new(RefCell::new(buffers));
-        let steps = vec![Step(0, None, vec![])];
-        let steps = Rc::new(RefCell::new(steps));
-        Self { buffers, steps }
+    }
+    /// Swaps the row at the given index with another, if there is one.
+    ///
+    /// Baths aren't moved, since they have to stay at the end.
+    fn swap_steps(&mut self, row: usize, other: Option<usize>) -> ShouldRender {
+        let mut steps = loop {
+            let steps = self.steps.try_borrow_mut();
+            if let Ok(steps) = steps {
+                break steps;
+            }
+        };
+        let other = match other {
+            Some(other) if row < steps.len() && other < steps.len() => other,
+            _ => return false,
+        };
+        if steps[row].3 == StepKind::Bath || steps[other].3 == StepKind::Bath {
+            return false;
+        }
+        steps.swap(row, other);
+        renumber(&mut steps);
+        true
     }
 }
 
 impl Component for Root {
     type Message = Message;
     type Properties = ();
-    fn create(_: Self::Properties, _: ComponentLink<Self>) -> Self {
-        Self::default()
+    fn create(_: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut buffers: [Buffer; BUFFERS] = Default::default();
+        for (i, buf) in buffers.iter_mut().enumerate() {
+            buf.index = i;
+        }
+        let buffers = Rc::new(RefCell::new(buffers));
+        let steps = vec![Step::default()];
+        let steps = Rc::new(RefCell::new(steps));
+        Self {
+            buffers,
+            steps,
+            link,
+            fetch: FetchService::new(),
+            task: None,
+            submission: None,
+        }
     }
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
+            Message::Job(msg) => {
+                self.task = None;
+                self.submission = Some(match msg {
+                    JobMessage::Started(uuid) => Submission::Started(uuid),
+                    JobMessage::Busy => Submission::Busy,
+                    JobMessage::Failed(reason) => Submission::Failed(reason),
+                });
+                true
+            }
             Message::Buffer(msg) => match msg {
                 BufferMessage::Input(index, label) => {
-                    let mut buffers = loop {
-                        let buffers = self.buffers.try_borrow_mut();
-                        if let Ok(buffers) = buffers {
-                            break buffers;
-                        }
-                    };
-                    buffers[index].label = label;
-                    let buffers = buffers.clone().to_vec();
-                    let mut steps = loop {
-                        let steps = self.steps.try_borrow_mut();
-                        if let Ok(steps) = steps {
-                            break steps;
-                        }
-                    };
-                    for step in steps.iter_mut() {
-                        step.2 = buffers.clone();
-                    }
+                    self.update_buffer(index, |buffer| buffer.label = label);
+                    true
+                }
+                BufferMessage::Volume(index, volume) => {
+                    // Anything that isn't a sensible number of liters clears the volume.
+                    let volume = volume
+                        .trim()
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|liters| liters.is_finite() && *liters >= 0.0)
+                        .map(Volume::new::<liter>);
+                    self.update_buffer(index, |buffer| buffer.volume = volume);
                     true
                 }
                 BufferMessage::Ignore => false,
@@ -259,6 +526,35 @@ impl Component for Root {
                         unimplemented!()
                     }
                 }
+                ProtocolMessage::Perfuse(row) => {
+                    let mut steps = loop {
+                        let steps = self.steps.try_borrow_mut();
+                        if let Ok(steps) = steps {
+                            break steps;
+                        }
+                    };
+                    // The buffer carries over, but the duration has to be entered again.
+                    steps[row].3 = StepKind::Perfuse;
+                    if let Some(CStep::Perfuse(id, _)) = steps[row].1 {
+                        steps[row].1 = Some(CStep::Perfuse(id, None));
+                    }
+                    true
+                }
+                ProtocolMessage::Bath(row) => {
+                    let mut steps = loop {
+                        let steps = self.steps.try_borrow_mut();
+                        if let Ok(steps) = steps {
+                            break steps;
+                        }
+                    };
+                    // A bath never ends on its own, so nothing can follow it.
+                    steps.truncate(row + 1);
+                    steps[row].3 = StepKind::Bath;
+                    if let Some(CStep::Perfuse(id, _)) = steps[row].1 {
+                        steps[row].1 = Some(CStep::Perfuse(id, None));
+                    }
+                    true
+                }
                 ProtocolMessage::Input(row, _pos, val) => {
                     let mut steps = loop {
                         let steps = self.steps.try_borrow_mut();
@@ -278,14 +574,27 @@ impl Component for Root {
                     } else {
                         unimplemented!()
                     }
-                    let mut next = Step::default();
-                    next.2 = steps.last().map(|s| s.2.clone()).unwrap_or_default();
-                    steps.push(next);
-                    for (i, s) in steps.iter_mut().enumerate() {
-                        s.0 = i;
+                    renumber(&mut steps);
+                    true
+                }
+                ProtocolMessage::Delete(row) => {
+                    let mut steps = loop {
+                        let steps = self.steps.try_borrow_mut();
+                        if let Ok(steps) = steps {
+                            break steps;
+                        }
+                    };
+                    // Always leave a row to fill in.
+                    if steps.len() <= 1 || row >= steps.len() {
+                        return false;
                     }
+                    steps.remove(row);
+                    renumber(&mut steps);
                     true
                 }
+                ProtocolMessage::MoveUp(row) => self.swap_steps(row, row.checked_sub(1)),
+                ProtocolMessage::MoveDown(row) => self.swap_steps(row, Some(row + 1)),
+                ProtocolMessage::Start => self.submit(),
                 ProtocolMessage::Ignore => false,
             },
         }
@@ -298,10 +607,18 @@ impl Component for Root {
 
 impl Renderable<Self> for Root {
     fn view(&self) -> Html<Self> {
+        let job = match &self.submission {
+            Some(Submission::Pending) => "Starting…".to_string(),
+            Some(Submission::Started(uuid)) => format!("Running job {}", uuid),
+            Some(Submission::Busy) => "The machine is busy with another job.".to_string(),
+            Some(Submission::Failed(reason)) => format!("The job couldn't be started: {}", reason),
+            None => "".to_string(),
+        };
         html! {
             <>
             <Buffers: onchange=|e: BufferMessage| e.into(), buffers=self.buffers.clone(), />
             <Protocol: onchange=|e: ProtocolMessage| e.into(), steps=self.steps.clone(), buffers=self.buffers.clone(), />
+            <p id={"job"},>{job}</p>
             </>
         }
     }
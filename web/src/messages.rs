@@ -1,10 +1,12 @@
 pub enum Message {
     Buffer(BufferMessage),
     Protocol(ProtocolMessage),
+    Job(JobMessage),
 }
 
 pub enum BufferMessage {
     Input(usize, String),
+    Volume(usize, String),
     Ignore,
 }
 
@@ -17,6 +19,12 @@ impl From<BufferMessage> for Message {
 pub enum ProtocolMessage {
     Selected(usize, usize, String),
     Input(usize, usize, String),
+    Perfuse(usize),
+    Bath(usize),
+    Delete(usize),
+    MoveUp(usize),
+    MoveDown(usize),
+    Start,
     Ignore,
 }
 
@@ -25,3 +33,15 @@ impl From<ProtocolMessage> for Message {
         Message::Protocol(msg)
     }
 }
+
+pub enum JobMessage {
+    Started(String),
+    Busy,
+    Failed(String),
+}
+
+impl From<JobMessage> for Message {
+    fn from(msg: JobMessage) -> Self {
+        Message::Job(msg)
+    }
+}